@@ -1,32 +1,300 @@
 use std::str::FromStr;
 use anyhow::Result;
 use std::io;
+use std::path::PathBuf;
+use serde_json::{Map, Value};
+use structopt::StructOpt;
+
+use crate::parser::{self, EPath};
+
+/// Subcommands reading logs from other tools
+#[derive(Debug, StructOpt)]
+pub (crate) enum Command {
+    /// Follow the logs of kubernetes pods with kubectl, labelling each line with its pod and container
+    K8s {
+        /// A pod such as api-7d9f, a resource such as deployment/api, or a label selector such as app=api
+        target: String,
+
+        /// Namespace of the pods, instead of the namespace of the current context
+        #[structopt(short, long)]
+        namespace: Option<String>,
+    },
+
+    /// Write the lines of each file whose request id, or other field given to --by, is not in the other file, such as to compare a canary with a baseline. Without --by, lines are compared by their message with numbers and ids left out
+    Diff {
+        #[structopt(parse(from_os_str))]
+        a: PathBuf,
+
+        #[structopt(parse(from_os_str))]
+        b: PathBuf,
+
+        /// Field to compare the lines by, such as request_id
+        #[structopt(long)]
+        by: Option<String>,
+    },
+}
 
 #[derive(Debug)]
 pub (crate) struct MultOpt<T : Sized>(pub(crate) Vec<T>);
 
-impl Default for MultOpt<String> {
+impl Default for MultOpt<KeyPattern> {
     fn default() -> Self {
         Self(Vec::new())
     }
 }
 
+/// A key given to options such as --omit, with `*` and `?` wildcards, or the
+/// path of a nested value such as `user.id`. Keys with dots can be quoted,
+/// as in filters. Paths are parsed once, along with the options, rather than
+/// for every line.
+#[derive(Debug, Clone, PartialEq)]
+pub (crate) struct KeyPattern {
+    /// The pattern as given
+    text: String,
+    /// The pattern matched against keys, unquoted
+    key: String,
+    /// The path of a pattern addressing a nested value
+    path: Option<EPath>,
+}
+
+impl KeyPattern {
+    pub (crate) fn new(text: &str) -> KeyPattern {
+        let parsed = Some(text).filter(|text| ! is_glob(text))
+            .and_then(|text| parser::parse_path(text).ok());
+
+        match parsed {
+            Some(path) if path.segments().len() > 1 =>
+                KeyPattern { text: text.to_owned(), key: text.to_owned(), path: Some(path) },
+            Some(path) =>
+                KeyPattern { text: text.to_owned(), key: path.segments()[0].clone(), path: None },
+            None =>
+                KeyPattern { text: text.to_owned(), key: text.to_owned(), path: None },
+        }
+    }
+
+    pub (crate) fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl MultOpt<KeyPattern> {
+    pub (crate) fn from_keys<S: AsRef<str>>(keys: impl IntoIterator<Item = S>) -> Self {
+        MultOpt(keys.into_iter().map(|key| KeyPattern::new(key.as_ref())).collect())
+    }
+
+    /// Whether any of the patterns matches `key`, see [`glob_match`]
+    pub (crate) fn matches(&self, key: &str) -> bool {
+        self.0.iter().any(|p| glob_match(&p.key, key))
+    }
+
+    /// Whether `key` should be printed with --extract, either because a
+    /// pattern matches it or because a nested path such as `user.id` starts
+    /// with it
+    pub (crate) fn selects(&self, key: &str) -> bool {
+        self.matches(key) || self.0.iter().any(|p| p.path.as_ref().is_some_and(|path| path.segments()[0] == key))
+    }
+
+    /// Patterns that are nested paths into `json` rather than keys of it
+    pub (crate) fn nested_paths(&self, json: &Map<String, Value>) -> Vec<EPath> {
+        self.0.iter()
+            .filter(|p| ! json.contains_key(&p.key))
+            .filter_map(|p| p.path.clone())
+            .collect()
+    }
+}
+
+/// Splits a list of keys on the commas that are not in quotes, so quoted
+/// keys can have commas too
+pub (crate) fn split_keys(src: &str) -> Vec<&str> {
+    let mut keys = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+
+    for (index, c) in src.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = ! quoted,
+            ',' if ! quoted => {
+                keys.push(&src[start..index]);
+                start = index + 1;
+            },
+            _ => (),
+        }
+    }
+
+    keys.push(&src[start..]);
+    keys
+}
+
+/// The path of `name` in `$XDG_CONFIG_HOME/jaxe` or `~/.config/jaxe`, if
+/// that file exists
+pub (crate) fn config_file(name: &str) -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(dir.join("jaxe").join(name)).filter(|path| path.exists())
+}
+
+pub (crate) fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Matches `key` against a pattern where `*` matches any characters and
+/// `?` matches a single character. A pattern ending in `.*` also matches
+/// the key before the dot, so `labels.*` matches a `labels` object too.
+pub (crate) fn glob_match(pattern: &str, key: &str) -> bool {
+    if let Some(prefix) = pattern.strip_suffix(".*") {
+        if glob_match(prefix, key) {
+            return true
+        }
+    }
+
+    wildcard_match(pattern, key)
+}
+
+/// Matches `text` against a pattern where `*` matches any characters and
+/// `?` matches a single character
+pub (crate) fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let key: Vec<char> = text.chars().collect();
+
+    // Classic wildcard matching, backtracking to the last `*` seen
+    let (mut p, mut k) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while k < key.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == key[k]) {
+            p += 1;
+            k += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, k));
+            p += 1;
+        } else if let Some((sp, sk)) = star {
+            p = sp + 1;
+            k = sk + 1;
+            star = Some((sp, sk + 1));
+        } else {
+            return false
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
 
-impl std::fmt::Display for MultOpt<String> {
+impl std::fmt::Display for MultOpt<KeyPattern> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self.0)
+        write!(f, "{:?}", self.0.iter().map(KeyPattern::text).collect::<Vec<_>>())
     }
 }
 
-impl FromStr for MultOpt<String> {
+impl FromStr for MultOpt<KeyPattern> {
     type Err = io::Error;
 
     fn from_str(src: &str) -> Result<Self, Self::Err> {
         if src == "[]" {
             Ok(MultOpt::default())
         } else {
-            let v: Vec<String> = src.split(",").map(|v| v.to_string()).collect();
-            Ok(MultOpt(v))
+            Ok(MultOpt::from_keys(split_keys(src)))
+        }
+    }
+}
+
+/// A `key=value` option argument, such as `http.request.method=method`
+#[derive(Debug, Clone, PartialEq)]
+pub (crate) struct KeyValue(pub (crate) String, pub (crate) String);
+
+impl FromStr for KeyValue {
+    type Err = io::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src.split_once('=') {
+            Some((k, v)) if ! k.is_empty() =>
+                Ok(KeyValue(k.trim().to_owned(), v.trim().to_owned())),
+            _ =>
+                Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Expected key=value, got: {}", src)))
+        }
+    }
+}
+
+/// When to use colors, given to --color
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub (crate) enum ColorWhen {
+    Always,
+    Never,
+    /// Only when writing to a terminal and `NO_COLOR` is not set
+    Auto,
+}
+
+impl FromStr for ColorWhen {
+    type Err = io::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "always" => Ok(ColorWhen::Always),
+            "never" => Ok(ColorWhen::Never),
+            "auto" => Ok(ColorWhen::Auto),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Expected always, never or auto, got: {}", src))),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use super::*;
+
+    #[test]
+    fn glob_match_test() {
+        assert!(glob_match("msg", "msg"));
+        assert!(! glob_match("msg", "msgs"));
+        assert!(glob_match("http.*", "http.method"));
+        assert!(glob_match("http.*", "http"));
+        assert!(! glob_match("http.*", "https"));
+        assert!(glob_match("*_id", "request_id"));
+        assert!(glob_match("k*s.*.name", "kubernetes.pod.name"));
+        assert!(glob_match("lev?l", "level"));
+        assert!(! glob_match("lev?l", "levl"));
+    }
+
+    #[test]
+    fn key_pattern_test() {
+        let patterns: MultOpt<KeyPattern> = "user.id,http.*,msg".parse().unwrap();
+
+        assert!(patterns.matches("msg"));
+        assert!(patterns.matches("http.method"));
+        assert!(patterns.matches("user.id"));
+        assert!(! patterns.matches("user"));
+        assert!(patterns.selects("user"));
+
+        let json = json!({"user": {"id": 1}, "http": {"method": "GET"}});
+        assert_eq!(patterns.nested_paths(json.as_object().unwrap()), vec![EPath::new(vec!["user".into(), "id".into()])]);
+        assert_eq!(patterns.to_string(), r#"["user.id", "http.*", "msg"]"#);
+    }
+
+    #[test]
+    fn quoted_key_pattern_test() {
+        let patterns: MultOpt<KeyPattern> = r#""a/b","x,y",c"#.parse().unwrap();
+
+        assert!(patterns.matches("a/b"));
+        assert!(patterns.matches("x,y"));
+        assert!(patterns.matches("c"));
+        assert!(! patterns.matches("x"));
+        assert_eq!(patterns.to_string(), r#"["\"a/b\"", "\"x,y\"", "c"]"#);
+
+        let patterns: MultOpt<KeyPattern> = r#""k8s.pod".name,"http.method""#.parse().unwrap();
+        let json = json!({"k8s.pod": {"name": "api"}, "http.method": "GET"});
+        assert!(patterns.matches("http.method"));
+        assert!(patterns.selects("k8s.pod"));
+        assert_eq!(patterns.nested_paths(json.as_object().unwrap()), vec![EPath::new(vec!["k8s.pod".into(), "name".into()])]);
+
+        assert_eq!(split_keys(r#"a,"b,\",c",d"#), vec!["a", r#""b,\",c""#, "d"]);
+    }
+
+    #[test]
+    fn key_value_test() {
+        assert_eq!("a.b=c".parse::<KeyValue>().unwrap(), KeyValue("a.b".into(), "c".into()));
+        assert_eq!("a=b=c".parse::<KeyValue>().unwrap(), KeyValue("a".into(), "b=c".into()));
+        assert!("a".parse::<KeyValue>().is_err());
+        assert!("=a".parse::<KeyValue>().is_err());
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, FixedOffset, TimeDelta};
+use serde_json::Value;
+use anyhow::{anyhow, Result};
+
+use crate::Opt;
+use crate::parser::{self, EPath, EvalContext, Exp};
+use crate::timestamp;
+
+/// Holds back lines sharing a value of a field, such as a trace id, to write
+/// them together once the first of them is older than `window`, see
+/// --correlate
+pub (crate) struct Correlator {
+    field: String,
+    path: EPath,
+    window: Duration,
+    /// Groups waiting to be written, in the order of their first line
+    groups: Vec<Group>,
+}
+
+/// Lines to write together
+#[derive(Debug)]
+pub (crate) struct Group {
+    /// Such as `trace_id=abc`, `None` for a line without the field
+    pub (crate) label: Option<String>,
+    pub (crate) lines: Vec<Value>,
+    /// Time of the first line, if it has one
+    time: Option<DateTime<FixedOffset>>,
+    /// When the first line was read
+    read: Instant,
+}
+
+impl Correlator {
+    pub (crate) fn new(field: &str, window: Duration) -> Result<Correlator> {
+        Ok(Correlator { field: field.to_owned(), path: parser::parse_path(field)?, window, groups: vec![] })
+    }
+
+    /// Adds `line` to its group. Returns the groups to write now: those whose
+    /// window is over, and `line` on its own when it doesn't have the field.
+    pub (crate) fn add(&mut self, line: Value, opts: &Opt) -> Vec<Group> {
+        let time = timestamp::of_line(&line, opts);
+        let mut ready = self.release(time, Instant::now());
+
+        let id = match self.path.lookup(&line) {
+            None | Some(Value::Null) => {
+                ready.push(Group { label: None, lines: vec![line], time, read: Instant::now() });
+                return ready
+            },
+            Some(Value::String(id)) => id.clone(),
+            Some(other) => other.to_string(),
+        };
+
+        let label = format!("{}={}", self.field, id);
+        match self.groups.iter_mut().find(|group| group.label.as_ref() == Some(&label)) {
+            Some(group) => group.lines.push(line),
+            None => self.groups.push(Group { label: Some(label), lines: vec![line], time, read: Instant::now() }),
+        }
+
+        ready
+    }
+
+    /// The groups whose window is over when no line came, going by when
+    /// their first line was read, as of `now`
+    pub (crate) fn expire(&mut self, now: Instant) -> Vec<Group> {
+        self.release(None, now)
+    }
+
+    /// Takes the groups whose window is over as of a line of time `time`,
+    /// read `now`
+    fn release(&mut self, time: Option<DateTime<FixedOffset>>, now: Instant) -> Vec<Group> {
+        let window = self.window;
+
+        // By the time of the lines when there is one, as input is often read
+        // much faster than it was written
+        let (done, waiting): (Vec<Group>, Vec<Group>) = self.groups.drain(..).partition(|group| match (time, group.time) {
+            (Some(time), Some(first)) => time.signed_duration_since(first).to_std().is_ok_and(|elapsed| elapsed > window),
+            _ => now.saturating_duration_since(group.read) > window,
+        });
+        self.groups = waiting;
+
+        done
+    }
+
+    /// All the groups still waiting, at the end of the input
+    pub (crate) fn flush(&mut self) -> Vec<Group> {
+        std::mem::take(&mut self.groups)
+    }
+}
+
+/// Key of the time between a start and an end line, added to end lines
+const ELAPSED_KEY: &str = "elapsed";
+
+/// Pairs lines matching a start filter with the next line matching an end
+/// filter that has the same key, and adds the time between them to the end
+/// line, see --duration-between
+pub (crate) struct DurationBetween {
+    start: Exp,
+    end: Exp,
+    key: Option<EPath>,
+    /// Time of the start lines and when they were read, by their key
+    started: HashMap<String, (Option<DateTime<FixedOffset>>, Instant)>,
+}
+
+impl DurationBetween {
+    pub (crate) fn new(filters: &[String], key: Option<&str>) -> Result<DurationBetween> {
+        let [start, end] = filters else {
+            return Err(anyhow!("Expected a start and an end filter"))
+        };
+
+        Ok(DurationBetween {
+            start: parser::parse(start)?,
+            end: parser::parse(end)?,
+            key: key.map(parser::parse_path).transpose()?,
+            started: HashMap::new(),
+        })
+    }
+
+    /// Remembers `line` if it is a start, or adds the time since its start if
+    /// it is an end. Times are those of the lines, or when they were read if
+    /// either has none.
+    pub (crate) fn annotate(&mut self, line: &mut Value, opts: &Opt) -> Result<()> {
+        let ctx = EvalContext { epsilon: opts.epsilon };
+
+        let key = match &self.key {
+            Some(path) => match path.lookup(line) {
+                None | Some(Value::Null) => return Ok(()),
+                Some(Value::String(key)) => key.clone(),
+                Some(other) => other.to_string(),
+            },
+            None => String::new(),
+        };
+
+        if parser::filter_with(&self.end, line, &ctx)? {
+            if let Some((start_time, read)) = self.started.remove(&key) {
+                let elapsed = match (timestamp::of_line(line, opts), start_time) {
+                    (Some(time), Some(start_time)) => time.signed_duration_since(start_time),
+                    _ => TimeDelta::from_std(read.elapsed()).unwrap_or_default(),
+                };
+
+                if let Value::Object(obj) = line {
+                    obj.insert(ELAPSED_KEY.to_owned(), Value::String(timestamp::span(elapsed)));
+                }
+            }
+        } else if parser::filter_with(&self.start, line, &ctx)? {
+            self.started.insert(key, (timestamp::of_line(line, opts), Instant::now()));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use structopt::StructOpt;
+    use super::*;
+
+    fn labels(groups: &[Group]) -> Vec<(Option<&str>, usize)> {
+        groups.iter().map(|group| (group.label.as_deref(), group.lines.len())).collect()
+    }
+
+    #[test]
+    fn correlate_test() {
+        let opts = Opt::from_iter(["jaxe", "-t", "time"]);
+        let mut correlator = Correlator::new("trace", Duration::from_secs(10)).unwrap();
+
+        let mut add = |trace: Value, time: &str| correlator.add(json!({"trace": trace, "time": time}), &opts);
+
+        assert!(add(json!("a"), "2024-03-01T10:00:00Z").is_empty());
+        assert!(add(json!(1), "2024-03-01T10:00:02Z").is_empty());
+        assert!(add(json!("a"), "2024-03-01T10:00:05Z").is_empty());
+        assert_eq!(labels(&add(json!(null), "2024-03-01T10:00:06Z")), vec![(None, 1)]);
+        // The group of a is over, the one of 1 has 1s left
+        assert_eq!(labels(&add(json!(1), "2024-03-01T10:00:11Z")), vec![(Some("trace=a"), 2)]);
+
+        assert_eq!(labels(&correlator.flush()), vec![(Some("trace=1"), 2)]);
+        assert!(correlator.flush().is_empty());
+    }
+
+    #[test]
+    fn expire_test() {
+        let opts = Opt::from_iter(["jaxe", "-t", "time"]);
+        let mut correlator = Correlator::new("trace", Duration::from_secs(10)).unwrap();
+        let now = Instant::now();
+
+        assert!(correlator.add(json!({"trace": "a", "time": "2024-03-01T10:00:00Z"}), &opts).is_empty());
+        assert!(correlator.add(json!({"trace": "b"}), &opts).is_empty());
+
+        assert!(correlator.expire(now + Duration::from_secs(5)).is_empty());
+        // Without a next line, the groups are written once 10s have passed
+        assert_eq!(labels(&correlator.expire(now + Duration::from_secs(11))), vec![(Some("trace=a"), 1), (Some("trace=b"), 1)]);
+        assert!(correlator.flush().is_empty());
+    }
+
+    #[test]
+    fn duration_between_test() {
+        let opts = Opt::from_iter(["jaxe", "-t", "time"]);
+        let mut durations = DurationBetween::new(&["event == start".to_owned(), "event == end".to_owned()], Some("req")).unwrap();
+
+        let mut annotate = |event: &str, req: u32, time: &str| {
+            let mut line = json!({"event": event, "req": req, "time": time});
+            durations.annotate(&mut line, &opts).unwrap();
+            line.get("elapsed").and_then(Value::as_str).map(str::to_owned)
+        };
+
+        assert_eq!(annotate("start", 1, "2024-03-01T10:00:00Z"), None);
+        assert_eq!(annotate("start", 2, "2024-03-01T10:00:01Z"), None);
+        assert_eq!(annotate("end", 2, "2024-03-01T10:00:01.250Z"), Some("250ms".to_owned()));
+        assert_eq!(annotate("end", 1, "2024-03-01T10:02:05Z"), Some("2m5s".to_owned()));
+        // Ends without a start are left alone
+        assert_eq!(annotate("end", 1, "2024-03-01T10:03:00Z"), None);
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::Opt;
+use crate::input;
+use crate::parser::{self, EPath};
+use crate::report;
+
+/// Keys of the lines of two files, to write the lines of each file whose key
+/// is not in the other one, see `jaxe diff`
+pub (crate) struct Diff {
+    key: Key,
+    /// Keys of the lines of each file
+    keys: [HashSet<String>; 2],
+}
+
+enum Key {
+    /// The value of a field, such as a request id
+    Field(EPath),
+    /// The message with its numbers and ids replaced, so lines written by
+    /// the same code have the same key
+    Message(Regex),
+}
+
+impl Diff {
+    /// Reads the keys of the lines of `paths`, which are read again to be
+    /// written
+    pub (crate) fn new(paths: &[PathBuf; 2], by: Option<&str>, opts: &Opt) -> Result<Diff> {
+        let key = match by {
+            Some(field) => Key::Field(parser::parse_path(field)?),
+            None => Key::Message(Regex::new(r"(?i)\b[0-9a-f]{8}(-[0-9a-f]{4}){3}-[0-9a-f]{12}\b|\b(0x)?[0-9a-f]*[0-9][0-9a-f]*\b")?),
+        };
+
+        let mut diff = Diff { key, keys: Default::default() };
+        for (index, path) in paths.iter().enumerate() {
+            diff.keys[index] = diff.read_keys(path, opts)?;
+        }
+
+        Ok(diff)
+    }
+
+    fn read_keys(&self, path: &Path, opts: &Opt) -> io::Result<HashSet<String>> {
+        let mut keys = HashSet::new();
+
+        for text in input::open(path, 0)? {
+            let text = match text {
+                Ok(text) => text,
+                // Reported when the lines are written
+                Err(err) if err.get_ref().is_some_and(|err| err.is::<input::InvalidLine>()) => continue,
+                Err(err) => return Err(err),
+            };
+
+            if let Some(key) = serde_json::from_str(&text).ok().and_then(|line| self.key(&line, opts)) {
+                keys.insert(key);
+            }
+        }
+
+        Ok(keys)
+    }
+
+    fn key(&self, line: &Value, opts: &Opt) -> Option<String> {
+        match &self.key {
+            Key::Field(path) => match path.lookup(line)? {
+                Value::Null => None,
+                Value::String(key) => Some(key.clone()),
+                other => Some(other.to_string()),
+            },
+            Key::Message(numbers) => {
+                let message = report::message_keys(opts).into_iter().find_map(|key| line.get(key)?.as_str())?;
+                Some(numbers.replace_all(message, "#").into_owned())
+            },
+        }
+    }
+
+    /// Whether the line `text` of the file `index` has a key that is not in
+    /// the other file. Lines without a key are in both.
+    pub (crate) fn is_new(&self, index: usize, text: &str, opts: &Opt) -> bool {
+        let Some(key) = serde_json::from_str(text).ok().and_then(|line| self.key(&line, opts)) else {
+            return false
+        };
+
+        ! self.keys[1 - index].contains(&key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use structopt::StructOpt;
+    use super::*;
+
+    fn write(name: &str, lines: &[&str]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("jaxe-diff-{}-{}", std::process::id(), name));
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        path
+    }
+
+    #[test]
+    fn diff_by_field_test() {
+        let opts = Opt::from_iter(["jaxe"]);
+        let paths = [
+            write("field-a", &[r#"{"req": "r1"}"#, r#"{"req": 2}"#, r#"{"msg": "no req"}"#]),
+            write("field-b", &[r#"{"req": "r1"}"#, r#"{"req": "r3"}"#]),
+        ];
+        let diff = Diff::new(&paths, Some("req"), &opts).unwrap();
+        paths.iter().for_each(|path| std::fs::remove_file(path).unwrap());
+
+        assert!(! diff.is_new(0, r#"{"req": "r1"}"#, &opts));
+        assert!(diff.is_new(0, r#"{"req": 2}"#, &opts));
+        assert!(! diff.is_new(0, r#"{"msg": "no req"}"#, &opts));
+        assert!(diff.is_new(1, r#"{"req": "r3"}"#, &opts));
+        assert!(! diff.is_new(1, "not json", &opts));
+    }
+
+    #[test]
+    fn diff_by_message_test() {
+        let opts = Opt::from_iter(["jaxe"]);
+        let paths = [
+            write("message-a", &[r#"{"msg": "user 42 logged in"}"#, r#"{"msg": "cache miss for 3f2a9c1e-0b7d-4c6e-9a51-2d8e7f0c4b19"}"#]),
+            write("message-b", &[r#"{"msg": "user 7 logged in"}"#, r#"{"message": "timeout after 0x1f retries"}"#]),
+        ];
+        let diff = Diff::new(&paths, None, &opts).unwrap();
+        paths.iter().for_each(|path| std::fs::remove_file(path).unwrap());
+
+        assert!(! diff.is_new(0, r#"{"msg": "user 1000 logged in"}"#, &opts));
+        assert!(diff.is_new(0, r#"{"msg": "cache miss for 00000000-1111-2222-3333-444444444444"}"#, &opts));
+        assert!(diff.is_new(1, r#"{"message": "timeout after 0x20 retries"}"#, &opts));
+        assert!(! diff.is_new(1, r#"{"msg": "user 8 logged in"}"#, &opts));
+    }
+
+    #[test]
+    fn diff_invalid_line_test() {
+        let opts = Opt::from_iter(["jaxe"]);
+        let paths = [write("invalid-a", &[r#"{"req": "r1"}"#]), write("invalid-b", &[])];
+        std::fs::write(&paths[1], b"{\"req\": \"r2\"}\n\xff\xfe\n{\"req\": \"r1\"}\n").unwrap();
+        let diff = Diff::new(&paths, Some("req"), &opts).unwrap();
+        paths.iter().for_each(|path| std::fs::remove_file(path).unwrap());
+
+        assert!(! diff.is_new(0, r#"{"req": "r1"}"#, &opts));
+        assert!(diff.is_new(1, r#"{"req": "r2"}"#, &opts));
+    }
+}
@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+const UNITS: &[(&str, f64)] = &[
+    ("ns", 1e-9),
+    ("us", 1e-6),
+    ("µs", 1e-6),
+    ("ms", 1e-3),
+    ("s", 1.0),
+    ("m", 60.0),
+    ("h", 3600.0),
+    ("d", 86400.0),
+];
+
+/// Parses duration strings such as `154ms`, `2s`, `1.5h` or `1h30m`. A unit
+/// is required, bare numbers are not considered durations.
+pub (crate) fn parse(input: &str) -> Option<Duration> {
+    let mut rest = input.trim();
+    let mut total = 0.0;
+
+    if rest.is_empty() {
+        return None
+    }
+
+    while ! rest.is_empty() {
+        let num_len = rest.find(|c: char| ! c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        let number: f64 = rest[..num_len].parse().ok()?;
+        rest = &rest[num_len..];
+
+        let unit_len = rest.find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(rest.len());
+        let (_, factor) = UNITS.iter().find(|(u, _)| *u == &rest[..unit_len])?;
+        rest = &rest[unit_len..];
+
+        total += number * factor;
+    }
+
+    // Durations come from log lines too, which can hold any number
+    Duration::try_from_secs_f64(total).ok()
+}
+
+/// Parses a duration given as a command line argument, see `parse`
+pub (crate) fn parse_arg(input: &str) -> anyhow::Result<Duration> {
+    parse(input).ok_or_else(|| anyhow::anyhow!("Expected a duration such as 30s or 5m, got: {}", input))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_simple_test() {
+        assert_eq!(parse("154ms"), Some(Duration::from_millis(154)));
+        assert_eq!(parse("2s"), Some(Duration::from_secs(2)));
+        assert_eq!(parse("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse("10us"), Some(Duration::from_micros(10)));
+    }
+
+    #[test]
+    fn parse_compound_test() {
+        assert_eq!(parse("1h30m"), Some(Duration::from_secs(5400)));
+    }
+
+    #[test]
+    fn parse_invalid_test() {
+        assert_eq!(parse("100"), None);
+        assert_eq!(parse("ms"), None);
+        assert_eq!(parse("10 apples"), None);
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("99999999999999999999d"), None);
+    }
+}
@@ -0,0 +1,205 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+use crate::Opt;
+use crate::parser::{self, EvalContext, Exp};
+use crate::template::Template;
+
+/// A command given to --exec, such as `notify-send "error" "{msg}"`. It is
+/// split into words like a shell would, and each word is a template, so
+/// values of the line are given as arguments and never read by a shell.
+#[derive(Debug)]
+pub (crate) struct CommandTemplate {
+    program: Template,
+    args: Vec<Template>,
+}
+
+impl FromStr for CommandTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let mut words = split_words(src)?.into_iter().map(|word| word.parse::<Template>());
+        let program = words.next().ok_or_else(|| anyhow!("Expected a command"))??;
+
+        Ok(CommandTemplate { program, args: words.collect::<Result<_>>()? })
+    }
+}
+
+impl CommandTemplate {
+    /// The program and arguments of the command for `line`
+    fn render(&self, line: &Value) -> (String, Vec<String>) {
+        (self.program.render(line), self.args.iter().map(|arg| arg.render(line)).collect())
+    }
+}
+
+/// Words of a command line, with single and double quotes and backslash
+/// escapes as in a shell
+fn split_words(src: &str) -> Result<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = src.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow!("Unclosed quote in command: {}", src)),
+                    }
+                }
+            },
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => word.extend(chars.next()),
+                        Some(c) => word.push(c),
+                        None => return Err(anyhow!("Unclosed quote in command: {}", src)),
+                    }
+                }
+            },
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    words.extend(word);
+    Ok(words)
+}
+
+/// What to do for lines matching a trigger
+#[derive(Debug)]
+pub (crate) enum Action {
+    /// Run a command, see --exec
+    Run(CommandTemplate),
+    /// Ring the terminal bell, see --bell
+    Bell,
+    /// Send a desktop notification with the level and message of the line,
+    /// see --notify
+    Notify,
+}
+
+/// How often --bell and --notify can go off
+pub (crate) const ALERT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An action for lines matching `filter`, at most once per `interval`
+pub (crate) struct Trigger {
+    action: Action,
+    /// Lines the action is for, all lines without it
+    filter: Option<Exp>,
+    interval: Duration,
+    last_run: Option<Instant>,
+}
+
+impl Trigger {
+    pub (crate) fn new(action: Action, filter: Option<Exp>, interval: Duration) -> Trigger {
+        Trigger { action, filter, interval, last_run: None }
+    }
+
+    /// Does the action for `line` if it matches, unless the action was done
+    /// less than `interval` ago. Commands are not waited for.
+    pub (crate) fn fire(&mut self, line: &Value, opts: &Opt) -> Result<()> {
+        let ctx = EvalContext { epsilon: opts.epsilon };
+        if let Some(filter) = &self.filter {
+            if ! parser::filter_with(filter, line, &ctx)? {
+                return Ok(())
+            }
+        }
+
+        if self.last_run.is_some_and(|last| last.elapsed() < self.interval) {
+            log::debug!("Not running {:?}, it ran less than {:?} ago", self.action, self.interval);
+            return Ok(())
+        }
+        self.last_run = Some(Instant::now());
+
+        let (program, args) = match &self.action {
+            Action::Run(command) => command.render(line),
+            Action::Notify => notification(line, opts),
+            Action::Bell => {
+                let mut stderr = io::stderr();
+                write!(stderr, "\x07")?;
+                stderr.flush()?;
+                return Ok(())
+            },
+        };
+
+        let child = Command::new(&program).args(&args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn();
+
+        match child {
+            Ok(mut child) => { thread::spawn(move || child.wait()); },
+            Err(err) => log::error!("Could not run {}: {}", program, err),
+        }
+
+        Ok(())
+    }
+}
+
+/// The command sending a desktop notification for `line`, titled with its
+/// level and showing its message, or the whole line without one
+fn notification(line: &Value, opts: &Opt) -> (String, Vec<String>) {
+    let text = |keys: &[String]| keys.iter().find_map(|key| match line.get(key)? {
+        Value::String(text) => Some(text.clone()),
+        other => Some(other.to_string()),
+    });
+
+    let title = text(&opts.level).map_or("jaxe".to_owned(), |level| format!("jaxe: {}", level));
+    let body = text(&opts.message).unwrap_or_else(|| line.to_string());
+
+    if cfg!(target_os = "macos") {
+        let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        let script = format!("display notification {} with title {}", quote(&body), quote(&title));
+        ("osascript".to_owned(), vec!["-e".to_owned(), script])
+    } else {
+        ("notify-send".to_owned(), vec![title, body])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use structopt::StructOpt;
+    use super::*;
+
+    #[test]
+    fn split_words_test() {
+        assert_eq!(split_words(r#"notify-send "error in {app}" '{msg}' a\ b"#).unwrap(), vec!["notify-send", "error in {app}", "{msg}", "a b"]);
+        assert_eq!(split_words(r#"echo "" x"#).unwrap(), vec!["echo", "", "x"]);
+        assert!(split_words(r#"echo "unclosed"#).is_err());
+        assert!(split_words("echo 'unclosed").is_err());
+    }
+
+    #[test]
+    fn render_test() {
+        let command: CommandTemplate = r#"notify-send "{level}" "{msg}; rm -rf /""#.parse().unwrap();
+        let (program, args) = command.render(&json!({"level": "ERROR", "msg": "$(reboot)"}));
+
+        assert_eq!(program, "notify-send");
+        assert_eq!(args, vec!["ERROR", "$(reboot); rm -rf /"]);
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn notification_test() {
+        let opts = Opt::from_iter(["jaxe", "-l", "level", "-m", "msg"]);
+
+        let (program, args) = notification(&json!({"level": "ERROR", "msg": "disk full"}), &opts);
+        assert_eq!(program, "notify-send");
+        assert_eq!(args, vec!["jaxe: ERROR", "disk full"]);
+
+        let (_, args) = notification(&json!({"status": 500}), &opts);
+        assert_eq!(args, vec!["jaxe", r#"{"status":500}"#]);
+    }
+}
@@ -1,59 +1,96 @@
+use std::collections::{BTreeMap, HashMap};
 use serde_json::Value;
 use crate::Result;
 use crate::parser;
 
 pub (crate) trait Filter {
     fn apply(&mut self, line: &Value) -> Result<bool>;
+
+    /// Human readable description of this filter, used in debug logging
+    fn describe(&self) -> String;
+
+    /// Substrings of `line` that made this filter match, used to highlight them
+    fn matches(&self, _line: &Value) -> Vec<parser::Match> {
+        vec![]
+    }
 }
 
-pub (crate) struct Filters(Vec<Box<dyn Filter>>);
+pub (crate) struct Filters {
+    /// Lets through the lines between --after and --before, before `filters`
+    range: Option<RangeFilter>,
+    filters: Vec<Box<dyn Filter>>,
+    /// Flips the combined decision of `filters`, like `grep -v`, but not the
+    /// range
+    invert: bool,
+    /// Drops lines repeating a value, see `is_unique`
+    unique: Option<UniqueBy>,
+}
 
 impl Filters {
-    pub (crate) fn from_opts(opts: &crate::Opt) -> Filters {
+    /// Compiles --filter, --after and --before, failing on the first
+    /// expression that does not parse
+    pub (crate) fn from_opts(opts: &crate::Opt) -> Result<Filters> {
         let mut filters: Vec<Box<dyn Filter>> = vec![];
 
-        #[cfg(feature = "jq")]
-        if opts.jq {
-            Self::add_jq_filters(opts, &mut filters);
+        Self::add_filters(opts, &mut filters)?;
+
+        let range = if opts.after.is_some() || opts.before.is_some() {
+            let start = opts.after.as_ref().map(|f| Self::compile(opts, f)).transpose()?;
+            let end = opts.before.as_ref().map(|f| Self::compile(opts, f)).transpose()?;
+            Some(RangeFilter::new(start, end))
         } else {
-            Self::add_filters(opts, &mut filters);
-        }
+            None
+        };
 
-        #[cfg(not(feature = "jq"))]
-        Self::add_filters(opts, &mut filters);
+        let unique = opts.unique_by.clone().map(|path| UniqueBy::new(path, opts.unique_capacity));
 
-        Filters(filters)
+        Ok(Filters { range, filters, invert: opts.invert_match, unique })
     }
 
-    fn add_filters(opts: &crate::Opt, filters: &mut Vec<Box<dyn Filter>>) {
-        for f in &opts.filter {
-            let exp = parser::parse(f).unwrap();
+    /// Filters used with --highlight to style lines, they never drop lines
+    pub (crate) fn highlights(opts: &crate::Opt) -> Result<Filters> {
+        let filters = opts.highlight.iter().map(|f| Self::compile(opts, f)).collect::<Result<_>>()?;
+        Ok(Filters { range: None, filters, invert: false, unique: None })
+    }
 
-            filters.push(
-                Box::new(JaxeFilter { filter: exp })
-            )
+    /// Whether `line` is the first with its value of --unique-by. Meant to be
+    /// checked once a line passed every other filter, so only lines that
+    /// are written are remembered.
+    pub (crate) fn is_unique(&mut self, line: &Value) -> Result<bool> {
+        match &mut self.unique {
+            Some(unique) => unique.apply(line),
+            None => Ok(true),
         }
     }
 
-    #[cfg(feature = "jq")]
-    fn add_jq_filters(opts: &crate::Opt, filters: &mut Vec<Box<dyn Filter>>) {
+    fn add_filters(opts: &crate::Opt, filters: &mut Vec<Box<dyn Filter>>) -> Result<()> {
         for f in &opts.filter {
-            filters.push(
-                Box::new(JqFilter { inner: jq_rs::compile(&f).unwrap() })
-            )
+            filters.push(Self::compile(opts, f)?)
+        }
+
+        Ok(())
+    }
+
+    fn compile(opts: &crate::Opt, f: &str) -> Result<Box<dyn Filter>> {
+        #[cfg(feature = "jq")]
+        if opts.jq {
+            let inner = jq_rs::compile(f).map_err(|err| anyhow::anyhow!("Could not compile jq filter: {}", err))?;
+            return Ok(Box::new(JqFilter { inner, source: f.to_owned() }))
         }
+
+        let exp = parser::parse(f)?;
+        let ctx = parser::EvalContext { epsilon: opts.epsilon };
+        Ok(Box::new(JaxeFilter { filter: exp, ctx, source: f.to_owned() }))
     }
 }
 
-impl Filter for Filters {
-    fn apply(&mut self, line: &Value) -> Result<bool> {
-        for filter in self.0.iter_mut() {
+impl Filters {
+    fn matches_all(&mut self, line: &Value) -> Result<bool> {
+        for filter in self.filters.iter_mut() {
             let res = filter.apply(line)?;
 
             if ! res {
-                // TODO: Implement debug for filter
-                // log::debug!("Line ignored, it does not match filter {:?}", filter);
-                log::debug!("Line ignored, it does not match filter");
+                log::debug!("Line does not match filter {}", filter.describe());
                 return Ok(false)
             }
         }
@@ -62,10 +99,49 @@ impl Filter for Filters {
     }
 }
 
+impl Filter for Filters {
+    fn apply(&mut self, line: &Value) -> Result<bool> {
+        // Range filters are stateful and need to see every line, so they
+        // go first, before any other filter can short circuit
+        let in_range = match &mut self.range {
+            Some(range) => range.apply(line)?,
+            None => true,
+        };
+
+        let res = in_range && self.matches_all(line)? != self.invert;
+
+        if ! res {
+            log::debug!("Line ignored by filters {}", self.describe());
+        }
+
+        Ok(res)
+    }
+
+    fn matches(&self, line: &Value) -> Vec<parser::Match> {
+        if self.invert {
+            return vec![]
+        }
+
+        self.filters.iter().flat_map(|f| f.matches(line)).collect()
+    }
+
+    fn describe(&self) -> String {
+        let all: Vec<String> = self.filters.iter().map(|f| f.describe()).collect();
+        let range = self.range.iter().map(|range| range.describe());
+
+        if self.invert {
+            range.chain([format!("not({})", all.join(", "))]).collect::<Vec<_>>().join(", ")
+        } else {
+            range.chain(all).collect::<Vec<_>>().join(", ")
+        }
+    }
+}
+
 
 #[cfg(feature = "jq")]
 struct JqFilter {
     inner: jq_rs::JqProgram,
+    source: String,
 }
 
 #[cfg(feature = "jq")]
@@ -82,15 +158,242 @@ impl Filter for JqFilter {
             Ok(true)
         }
     }
+
+    fn describe(&self) -> String {
+        format!("jq `{}`", self.source)
+    }
 }
 
 struct JaxeFilter {
     filter: parser::Exp,
+    ctx: parser::EvalContext,
+    source: String,
 }
 
 
 impl Filter for JaxeFilter {
     fn apply(&mut self, line: &Value) -> Result<bool> {
-        parser::filter(&self.filter, line)
+        parser::filter_with(&self.filter, line, &self.ctx)
+    }
+
+    fn describe(&self) -> String {
+        format!("`{}`", self.source)
+    }
+
+    fn matches(&self, line: &Value) -> Vec<parser::Match> {
+        parser::matches(&self.filter, line, &self.ctx)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum RangeState {
+    Waiting,
+    Inside,
+    Done,
+}
+
+/// Emits lines starting at the first line matching `start` and stops
+/// emitting at the first line matching `end`, like `sed '/start/,/end/'`.
+/// The line matching `start` is emitted, the line matching `end` is not.
+struct RangeFilter {
+    start: Option<Box<dyn Filter>>,
+    end: Option<Box<dyn Filter>>,
+    state: RangeState,
+}
+
+impl RangeFilter {
+    fn new(start: Option<Box<dyn Filter>>, end: Option<Box<dyn Filter>>) -> RangeFilter {
+        let state = if start.is_some() { RangeState::Waiting } else { RangeState::Inside };
+        RangeFilter { start, end, state }
+    }
+}
+
+impl Filter for RangeFilter {
+    fn apply(&mut self, line: &Value) -> Result<bool> {
+        match self.state {
+            RangeState::Waiting => {
+                let started = match self.start.as_mut() {
+                    Some(f) => f.apply(line)?,
+                    None => true,
+                };
+
+                if started {
+                    log::debug!("Line matches --after, starting to emit lines");
+                    self.state = RangeState::Inside;
+                }
+
+                Ok(started)
+            },
+            RangeState::Inside => {
+                let ended = match self.end.as_mut() {
+                    Some(f) => f.apply(line)?,
+                    None => false,
+                };
+
+                if ended {
+                    log::debug!("Line matches --before, no more lines will be emitted");
+                    self.state = RangeState::Done;
+                }
+
+                Ok(! ended)
+            },
+            RangeState::Done =>
+                Ok(false)
+        }
+    }
+
+    fn describe(&self) -> String {
+        let start = self.start.as_ref().map(|f| f.describe()).unwrap_or_else(|| "start".to_owned());
+        let end = self.end.as_ref().map(|f| f.describe()).unwrap_or_else(|| "end".to_owned());
+        format!("range {} to {} ({:?})", start, end, self.state)
+    }
+}
+
+/// Lets through the first line of each value of a path, see --unique-by.
+/// Only the `capacity` most recently seen values are remembered, so a value
+/// is let through again once it hasn't been seen for a while. Lines without
+/// the path are always let through.
+struct UniqueBy {
+    path: parser::EPath,
+    capacity: usize,
+    /// When each value was last seen, by a counter of lines
+    seen: HashMap<String, u64>,
+    /// Values by when they were last seen, least recent first
+    recency: BTreeMap<u64, String>,
+    clock: u64,
+}
+
+impl UniqueBy {
+    fn new(path: parser::EPath, capacity: usize) -> UniqueBy {
+        UniqueBy { path, capacity: capacity.max(1), seen: HashMap::new(), recency: BTreeMap::new(), clock: 0 }
+    }
+}
+
+impl Filter for UniqueBy {
+    fn apply(&mut self, line: &Value) -> Result<bool> {
+        let value = match self.path.lookup(line) {
+            None | Some(Value::Null) => return Ok(true),
+            Some(Value::String(s)) => s.clone(),
+            Some(other) => other.to_string(),
+        };
+
+        self.clock += 1;
+
+        if let Some(last) = self.seen.insert(value.clone(), self.clock) {
+            self.recency.remove(&last);
+            self.recency.insert(self.clock, value);
+            log::debug!("Line ignored, its value was already seen by {}", self.describe());
+            return Ok(false)
+        }
+
+        self.recency.insert(self.clock, value);
+        if self.seen.len() > self.capacity {
+            if let Some((_, oldest)) = self.recency.pop_first() {
+                self.seen.remove(&oldest);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn describe(&self) -> String {
+        format!("unique by {}", self.path.segments().join("."))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use structopt::StructOpt;
+    use super::*;
+
+    fn jaxe_filter(f: &str) -> Box<dyn Filter> {
+        Box::new(JaxeFilter { filter: parser::parse(f).unwrap(), ctx: parser::EvalContext::default(), source: f.to_owned() })
+    }
+
+    fn apply_all(filter: &mut dyn Filter, lines: &[Value]) -> Vec<bool> {
+        lines.iter().map(|l| filter.apply(l).unwrap()).collect()
+    }
+
+    #[test]
+    fn range_filter_after_test() {
+        let mut f = RangeFilter::new(Some(jaxe_filter("msg == start")), None);
+        let lines = vec![json!({"msg": "a"}), json!({"msg": "start"}), json!({"msg": "b"}), json!({"msg": "start"})];
+
+        assert_eq!(apply_all(&mut f, &lines), vec![false, true, true, true]);
+    }
+
+    #[test]
+    fn invalid_range_test() {
+        assert!(Filters::from_opts(&crate::Opt::from_iter(["jaxe", "--after", "== 1"])).is_err());
+        assert!(Filters::from_opts(&crate::Opt::from_iter(["jaxe", "--before", "and(msg == a"])).is_err());
+        assert!(Filters::from_opts(&crate::Opt::from_iter(["jaxe", "--after", "msg == start", "--before", "msg == end"])).is_ok());
+    }
+
+    #[test]
+    fn invalid_highlight_test() {
+        assert!(Filters::highlights(&crate::Opt::from_iter(["jaxe", "--highlight", "== 1"])).is_err());
+        assert!(Filters::highlights(&crate::Opt::from_iter(["jaxe", "--highlight", "level == ERROR"])).is_ok());
+    }
+
+    #[test]
+    fn range_filter_before_test() {
+        let mut f = RangeFilter::new(None, Some(jaxe_filter("msg == end")));
+        let lines = vec![json!({"msg": "a"}), json!({"msg": "end"}), json!({"msg": "b"})];
+
+        assert_eq!(apply_all(&mut f, &lines), vec![true, false, false]);
+    }
+
+    #[test]
+    fn range_filter_after_and_before_test() {
+        let mut f = RangeFilter::new(Some(jaxe_filter("msg == start")), Some(jaxe_filter("msg == end")));
+        let lines = vec![
+            json!({"msg": "end"}), json!({"msg": "start"}), json!({"msg": "a"}),
+            json!({"msg": "end"}), json!({"msg": "start"}),
+        ];
+
+        assert_eq!(apply_all(&mut f, &lines), vec![false, true, true, false, false]);
+    }
+
+    #[test]
+    fn describe_test() {
+        let range = RangeFilter::new(Some(jaxe_filter("msg == start")), None);
+        assert_eq!(range.describe(), "range `msg == start` to end (Waiting)");
+
+        let filters = Filters { range: None, filters: vec![jaxe_filter("exists(a)"), jaxe_filter("b != 1")], invert: false, unique: None };
+        assert_eq!(filters.describe(), "`exists(a)`, `b != 1`");
+    }
+
+    #[test]
+    fn invert_filters_test() {
+        let mut filters = Filters { range: None, filters: vec![jaxe_filter("a == 1"), jaxe_filter("b == 2")], invert: true, unique: None };
+        let lines = vec![json!({"a": 1, "b": 2}), json!({"a": 1, "b": 3}), json!({"a": 0})];
+
+        assert_eq!(apply_all(&mut filters, &lines), vec![false, true, true]);
+
+        // The range is not inverted, only lines in it are
+        let range = RangeFilter::new(Some(jaxe_filter("msg == start")), Some(jaxe_filter("msg == end")));
+        let mut filters = Filters { range: Some(range), filters: vec![jaxe_filter("a == 1")], invert: true, unique: None };
+        let lines = vec![
+            json!({"msg": "before", "a": 0}), json!({"msg": "start", "a": 1}), json!({"msg": "in", "a": 0}),
+            json!({"msg": "end", "a": 0}), json!({"msg": "after", "a": 0}),
+        ];
+
+        assert_eq!(apply_all(&mut filters, &lines), vec![false, false, true, false, false]);
+        assert_eq!(filters.describe(), "range `msg == start` to `msg == end` (Done), not(`a == 1`)");
+    }
+
+    #[test]
+    fn unique_by_test() {
+        let mut f = UniqueBy::new(parser::parse_path("req.id").unwrap(), 2);
+        let line = |id: Value| json!({"req": {"id": id}});
+        let lines = vec![
+            line(json!(1)), line(json!(1)), line(json!("1")), json!({"msg": "no id"}), json!({"msg": "no id"}),
+            line(json!(2)), line(json!(1)), line(json!(3)),
+            // 2 was forgotten as the least recently seen, 1 is still known
+            line(json!(1)), line(json!(2)),
+        ];
+
+        assert_eq!(apply_all(&mut f, &lines), vec![true, false, false, true, true, true, false, true, false, true]);
     }
 }
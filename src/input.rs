@@ -0,0 +1,1095 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::net::{TcpListener, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, FixedOffset};
+use anyhow::anyhow;
+use serde_json::{json, Map, Value};
+
+use crate::Opt;
+use crate::cli;
+use crate::timestamp;
+
+/// A stream of input lines, each with its line ending
+pub (crate) type Source<'a> = Box<dyn Iterator<Item = io::Result<String>> + 'a>;
+
+/// Reads lines from `reader`, keeping their line endings
+pub (crate) struct Lines<R> {
+    reader: R,
+    /// Whether reading failed, after which there are no more lines
+    failed: bool,
+}
+
+impl<R: BufRead> Lines<R> {
+    pub (crate) fn new(reader: R) -> Lines<R> {
+        Lines { reader, failed: false }
+    }
+}
+
+/// A line that is not valid UTF-8, which is skipped
+#[derive(Debug)]
+pub (crate) struct InvalidLine {
+    /// Length of the line in bytes, with its line ending
+    pub (crate) len: usize,
+}
+
+impl std::fmt::Display for InvalidLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Skipped a line that is not valid UTF-8")
+    }
+}
+
+impl std::error::Error for InvalidLine {}
+
+impl<R: BufRead> Iterator for Lines<R> {
+    type Item = io::Result<String>;
+
+    /// The next line. Lines that are not valid UTF-8 are errors holding an
+    /// [`InvalidLine`], and reading goes on after them.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None
+        }
+
+        let mut line = vec![];
+
+        match self.reader.read_until(b'\n', &mut line) {
+            Ok(0) => None,
+            Ok(len) => Some(String::from_utf8(line).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, InvalidLine { len }))),
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            },
+        }
+    }
+}
+
+/// Lines tagged with the index of the source they were read from
+pub (crate) type Tagged<'a> = Box<dyn Iterator<Item = (usize, io::Result<String>)> + 'a>;
+
+/// Opens a file given on the command line at byte `offset`, `-` being stdin
+pub (crate) fn open(path: &Path, offset: u64) -> io::Result<Source<'static>> {
+    if path == Path::new("-") {
+        return Ok(Box::new(Lines::new(io::stdin().lock())))
+    }
+
+    let mut file = File::open(path).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+    file.seek(SeekFrom::Start(offset))?;
+    Ok(Box::new(Lines::new(BufReader::new(file))))
+}
+
+/// The files to read for the paths given on the command line, each with
+/// its tag when given as `path:tag`. Directories are read recursively and
+/// glob patterns such as `logs/**/*.json` are expanded, each in name order.
+/// With `by_mtime`, all files are sorted by modification time instead,
+/// oldest first.
+pub (crate) fn expand(paths: &[PathBuf], by_mtime: bool) -> io::Result<Vec<(PathBuf, Option<String>)>> {
+    let mut files = vec![];
+
+    for (path, tag) in paths.iter().map(|path| split_tag(path)) {
+        let pattern = path.to_string_lossy();
+        let tagged = |found: Vec<PathBuf>| found.into_iter().map(|p| (p, tag.clone()));
+
+        if path.is_dir() {
+            let mut found = vec![];
+            descendants(&path, &mut found);
+            files.extend(tagged(found.into_iter().filter(|p| p.is_file()).collect()));
+        } else if cli::is_glob(&pattern) && ! path.exists() {
+            let found = glob(&pattern);
+
+            if found.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, format!("{}: no files match", pattern)))
+            }
+
+            files.extend(tagged(found));
+        } else {
+            files.push((path.clone(), tag.clone()));
+        }
+    }
+
+    if by_mtime {
+        files.sort_by_cached_key(|(path, _)| fs::metadata(path).and_then(|m| m.modified()).ok());
+    }
+
+    Ok(files)
+}
+
+/// Splits `path:tag` into the path and the tag, unless a file is named so
+fn split_tag(path: &Path) -> (PathBuf, Option<String>) {
+    let Some((file, tag)) = path.to_str().filter(|_| ! path.exists()).and_then(|text| text.rsplit_once(':')) else {
+        return (path.to_owned(), None)
+    };
+
+    if file.is_empty() || tag.is_empty() || tag.contains(['/', '\\']) {
+        return (path.to_owned(), None)
+    }
+
+    (PathBuf::from(file), Some(tag.to_owned()))
+}
+
+/// Short labels telling `files` apart: their tag, else their file name, or
+/// their path when several of them have that name
+pub (crate) fn labels(files: &[(PathBuf, Option<String>)]) -> Vec<String> {
+    let name = |path: &Path| path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+
+    files.iter().map(|(path, tag)| match tag {
+        Some(tag) => tag.clone(),
+        None if files.iter().filter(|(other, _)| name(other) == name(path)).count() > 1 => path.display().to_string(),
+        None => name(path),
+    }).collect()
+}
+
+/// Files matching `pattern`, where `**` matches any number of directories
+fn glob(pattern: &str) -> Vec<PathBuf> {
+    let root = if pattern.starts_with('/') { PathBuf::from("/") } else { PathBuf::new() };
+    let mut paths = vec![root];
+
+    for component in pattern.split('/').filter(|c| ! c.is_empty()) {
+        paths = paths.iter().flat_map(|base| matching(base, component)).collect();
+    }
+
+    paths.retain(|path| path.is_file());
+    paths.dedup();
+    paths
+}
+
+/// Paths under `base` matching a single component of a glob pattern
+fn matching(base: &Path, component: &str) -> Vec<PathBuf> {
+    if component == "**" {
+        let mut found = vec![base.to_owned()];
+        descendants(base, &mut found);
+        return found
+    }
+
+    if ! cli::is_glob(component) {
+        let path = base.join(component);
+        return if path.exists() { vec![path] } else { vec![] }
+    }
+
+    children(base).into_iter()
+        .filter(|path| path.file_name().is_some_and(|name| cli::wildcard_match(component, &name.to_string_lossy())))
+        .collect()
+}
+
+/// Entries of directory `dir` in name order, or none when it can't be read
+fn children(dir: &Path) -> Vec<PathBuf> {
+    let listed = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let Ok(entries) = fs::read_dir(listed) else { return vec![] };
+
+    let mut paths: Vec<_> = entries.flatten().map(|entry| dir.join(entry.file_name())).collect();
+    paths.sort();
+    paths
+}
+
+/// Adds every file and directory under `dir` to `found`, without following
+/// symbolic links to directories
+fn descendants(dir: &Path, found: &mut Vec<PathBuf>) {
+    for path in children(dir) {
+        let is_dir = fs::symlink_metadata(&path).is_ok_and(|m| m.is_dir());
+        found.push(path.clone());
+
+        if is_dir {
+            descendants(&path, found);
+        }
+    }
+}
+
+/// Follows the logs of the kubernetes pods of `target` with kubectl. Each
+/// line is prefixed with its pod and container, see `kubectl_source`.
+pub (crate) fn kubectl_logs(target: &str, namespace: Option<&str>) -> io::Result<Live> {
+    let mut command = Command::new("kubectl");
+    command.args(["logs", "--follow", "--prefix", "--all-containers"]);
+
+    if let Some(namespace) = namespace {
+        command.args(["--namespace", namespace]);
+    }
+
+    if target.contains('=') {
+        command.args(["--selector", target]);
+    } else {
+        command.arg(target);
+    }
+
+    spawn(command)
+}
+
+/// Follows the journal with journalctl, only for `unit` if given. Entries
+/// are read with --preset journal.
+pub (crate) fn journal(unit: Option<&str>) -> io::Result<Live> {
+    let mut command = Command::new("journalctl");
+    command.args(["--output", "json", "--follow"]);
+
+    if let Some(unit) = unit {
+        command.args(["--unit", unit]);
+    }
+
+    spawn(command)
+}
+
+/// The lines written by `command` to its stdout
+fn spawn(mut command: Command) -> io::Result<Live> {
+    let name = command.get_program().to_string_lossy().into_owned();
+    let child = command.stdout(Stdio::piped()).spawn()
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", name, err)))?;
+    let stdout = child.stdout.ok_or_else(|| io::Error::other(format!("{}: no output", name)))?;
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in Lines::new(BufReader::new(stdout)) {
+            if sender.send(line).is_err() {
+                return
+            }
+        }
+    });
+
+    Ok(Live { receiver })
+}
+
+/// How often `Live::events` and `Followed` tell that no line came, see
+/// `Event::Tick`
+const TICK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Lines read by other threads from sources that can be quiet for a long
+/// time, such as commands and sockets
+pub (crate) struct Live {
+    receiver: Receiver<io::Result<String>>,
+}
+
+impl Live {
+    /// The lines as events of source 0, with an `Event::Tick` whenever no
+    /// line came for a while
+    pub (crate) fn events(self) -> impl Iterator<Item = Event> {
+        std::iter::from_fn(move || match self.receiver.recv_timeout(TICK_INTERVAL) {
+            Ok(line) => Some(Event::Line(0, line)),
+            Err(RecvTimeoutError::Timeout) => Some(Event::Tick),
+            Err(RecvTimeoutError::Disconnected) => None,
+        })
+    }
+}
+
+impl Iterator for Live {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+/// Addresses to receive lines on, given to --listen
+#[derive(Debug, Clone, PartialEq)]
+pub (crate) enum Listen {
+    /// Each datagram holds one or more lines
+    Udp(String),
+    /// Lines of any number of connections
+    Tcp(String),
+    /// Lines of any number of connections to a unix domain socket
+    Unix(PathBuf),
+}
+
+/// `udp:PORT` or `tcp:PORT` to listen on localhost, `udp:HOST:PORT` and
+/// `tcp:HOST:PORT`, or `unix:PATH`
+impl FromStr for Listen {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let (protocol, address) = src.split_once(':').ok_or_else(|| anyhow!("Expected udp:PORT, tcp:PORT or unix:PATH, got: {}", src))?;
+
+        if protocol == "unix" {
+            return match cfg!(unix) {
+                true => Ok(Listen::Unix(PathBuf::from(address))),
+                false => Err(anyhow!("Unix domain sockets are not supported on this platform")),
+            }
+        }
+
+        let address = match address.parse::<u16>() {
+            Ok(port) => format!("127.0.0.1:{}", port),
+            Err(_) => address.to_owned(),
+        };
+
+        match protocol {
+            "udp" => Ok(Listen::Udp(address)),
+            "tcp" => Ok(Listen::Tcp(address)),
+            _ => Err(anyhow!("Unknown protocol: {}, expected udp, tcp or unix", protocol)),
+        }
+    }
+}
+
+/// The lines received on `address`. Lines of concurrent connections are
+/// interleaved, each line staying whole. Failing connections are dropped
+/// without ending the input.
+pub (crate) fn listen(address: &Listen) -> io::Result<Live> {
+    let (sender, receiver) = mpsc::channel();
+
+    match address {
+        Listen::Udp(address) => {
+            let socket = UdpSocket::bind(address).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", address, err)))?;
+            thread::spawn(move || receive_datagrams(socket, sender));
+        },
+        Listen::Tcp(address) => {
+            let listener = TcpListener::bind(address).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", address, err)))?;
+            thread::spawn(move || accept(listener.incoming(), sender));
+        },
+        #[cfg(unix)]
+        Listen::Unix(path) => {
+            let listener = bind_unix(path).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+            thread::spawn(move || accept(listener.incoming(), sender));
+        },
+        #[cfg(not(unix))]
+        Listen::Unix(_) =>
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Unix domain sockets are not supported on this platform")),
+    }
+
+    Ok(Live { receiver })
+}
+
+/// Binds a unix domain socket at `path`, replacing the socket of a
+/// previous run that was not removed
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> io::Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let stale = fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) && UnixStream::connect(path).is_err();
+    if stale {
+        fs::remove_file(path)?;
+    }
+
+    UnixListener::bind(path)
+}
+
+/// Reads the lines of each connection in its own thread
+fn accept<S: io::Read + Send + 'static>(connections: impl Iterator<Item = io::Result<S>>, sender: Sender<io::Result<String>>) {
+    for connection in connections {
+        match connection {
+            Ok(connection) => {
+                let sender = sender.clone();
+                thread::spawn(move || forward_lines(BufReader::new(connection), sender));
+            },
+            Err(err) => log::debug!("Could not accept connection: {}", err),
+        }
+    }
+}
+
+fn receive_datagrams(socket: UdpSocket, sender: Sender<io::Result<String>>) {
+    let mut buffer = vec![0; 65536];
+
+    loop {
+        let len = match socket.recv(&mut buffer) {
+            Ok(len) => len,
+            Err(err) => {
+                let _ = sender.send(Err(err));
+                return
+            },
+        };
+
+        for line in String::from_utf8_lossy(&buffer[..len]).split_inclusive('\n') {
+            if sender.send(Ok(line.to_owned())).is_err() {
+                return
+            }
+        }
+    }
+}
+
+/// Sends the lines of a connection until it is closed
+fn forward_lines(reader: impl BufRead, sender: Sender<io::Result<String>>) {
+    for line in Lines::new(reader) {
+        match line {
+            Ok(line) => if sender.send(Ok(line)).is_err() {
+                return
+            },
+            Err(err) => {
+                log::debug!("Dropping connection: {}", err);
+                return
+            },
+        }
+    }
+}
+
+/// The pod and container of a line prefixed by `kubectl logs --prefix`,
+/// such as `[pod/api-7d9f/app] `, and the length of that prefix
+pub (crate) fn kubectl_source(line: &str) -> Option<(&str, usize)> {
+    let rest = line.strip_prefix('[')?;
+    let end = rest.find("] ")?;
+    let source = &rest[..end];
+
+    Some((source.strip_prefix("pod/").unwrap_or(source), end + 3))
+}
+
+/// Strips what comes before the json of `line`, such as the timestamp
+/// added by docker, see --skip-prefix and --prefix-regex. Returns the rest
+/// of the line and the named groups of --prefix-regex.
+pub (crate) fn strip_prefix<'l>(line: &'l str, opts: &Opt) -> (&'l str, Map<String, Value>) {
+    let mut fields = Map::new();
+
+    if let Some(regex) = &opts.prefix_regex {
+        if let Some(captures) = regex.captures(line).filter(|captures| captures.get(0).is_some_and(|m| m.start() == 0)) {
+            for name in regex.capture_names().flatten() {
+                if let Some(value) = captures.name(name) {
+                    fields.insert(name.to_owned(), Value::String(value.as_str().to_owned()));
+                }
+            }
+
+            return (&line[captures[0].len()..], fields)
+        }
+    }
+
+    if opts.skip_prefix {
+        if let Some(start) = line.find(['{', '[']) {
+            return (&line[start..], fields)
+        }
+    }
+
+    (line, fields)
+}
+
+/// The lines of each source, one source after the other
+pub (crate) fn concat(sources: Vec<Source<'_>>) -> Tagged<'_> {
+    Box::new(sources.into_iter().enumerate().flat_map(|(index, source)| source.map(move |line| (index, line))))
+}
+
+/// Joins the lines of json objects and arrays written over several lines,
+/// such as pretty printed ones, see --multiline. Other lines are left as
+/// they are.
+pub (crate) struct Records<'a> {
+    lines: Source<'a>,
+}
+
+impl<'a> Records<'a> {
+    pub (crate) fn new(lines: Source<'a>) -> Records<'a> {
+        Records { lines }
+    }
+}
+
+impl Iterator for Records<'_> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record = match self.lines.next()? {
+            Ok(line) => line,
+            Err(err) => return Some(Err(err)),
+        };
+
+        if ! record.trim_start().starts_with(['{', '[']) {
+            return Some(Ok(record))
+        }
+
+        let mut nesting = Nesting::default();
+        nesting.scan(&record);
+
+        while nesting.depth > 0 {
+            match self.lines.next() {
+                Some(Ok(line)) => {
+                    nesting.scan(&line);
+                    record.push_str(&line);
+                },
+                Some(Err(err)) => return Some(Err(err)),
+                None => break,
+            }
+        }
+
+        Some(Ok(record))
+    }
+}
+
+/// How deeply nested in json objects and arrays text is, ignoring brackets
+/// within strings
+#[derive(Debug, Default)]
+struct Nesting {
+    depth: i64,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl Nesting {
+    fn scan(&mut self, text: &str) {
+        for c in text.chars() {
+            match c {
+                _ if self.escaped => self.escaped = false,
+                '\\' if self.in_string => self.escaped = true,
+                '"' => self.in_string = ! self.in_string,
+                '{' | '[' if ! self.in_string => self.depth += 1,
+                '}' | ']' if ! self.in_string => self.depth -= 1,
+                _ => (),
+            }
+        }
+    }
+}
+
+/// How long to wait for followed files to grow
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A file followed as it is written, see --follow. The file is reopened
+/// when it is replaced or truncated, as log rotation does, and waited for
+/// while it does not exist.
+pub (crate) struct Follow {
+    path: PathBuf,
+    reader: Option<BufReader<File>>,
+    /// Bytes read since the file was opened
+    position: u64,
+    /// Start of a line still being written
+    partial: String,
+    /// Whether reading failed, after which the file is no longer followed
+    failed: bool,
+}
+
+impl Follow {
+    pub (crate) fn new(path: &Path) -> io::Result<Follow> {
+        if path == Path::new("-") {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "stdin can't be followed, pipe it instead"))
+        }
+
+        Ok(Follow { path: path.to_owned(), reader: None, position: 0, partial: String::new(), failed: false })
+    }
+
+    /// The next complete line of the file, or None when there is none yet
+    pub (crate) fn poll(&mut self) -> io::Result<Option<String>> {
+        if self.failed {
+            return Ok(None)
+        }
+
+        if self.reader.is_none() {
+            match File::open(&self.path) {
+                Ok(file) => {
+                    self.reader = Some(BufReader::new(file));
+                    self.position = 0;
+                },
+                Err(err) if err.kind() == io::ErrorKind::NotFound =>
+                    return Ok(None),
+                Err(err) => {
+                    self.failed = true;
+                    return Err(io::Error::new(err.kind(), format!("{}: {}", self.path.display(), err)))
+                },
+            }
+        }
+
+        let Some(reader) = self.reader.as_mut() else { return Ok(None) };
+        let read = match reader.read_line(&mut self.partial) {
+            Ok(read) => read,
+            // The line that is not valid UTF-8 was skipped
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => return Err(err),
+            Err(err) => {
+                self.failed = true;
+                return Err(err)
+            },
+        };
+        self.position += read as u64;
+
+        if self.partial.ends_with('\n') {
+            return Ok(Some(std::mem::take(&mut self.partial)))
+        }
+
+        if read == 0 && self.rotated()? {
+            log::debug!("Reopening {}", self.path.display());
+            self.reader = None;
+
+            if ! self.partial.is_empty() {
+                return Ok(Some(std::mem::take(&mut self.partial)))
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Whether the file at `path` was replaced or truncated since it was opened
+    fn rotated(&self) -> io::Result<bool> {
+        let (Some(reader), Ok(current)) = (&self.reader, fs::metadata(&self.path)) else { return Ok(false) };
+        let opened = reader.get_ref().metadata()?;
+
+        Ok(current.len() < self.position || ! same_file(&opened, &current))
+    }
+}
+
+/// Whether both metadata are of the same file. Always true where files
+/// have no identity
+fn same_file(left: &Metadata, right: &Metadata) -> bool {
+    file_id(left) == file_id(right)
+}
+
+#[cfg(unix)]
+fn file_id(meta: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Where reading stopped in each file, to resume from there on the next
+/// run, see --checkpoint
+pub (crate) struct Checkpoint {
+    path: PathBuf,
+    /// Offset and identity of each file, by absolute path
+    files: Map<String, Value>,
+}
+
+impl Checkpoint {
+    /// Loads the checkpoint at `path`, empty when it doesn't exist yet
+    pub (crate) fn load(path: &Path) -> io::Result<Checkpoint> {
+        let files = match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), err)))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Map::new(),
+            Err(err) => return Err(io::Error::new(err.kind(), format!("{}: {}", path.display(), err))),
+        };
+
+        Ok(Checkpoint { path: path.to_owned(), files })
+    }
+
+    /// The offset to resume reading `file` at. Files that were replaced or
+    /// truncated since are read from the start.
+    pub (crate) fn offset(&self, file: &Path) -> u64 {
+        let (Some(entry), Ok(meta)) = (checkpoint_key(file).and_then(|key| self.files.get(&key)), fs::metadata(file)) else { return 0 };
+        let offset = entry["offset"].as_u64().unwrap_or(0);
+
+        if offset <= meta.len() && entry["id"] == json!(file_id(&meta)) {
+            offset
+        } else {
+            0
+        }
+    }
+
+    /// Records that `file` was read up to `offset`
+    pub (crate) fn set(&mut self, file: &Path, offset: u64) {
+        let (Some(key), Ok(meta)) = (checkpoint_key(file), fs::metadata(file)) else { return };
+        self.files.insert(key, json!({ "offset": offset, "id": file_id(&meta) }));
+    }
+
+    /// Writes the checkpoint, replacing the previous one at once so that an
+    /// interrupted run doesn't leave it half written
+    pub (crate) fn save(&self) -> io::Result<()> {
+        let mut partial = self.path.clone().into_os_string();
+        partial.push(".tmp");
+
+        fs::write(&partial, Value::Object(self.files.clone()).to_string())?;
+        fs::rename(&partial, &self.path)
+    }
+}
+
+fn checkpoint_key(file: &Path) -> Option<String> {
+    fs::canonicalize(file).ok().map(|path| path.to_string_lossy().into_owned())
+}
+
+/// What happened while reading input
+pub (crate) enum Event {
+    /// A line of the source with the given index
+    Line(usize, io::Result<String>),
+    /// No line was read for this long, see --idle-marker
+    Idle(Duration),
+    /// No line was read for a short while, to write lines held back for
+    /// too long, see --correlate
+    Tick,
+}
+
+/// Lines of followed files as they are written, checking each file in turn
+pub (crate) struct Followed {
+    files: Vec<Follow>,
+    /// The file checked first, the last one that had a line
+    current: usize,
+    /// How long files can be quiet before an `Event::Idle`
+    idle: Option<Duration>,
+    /// When the last line was read
+    last_line: Instant,
+    /// Idle events since the last line
+    idle_count: u32,
+    /// When the last line or `Event::Tick` was
+    last_tick: Instant,
+}
+
+impl Followed {
+    pub (crate) fn new(files: Vec<Follow>, idle: Option<Duration>) -> Followed {
+        Followed { files, current: 0, idle, last_line: Instant::now(), idle_count: 0, last_tick: Instant::now() }
+    }
+}
+
+impl Iterator for Followed {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.files.is_empty() {
+            return None
+        }
+
+        loop {
+            for offset in 0..self.files.len() {
+                let index = (self.current + offset) % self.files.len();
+
+                match self.files[index].poll() {
+                    Ok(None) => continue,
+                    Ok(Some(line)) => {
+                        self.current = index;
+                        self.last_line = Instant::now();
+                        self.last_tick = self.last_line;
+                        self.idle_count = 0;
+                        return Some(Event::Line(index, Ok(line)))
+                    },
+                    Err(err) =>
+                        return Some(Event::Line(index, Err(err))),
+                }
+            }
+
+            if let Some(idle) = self.idle {
+                let quiet = self.last_line.elapsed();
+
+                if quiet >= idle * (self.idle_count + 1) {
+                    self.idle_count += 1;
+                    return Some(Event::Idle(idle * self.idle_count))
+                }
+            }
+
+            if self.last_tick.elapsed() >= TICK_INTERVAL {
+                self.last_tick = Instant::now();
+                return Some(Event::Tick)
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+type Time = Option<DateTime<FixedOffset>>;
+
+/// Merges sources into one stream ordered by the time of their lines, see
+/// --merge-by-time. Each source is expected to be ordered already. Lines
+/// without a time stay after the line before them in their source.
+pub (crate) struct MergeByTime<'a> {
+    sources: Vec<Source<'a>>,
+    /// The next line of each source, waiting to be written
+    heads: Vec<Option<io::Result<String>>>,
+    /// Time of the last line of each source that had one
+    last: Vec<Time>,
+    /// Sources with a line waiting, earliest first. Ties go to the source
+    /// given first.
+    queue: BinaryHeap<Reverse<(Time, usize)>>,
+    opts: &'a Opt,
+}
+
+impl<'a> MergeByTime<'a> {
+    pub (crate) fn new(sources: Vec<Source<'a>>, opts: &'a Opt) -> MergeByTime<'a> {
+        let count = sources.len();
+        let mut merge = MergeByTime {
+            sources,
+            heads: (0..count).map(|_| None).collect(),
+            last: vec![None; count],
+            queue: BinaryHeap::with_capacity(count),
+            opts,
+        };
+
+        for index in 0..count {
+            merge.advance(index);
+        }
+
+        merge
+    }
+
+    /// Reads the next line of source `index` and queues it. Errors are
+    /// queued first, to be reported right away.
+    fn advance(&mut self, index: usize) {
+        let Some(next) = self.sources[index].next() else { return };
+
+        let time = match &next {
+            Ok(line) => serde_json::from_str(line).ok()
+                .and_then(|json| timestamp::of_line(&json, self.opts))
+                .or(self.last[index]),
+            Err(_) => None,
+        };
+
+        if next.is_ok() {
+            self.last[index] = time;
+        }
+
+        self.heads[index] = Some(next);
+        self.queue.push(Reverse((time, index)));
+    }
+}
+
+impl Iterator for MergeByTime<'_> {
+    type Item = (usize, io::Result<String>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((_, index)) = self.queue.pop()?;
+        let line = self.heads[index].take()?;
+
+        // Sources end by themselves after errors they can't read past, so
+        // an invalid line doesn't drop the rest of its source
+        self.advance(index);
+
+        Some((index, line))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use structopt::StructOpt;
+    use super::*;
+
+    fn source(text: &'static str) -> Source<'static> {
+        Box::new(Lines::new(text.as_bytes()))
+    }
+
+    #[test]
+    fn listen_test() {
+        assert_eq!("udp:5514".parse::<Listen>().unwrap(), Listen::Udp("127.0.0.1:5514".to_owned()));
+        assert_eq!("tcp:0.0.0.0:9000".parse::<Listen>().unwrap(), Listen::Tcp("0.0.0.0:9000".to_owned()));
+        assert_eq!("unix:/tmp/app.sock".parse::<Listen>().unwrap(), Listen::Unix(PathBuf::from("/tmp/app.sock")));
+        assert!("http:80".parse::<Listen>().is_err());
+        assert!("9000".parse::<Listen>().is_err());
+    }
+
+    #[test]
+    fn listen_udp_test() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        drop(socket);
+
+        let mut lines = listen(&Listen::Udp(address.clone())).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(b"{\"a\": 1}\n{\"b\": 2}\n", &address).unwrap();
+
+        assert_eq!(lines.next().unwrap().unwrap(), "{\"a\": 1}\n");
+        assert_eq!(lines.next().unwrap().unwrap(), "{\"b\": 2}\n");
+    }
+
+    #[test]
+    fn listen_tcp_test() {
+        use std::io::Write;
+        use std::net::TcpStream;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let mut lines = listen(&Listen::Tcp(address.clone())).unwrap();
+        let mut first = TcpStream::connect(&address).unwrap();
+        let mut second = TcpStream::connect(&address).unwrap();
+
+        first.write_all(b"{\"a\": ").unwrap();
+        second.write_all(b"{\"b\": 2}\n").unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "{\"b\": 2}\n");
+
+        first.write_all(b"1}\n").unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "{\"a\": 1}\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn listen_unix_test() {
+        use std::io::Write;
+        use std::os::unix::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("jaxe-listen-{}.sock", std::process::id()));
+        // A socket left by a previous run is replaced
+        std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        let mut lines = listen(&Listen::Unix(path.clone())).unwrap();
+        let mut first = UnixStream::connect(&path).unwrap();
+        let mut second = UnixStream::connect(&path).unwrap();
+
+        first.write_all(b"{\"a\": ").unwrap();
+        second.write_all(b"{\"b\": 2}\n").unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "{\"b\": 2}\n");
+
+        first.write_all(b"1}\n").unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "{\"a\": 1}\n");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn lines_test() {
+        let lines: Vec<_> = Lines::new("a\nb\r\nc".as_bytes()).map(Result::unwrap).collect();
+
+        assert_eq!(lines, vec!["a\n", "b\r\n", "c"]);
+
+        let lines: Vec<_> = Lines::new(&b"a\n\xff\xfe\nc\n"[..]).collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].as_ref().unwrap(), "a\n");
+        assert_eq!(lines[1].as_ref().unwrap_err().get_ref().and_then(|err| err.downcast_ref::<InvalidLine>()).unwrap().len, 3);
+        assert_eq!(lines[2].as_ref().unwrap(), "c\n");
+    }
+
+    #[test]
+    fn merge_by_time_test() {
+        let opts = Opt::from_iter(["jaxe", "-t", "time", "--merge-by-time", "a.log"]);
+
+        let first = source(concat!(
+            "{\"time\": \"2024-05-01T14:00:00Z\", \"msg\": \"a1\"}\n",
+            "not json\n",
+            "{\"time\": \"2024-05-01T14:00:05Z\", \"msg\": \"a2\"}\n",
+        ));
+        let second = source(concat!(
+            "{\"time\": \"2024-05-01T14:00:01Z\", \"msg\": \"b1\"}\n",
+            "{\"time\": \"2024-05-01T14:00:09Z\", \"msg\": \"b2\"}\n",
+        ));
+
+        let merged: Vec<_> = MergeByTime::new(vec![first, second], &opts)
+            .map(|(index, line)| serde_json::from_str::<serde_json::Value>(&line.unwrap())
+                .map(|json| (index, json["msg"].as_str().unwrap().to_owned()))
+                .unwrap_or_else(|_| (index, "-".to_owned())))
+            .collect();
+
+        assert_eq!(merged, vec![(0, "a1".to_owned()), (0, "-".to_owned()), (1, "b1".to_owned()), (0, "a2".to_owned()), (1, "b2".to_owned())]);
+    }
+
+    #[test]
+    fn merge_by_time_invalid_line_test() {
+        let opts = Opt::from_iter(["jaxe", "-t", "time", "--merge-by-time", "a.log"]);
+
+        let first: Source = Box::new(Lines::new(&b"{\"time\": \"2024-05-01T14:00:01Z\", \"msg\": \"a1\"}\n\xff\xfe\n{\"time\": \"2024-05-01T14:00:03Z\", \"msg\": \"a3\"}\n"[..]));
+        let second = source("{\"time\": \"2024-05-01T14:00:02Z\", \"msg\": \"b2\"}\n");
+
+        let merged: Vec<_> = MergeByTime::new(vec![first, second], &opts)
+            .map(|(index, line)| match line {
+                Ok(line) => (index, serde_json::from_str::<serde_json::Value>(&line).unwrap()["msg"].as_str().unwrap().to_owned()),
+                Err(_) => (index, "error".to_owned()),
+            })
+            .collect();
+
+        assert_eq!(merged, vec![(0, "a1".to_owned()), (0, "error".to_owned()), (1, "b2".to_owned()), (0, "a3".to_owned())]);
+    }
+
+    #[test]
+    fn follow_test() {
+        let path = std::env::temp_dir().join(format!("jaxe-follow-{}.log", std::process::id()));
+        fs::write(&path, "a1\na").unwrap();
+
+        let mut follow = Follow::new(&path).unwrap();
+        assert_eq!(follow.poll().unwrap(), Some("a1\n".to_owned()));
+        assert_eq!(follow.poll().unwrap(), None);
+
+        fs::write(&path, "a1\na2\na3\n").unwrap();
+        assert_eq!(follow.poll().unwrap(), Some("a2\n".to_owned()));
+        assert_eq!(follow.poll().unwrap(), Some("a3\n".to_owned()));
+
+        fs::write(&path, "b1\n").unwrap();
+        assert_eq!(follow.poll().unwrap(), None);
+        assert_eq!(follow.poll().unwrap(), Some("b1\n".to_owned()));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn labels_test() {
+        let files = [
+            (PathBuf::from("api/app.log"), None),
+            (PathBuf::from("worker/app.log"), None),
+            (PathBuf::from("/var/log/db.log"), None),
+            (PathBuf::from("a.log"), Some("canary".to_owned())),
+        ];
+
+        assert_eq!(labels(&files), vec!["api/app.log", "worker/app.log", "db.log", "canary"]);
+        assert_eq!(split_tag(Path::new("a.log:canary")), (PathBuf::from("a.log"), Some("canary".to_owned())));
+        assert_eq!(split_tag(Path::new("logs:2024/a.log")), (PathBuf::from("logs:2024/a.log"), None));
+    }
+
+    #[test]
+    fn expand_test() {
+        let dir = std::env::temp_dir().join(format!("jaxe-expand-{}", std::process::id()));
+        fs::create_dir_all(dir.join("api/old")).unwrap();
+        fs::write(dir.join("api/b.json"), "").unwrap();
+        fs::write(dir.join("api/a.json"), "").unwrap();
+        fs::write(dir.join("api/old/c.json"), "").unwrap();
+        fs::write(dir.join("api/notes.txt"), "").unwrap();
+
+        let relative = |paths: Vec<(PathBuf, Option<String>)>| -> Vec<String> {
+            paths.iter().map(|(p, _)| p.strip_prefix(&dir).unwrap().to_string_lossy().into_owned()).collect()
+        };
+
+        let found = expand(&[dir.join("api/*.json")], false).unwrap();
+        assert_eq!(relative(found), vec!["api/a.json", "api/b.json"]);
+
+        let found = expand(&[dir.join("**/*.json")], false).unwrap();
+        assert_eq!(relative(found), vec!["api/a.json", "api/b.json", "api/old/c.json"]);
+
+        let found = expand(&[dir.join("api")], false).unwrap();
+        assert_eq!(relative(found), vec!["api/a.json", "api/b.json", "api/notes.txt", "api/old/c.json"]);
+
+        assert!(expand(&[dir.join("*.log")], false).is_err());
+        assert_eq!(expand(&[PathBuf::from("-")], false).unwrap(), vec![(PathBuf::from("-"), None)]);
+
+        let found = expand(&[dir.join("api/*.json:api"), dir.join("api/old/c.json")], false).unwrap();
+        assert_eq!(found.iter().map(|(_, tag)| tag.as_deref()).collect::<Vec<_>>(), vec![Some("api"), Some("api"), None]);
+        assert_eq!(labels(&found), vec!["api", "api", "c.json"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_test() {
+        let dir = std::env::temp_dir().join(format!("jaxe-checkpoint-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("app.log");
+        let state = dir.join("state.json");
+        fs::write(&log, "a1\na2\n").unwrap();
+
+        let mut checkpoint = Checkpoint::load(&state).unwrap();
+        assert_eq!(checkpoint.offset(&log), 0);
+        checkpoint.set(&log, 3);
+        checkpoint.save().unwrap();
+
+        let checkpoint = Checkpoint::load(&state).unwrap();
+        assert_eq!(checkpoint.offset(&log), 3);
+        let lines: Vec<_> = open(&log, 3).unwrap().map(Result::unwrap).collect();
+        assert_eq!(lines, vec!["a2\n"]);
+
+        fs::write(&log, "b").unwrap();
+        assert_eq!(checkpoint.offset(&log), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn records_test() {
+        let text = concat!(
+            "{\"msg\": \"one line\"}\n",
+            "{\n",
+            "  \"msg\": \"pretty {[\\\" \",\n",
+            "  \"tags\": [\"a\"]\n",
+            "}\n",
+            "plain text\n",
+        );
+
+        let records: Vec<_> = Records::new(source(text)).map(Result::unwrap).collect();
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0], "{\"msg\": \"one line\"}\n");
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&records[1]).unwrap()["tags"][0], "a");
+        assert_eq!(records[2], "plain text\n");
+    }
+
+    #[test]
+    fn strip_prefix_test() {
+        let opts = Opt::from_iter(["jaxe", "--skip-prefix"]);
+        assert_eq!(strip_prefix("2024-05-01T14:00:00Z stdout F {\"msg\": \"a\"}", &opts).0, "{\"msg\": \"a\"}");
+        assert_eq!(strip_prefix("plain text", &opts).0, "plain text");
+
+        let opts = Opt::from_iter(["jaxe", "--prefix-regex", r"^(?P<ts>\S+) (?P<stream>\w+) \w "]);
+        let (rest, fields) = strip_prefix("2024-05-01T14:00:00Z stderr F {\"msg\": \"a\"}", &opts);
+        assert_eq!(rest, "{\"msg\": \"a\"}");
+        assert_eq!(Value::Object(fields), json!({"ts": "2024-05-01T14:00:00Z", "stream": "stderr"}));
+        assert_eq!(strip_prefix("{\"msg\": \"a\"}", &opts).0, "{\"msg\": \"a\"}");
+    }
+
+    #[test]
+    fn kubectl_source_test() {
+        assert_eq!(kubectl_source("[pod/api-7d9f/app] {\"msg\": \"a\"}"), Some(("api-7d9f/app", 19)));
+        assert_eq!(kubectl_source("{\"msg\": \"a\"}"), None);
+    }
+
+    #[test]
+    fn concat_test() {
+        let lines: Vec<_> = concat(vec![source("a1\na2\n"), source("b1\n")])
+            .map(|(index, line)| (index, line.unwrap()))
+            .collect();
+
+        assert_eq!(lines, vec![(0, "a1\n".to_owned()), (0, "a2\n".to_owned()), (1, "b1\n".to_owned())]);
+    }
+}
@@ -0,0 +1,179 @@
+use std::str::FromStr;
+use serde_json::Value;
+use anyhow::{anyhow, Result};
+
+use crate::Opt;
+use crate::cli::KeyValue;
+
+/// Numeric levels used by bunyan and pino
+const NUMERIC_LEVELS: &[(&str, &str)] = &[
+    ("10", "TRACE"),
+    ("20", "DEBUG"),
+    ("30", "INFO"),
+    ("40", "WARN"),
+    ("50", "ERROR"),
+    ("60", "FATAL"),
+];
+
+/// Syslog severities, by number and keyword
+const SYSLOG_LEVELS: &[(&str, &str)] = &[
+    ("0", "FATAL"), ("emerg", "FATAL"), ("panic", "FATAL"),
+    ("1", "FATAL"), ("alert", "FATAL"),
+    ("2", "FATAL"), ("crit", "FATAL"),
+    ("3", "ERROR"), ("err", "ERROR"), ("error", "ERROR"),
+    ("4", "WARN"), ("warning", "WARN"), ("warn", "WARN"),
+    ("5", "INFO"), ("notice", "INFO"),
+    ("6", "INFO"), ("info", "INFO"),
+    ("7", "DEBUG"), ("debug", "DEBUG"),
+];
+
+/// The built-in table used to name levels, given to --level-scheme
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub (crate) enum Scheme {
+    /// Numeric levels of bunyan and pino, such as 30 for INFO
+    Bunyan,
+    /// Syslog severities 0 to 7 and their keywords, such as err or notice
+    Syslog,
+}
+
+impl FromStr for Scheme {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "bunyan" | "pino" => Ok(Scheme::Bunyan),
+            "syslog" => Ok(Scheme::Syslog),
+            _ => Err(anyhow!("Unknown level scheme: {}, expected bunyan or syslog", src)),
+        }
+    }
+}
+
+/// Standard level names, from least to most severe
+const SEVERITY: &[&str] = &["TRACE", "DEBUG", "INFO", "WARN", "ERROR", "FATAL"];
+
+pub (crate) fn severity(name: &str) -> Option<usize> {
+    SEVERITY.iter().position(|s| s.eq_ignore_ascii_case(name))
+}
+
+/// The least severe level shown, given to --min-level
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub (crate) struct MinLevel(usize);
+
+impl FromStr for MinLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        severity(src).map(MinLevel).ok_or_else(|| anyhow!("Unknown level: {}, expected one of {}", src, SEVERITY.join(", ")))
+    }
+}
+
+/// Whether the level of `line` is at least `min`. Lines without a level, or
+/// with a level that is not a standard name, are always shown.
+pub (crate) fn at_least(line: &Value, opts: &Opt, min: MinLevel) -> bool {
+    let level = opts.level.iter().find_map(|key| line.get(key)).and_then(|l| l.as_str());
+
+    match level.and_then(severity) {
+        Some(s) => s >= min.0,
+        None => true,
+    }
+}
+
+fn lookup<'a>(table: impl IntoIterator<Item = (&'a str, &'a str)>, raw: &str) -> Option<&'a str> {
+    table.into_iter().find(|(from, _)| *from == raw).map(|(_, to)| to)
+}
+
+/// The level name for a level value such as `30`, from --level-map, from
+/// --level-alias ignoring case, or from the built-in table of `scheme`.
+/// Levels without a name are `None`.
+pub (crate) fn normalize(value: &Value, level_map: &[KeyValue], aliases: &[KeyValue], scheme: Scheme) -> Option<String> {
+    let raw = match value {
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s.clone(),
+        _ => return None,
+    };
+
+    let mapped = level_map.iter().map(|KeyValue(from, to)| (from.as_str(), to.as_str()));
+
+    let builtin = || match scheme {
+        Scheme::Bunyan => lookup(NUMERIC_LEVELS.iter().copied(), &raw),
+        Scheme::Syslog => lookup(SYSLOG_LEVELS.iter().copied(), &raw.to_lowercase()),
+    };
+
+    let alias = || aliases.iter()
+        .find(|KeyValue(from, _)| from.eq_ignore_ascii_case(&raw))
+        .map(|KeyValue(_, to)| to.as_str());
+
+    lookup(mapped, &raw)
+        .or_else(alias)
+        .or_else(builtin)
+        .map(|name| name.to_owned())
+}
+
+/// Replaces the value of the level key of `line` with its name, so numeric
+/// levels are filtered, colored and written like named ones
+pub (crate) fn normalize_line(line: &mut Value, opts: &Opt) {
+    let Value::Object(obj) = line else { return };
+
+    let Some(key) = opts.level.iter().find(|key| obj.contains_key(*key)) else { return };
+
+    if let Some(name) = normalize(&obj[key], &opts.level_map, &opts.level_alias, opts.level_scheme) {
+        obj.insert(key.clone(), Value::String(name));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use structopt::StructOpt;
+    use super::*;
+
+    #[test]
+    fn normalize_test() {
+        assert_eq!(normalize(&json!(30), &[], &[], Scheme::Bunyan), Some("INFO".to_owned()));
+        assert_eq!(normalize(&json!("50"), &[], &[], Scheme::Bunyan), Some("ERROR".to_owned()));
+        assert_eq!(normalize(&json!("INFO"), &[], &[], Scheme::Bunyan), None);
+        assert_eq!(normalize(&json!(35), &[], &[], Scheme::Bunyan), None);
+        assert_eq!(normalize(&json!(null), &[], &[], Scheme::Bunyan), None);
+    }
+
+    #[test]
+    fn normalize_level_map_test() {
+        let level_map = vec![KeyValue("35".into(), "NOTICE".into()), KeyValue("30".into(), "info".into())];
+
+        assert_eq!(normalize(&json!(35), &level_map, &[], Scheme::Bunyan), Some("NOTICE".to_owned()));
+        assert_eq!(normalize(&json!(30), &level_map, &[], Scheme::Bunyan), Some("info".to_owned()));
+        assert_eq!(normalize(&json!(40), &level_map, &[], Scheme::Bunyan), Some("WARN".to_owned()));
+    }
+
+    #[test]
+    fn normalize_syslog_test() {
+        assert_eq!(normalize(&json!(3), &[], &[], Scheme::Syslog), Some("ERROR".to_owned()));
+        assert_eq!(normalize(&json!("crit"), &[], &[], Scheme::Syslog), Some("FATAL".to_owned()));
+        assert_eq!(normalize(&json!("NOTICE"), &[], &[], Scheme::Syslog), Some("INFO".to_owned()));
+        assert_eq!(normalize(&json!(30), &[], &[], Scheme::Syslog), None);
+        assert_eq!(normalize(&json!(3), &[], &[], Scheme::Bunyan), None);
+    }
+
+    #[test]
+    fn normalize_alias_test() {
+        let aliases = vec![KeyValue("warning".into(), "WARN".into()), KeyValue("critical".into(), "ERROR".into())];
+
+        assert_eq!(normalize(&json!("Warning"), &[], &aliases, Scheme::Bunyan), Some("WARN".to_owned()));
+        assert_eq!(normalize(&json!("critical"), &[], &aliases, Scheme::Bunyan), Some("ERROR".to_owned()));
+        assert_eq!(normalize(&json!("notice"), &[], &aliases, Scheme::Bunyan), None);
+    }
+
+    #[test]
+    fn at_least_test() {
+        let opts = Opt::from_iter(["jaxe", "-l", "level"]);
+        let min: MinLevel = "warn".parse().unwrap();
+
+        assert!(at_least(&json!({"level": "ERROR"}), &opts, min));
+        assert!(at_least(&json!({"level": "WARN"}), &opts, min));
+        assert!(! at_least(&json!({"level": "info"}), &opts, min));
+        assert!(at_least(&json!({"level": "NOTICE"}), &opts, min));
+        assert!(at_least(&json!({"msg": "no level"}), &opts, min));
+
+        assert!("verbose".parse::<MinLevel>().is_err());
+    }
+}
@@ -1,147 +1,726 @@
-use std::io::{self, Write, BufRead};
-use std::collections::HashMap;
-use termcolor::{BufferWriter, WriteColor, ColorChoice, Color, ColorSpec};
+use std::io::{self, Write, IsTerminal};
+use std::path::{Path, PathBuf};
+use termcolor::{BufferWriter, WriteColor, ColorChoice, ColorSpec};
 use anyhow::Result;
-use serde_json::Value;
+use serde_json::{Map, Value};
+use chrono::TimeDelta;
+use terminal_size::Width;
 use structopt::StructOpt;
+use regex::Regex;
 
 mod parser;
 mod cli;
 mod filters;
+mod duration;
+mod output;
+mod template;
+mod transform;
+mod theme;
+mod level;
+mod timestamp;
+mod input;
+mod preset;
+mod plaintext;
+mod serve;
+mod metrics;
+mod exec;
+mod report;
+mod rate;
+mod correlate;
+mod trace;
+mod diff;
+mod replay;
 
 use cli::*;
 use filters::Filter;
+use output::OutputFormat;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "jaxe", about = "A j[son] [pick]axe!")]
 pub(crate) struct Opt {
-    /// Fields to extract, default to extracting all fields
+    /// Fields to extract, default to extracting all fields. Supports * and ? wildcards and nested paths such as user.id
     #[structopt(short, long, default_value)]
-    extract: MultOpt<String>,
+    extract: MultOpt<KeyPattern>,
 
-    /// Fields to omit
+    /// Fields to omit. Supports * and ? wildcards and nested paths such as user.id
     #[structopt(short, long, default_value)]
-    omit: MultOpt<String>,
+    omit: MultOpt<KeyPattern>,
+
+    /// Fields written dimmed, to keep them out of the way without omitting them. Supports * and ? wildcards
+    #[structopt(long, default_value)]
+    dim: MultOpt<KeyPattern>,
+
+    /// Fields whose values are replaced with --redact-mask. Supports * and ? wildcards and nested paths
+    #[structopt(long, default_value)]
+    redact: MultOpt<KeyPattern>,
+
+    /// Text used to replace redacted values
+    #[structopt(long, default_value = "***")]
+    redact_mask: String,
+
+    /// Fields whose values are replaced with a short stable hash. Supports * and ? wildcards and nested paths
+    #[structopt(long, default_value)]
+    hash: MultOpt<KeyPattern>,
+
+    /// Salt mixed into --hash values
+    #[structopt(long, env = "JAXE_HASH_SALT", hide_env_values = true, default_value = "")]
+    hash_salt: String,
+
+    /// Rename keys, such as kubernetes.pod_name=pod. Sources can be keys or nested paths
+    #[structopt(long, use_delimiter = true, require_delimiter = true)]
+    rename: Vec<KeyValue>,
+
+    /// Decode fields before filtering and display, such as payload=base64,query=url. Supported encodings are base64 and url
+    #[structopt(long, use_delimiter = true, require_delimiter = true)]
+    decode: Vec<transform::Decoding>,
+
+    /// String fields holding serialized JSON to parse, so they can be filtered and printed like other fields. Supports * and ? wildcards and nested paths
+    #[structopt(long, default_value)]
+    parse_nested: MultOpt<KeyPattern>,
+
+    /// Add a field computed from other fields, such as 'latency_s = duration_ms / 1000'. Supports + - * / and string concatenation with +
+    #[structopt(long, number_of_values = 1)]
+    derive: Vec<transform::Derivation>,
+
+    /// Omit fields with null, empty string, empty array or empty object values
+    #[structopt(long)]
+    omit_empty: bool,
 
     /// Do not print non-json lines
     #[structopt(short = "j", long)]
     no_omit_json: bool,
 
     /// Filter by. See parse language
-    #[structopt(short = "f", long)]
+    #[structopt(short = "f", long, number_of_values = 1)]
     filter: Vec<String>,
 
+    /// Only print lines that do not match the filters
+    #[structopt(short = "v", long)]
+    invert_match: bool,
+
+    /// Only write the first line of each value of this field, such as request_id, to drop repeated events of retries or fan-out. Lines without the field are written
+    #[structopt(long, parse(try_from_str = parser::parse_path))]
+    unique_by: Option<parser::EPath>,
+
+    /// Values remembered by --unique-by, the least recently seen are forgotten beyond this
+    #[structopt(long, default_value = "100000")]
+    unique_capacity: usize,
+
+    /// Hold back lines sharing a value of this field, such as trace_id, to write them together, indented after a separator. A group is written once its first line is older than --correlate-window
+    #[structopt(long)]
+    correlate: Option<String>,
+
+    /// How long --correlate waits for more lines of a group, by the time of the lines or as they are read when they have none
+    #[structopt(long, default_value = "30s", parse(try_from_str = duration::parse_arg))]
+    correlate_window: std::time::Duration,
+
+    /// Add the time since the last line matching the first filter to the next line matching the second one, as elapsed. Lines are paired by --key if given, as in --duration-between 'event == start' 'event == end' --key request_id
+    #[structopt(long, number_of_values = 2, value_names = &["start", "end"])]
+    duration_between: Vec<String>,
+
+    /// Field pairing the lines of --duration-between
+    #[structopt(long = "key", requires = "duration-between")]
+    duration_key: Option<String>,
+
+    /// Write spans, lines with a traceId and a spanId, as a tree of each trace with the start and duration of its spans. A trace is written once its root span is read, or at the end of the input
+    #[structopt(long)]
+    trace_tree: bool,
+
+    /// Print lines matching these filters in bold, without filtering out other lines
+    #[structopt(long, number_of_values = 1)]
+    highlight: Vec<String>,
+
+    /// Only start printing lines once a line matches this filter. The matching line is printed
+    #[structopt(long)]
+    after: Option<String>,
+
+    /// Stop printing lines once a line matches this filter. The matching line is not printed
+    #[structopt(long)]
+    before: Option<String>,
+
+    /// Maximum difference between two numbers for them to be considered equal by filters
+    #[structopt(long, default_value = "0")]
+    epsilon: f64,
+
     /// Use jq filters
     #[cfg(feature = "jq")]
     #[structopt(long)]
     jq: bool,
 
     /// level keys. The first of these keys in the json line will be used as the level of the log line and formatted at the start of the line.
-    #[structopt(short, long)]
+    #[structopt(short, long, number_of_values = 1)]
     level: Vec<String>,
 
+    /// Names of level values, such as 30=INFO,40=WARN,50=ERROR. Levels of --level-scheme are known without this
+    #[structopt(long, use_delimiter = true, require_delimiter = true)]
+    level_map: Vec<KeyValue>,
+
+    /// Level names to use for other level values, such as warning=WARN,critical=ERROR. Case is ignored
+    #[structopt(long, use_delimiter = true, require_delimiter = true)]
+    level_alias: Vec<KeyValue>,
+
+    /// Only show lines with at least this level: TRACE, DEBUG, INFO, WARN, ERROR or FATAL. Lines without a known level are always shown
+    #[structopt(long)]
+    min_level: Option<level::MinLevel>,
+
+    /// Built-in level names: bunyan for bunyan and pino numeric levels, or syslog for syslog severities and keywords
+    #[structopt(long, default_value = "bunyan")]
+    level_scheme: level::Scheme,
+
     /// Time keys. The first of these keys in the json line will be used as the date of the log line and formatted after the level.
-    #[structopt(short, long)]
+    #[structopt(short, long, number_of_values = 1)]
     time: Vec<String>,
 
-    /// Disable colors
+    /// Write timestamps with this chrono format, such as '%H:%M:%S%.3f'. Timestamps that can't be parsed are written as they are
+    #[structopt(long)]
+    time_format: Option<timestamp::TimeFormat>,
+
+    /// Convert timestamps to this time zone: local, UTC or a tz database name such as Europe/Lisbon
+    #[structopt(long)]
+    time_zone: Option<timestamp::Zone>,
+
+    /// Write timestamps as absolute times or as relative times such as 3s ago
+    #[structopt(long, default_value = "absolute")]
+    time_display: timestamp::TimeDisplay,
+
+    /// Write the time since the previous line after the timestamp, such as +12ms
+    #[structopt(long)]
+    delta: bool,
+
+    /// Interleave the lines of the given files in the order of their timestamps. Each file should be ordered already
+    #[structopt(long)]
+    merge_by_time: bool,
+
+    /// Read files in the order they were last modified, oldest first, instead of by name
+    #[structopt(long)]
+    sort_by_mtime: bool,
+
+    /// With --follow, write a separator when no line was read for this long, such as 60s, to tell a quiet service from a stuck pipe
+    #[structopt(long, requires = "follow", parse(try_from_str = duration::parse_arg))]
+    idle_marker: Option<std::time::Duration>,
+
+    /// Read logs of a known format: docker (json-file logging driver), journal (journalctl -o json), gelf (Graylog), cloudwatch (AWS CloudWatch Logs), gcp (gcloud logging read --format json), ecs (Elastic Common Schema), otel (OpenTelemetry), bunyan, pino, or a preset of ~/.config/jaxe/presets.toml
+    #[structopt(long)]
+    preset: Option<preset::Preset>,
+
+    /// Follow the systemd journal with journalctl, only for the given unit if any
+    #[structopt(long, conflicts_with = "files")]
+    journal: Option<Option<String>>,
+
+    /// Receive lines on udp:PORT, tcp:PORT or the unix domain socket unix:PATH instead of reading files. Ports are opened on localhost unless a host is given as in tcp:0.0.0.0:9000
+    #[structopt(long, conflicts_with_all = &["files", "journal"])]
+    listen: Option<input::Listen>,
+
+    /// Stream the lines written to clients of GET /events on this address, such as :8080 for localhost, as server-sent events. Clients can pass their own filter and a format of text or json, as in /events?filter=status%3E=500&format=json
+    #[structopt(long)]
+    serve: Option<String>,
+
+    /// Draw a sparkline of the lines written per second over the last minute on stderr, with the rate of each level. Meant for --follow
+    #[structopt(long)]
+    rate: bool,
+
+    /// Write at most this many lines per second, minute or hour, such as 200/s, and a summary of the lines dropped past it, to keep a tight error loop from flooding the terminal
+    #[structopt(long)]
+    rate_limit: Option<rate::RateLimit>,
+
+    /// Serve counters of the lines read, per level and per --metric-filter in the Prometheus format on GET /metrics at this address, such as :9200 for localhost or 0.0.0.0:9200 for all interfaces. Meant for --follow, to use a tail as a source of alerts
+    #[structopt(long)]
+    metrics_listen: Option<String>,
+
+    /// A filter whose matches are counted by --metrics-listen, given a name as in errors='level == ERROR'
+    #[structopt(long, number_of_values = 1, requires = "metrics-listen")]
+    metric_filter: Vec<KeyValue>,
+
+    /// Run this command for lines that are written, such as 'notify-send "{level}" "{msg}"'. Words can hold placeholders as in --format, values are passed as arguments without a shell
+    #[structopt(long)]
+    exec: Option<exec::CommandTemplate>,
+
+    /// Only run --exec for lines matching this filter
+    #[structopt(long, requires = "exec")]
+    exec_on: Option<String>,
+
+    /// Run --exec at most once in this time, lines matching in between are ignored
+    #[structopt(long, default_value = "5s", parse(try_from_str = duration::parse_arg))]
+    exec_interval: std::time::Duration,
+
+    /// Ring the terminal bell for lines that are written and match this filter, at most once a second
+    #[structopt(long)]
+    bell: Option<String>,
+
+    /// Send a desktop notification with notify-send, or osascript on macOS, for lines that are written and match this filter, at most once a second
+    #[structopt(long)]
+    notify: Option<String>,
+
+    /// Format of lines: json, where syslog and glog lines are read too, logfmt, or auto to read json, syslog, glog and logfmt lines
+    #[structopt(long, default_value = "json")]
+    input: plaintext::InputFormat,
+
+    /// Ignore anything before the first { or [ of lines, such as a timestamp added by docker or kubernetes
+    #[structopt(long)]
+    skip_prefix: bool,
+
+    /// Strip text matching this regex from the start of lines before parsing them. Named groups such as (?P<stream>\w+) are added as fields
+    #[structopt(long)]
+    prefix_regex: Option<Regex>,
+
+    /// Read json objects and arrays written over several lines, such as pretty printed ones, as a single line
+    #[structopt(long, conflicts_with = "follow")]
+    multiline: bool,
+
+    /// Resume reading each file where the previous run with this checkpoint file stopped, and record where this run stops. A last line without a line ending is left for the next run
+    #[structopt(long, parse(from_os_str), conflicts_with = "follow")]
+    checkpoint: Option<PathBuf>,
+
+    /// Keep reading the given files as they grow, like tail -F. Files are reopened when they are rotated
+    #[structopt(short = "F", long, conflicts_with = "merge-by-time")]
+    follow: bool,
+
+    /// Write the lines as they were written, waiting between them for the time between their times, such as to feed recorded logs into a dashboard
+    #[structopt(long)]
+    replay: bool,
+
+    /// How many times faster than they were written lines are replayed with --replay, such as 10 or 0.5
+    #[structopt(long, default_value = "1", parse(try_from_str = replay::parse_speed))]
+    speed: f64,
+
+    /// Write a separator line when more than this time passed between two lines, such as 30s
+    #[structopt(long, parse(try_from_str = duration::parse_arg))]
+    mark_gaps: Option<std::time::Duration>,
+
+    /// Only show lines at or after this time, such as '2024-05-01 14:00' or 2h for two hours ago. Lines without a timestamp are always shown
+    #[structopt(long)]
+    since: Option<timestamp::TimeBound>,
+
+    /// Only show lines before this time, such as '2024-05-01 15:00' or 30m for 30 minutes ago
+    #[structopt(long)]
+    until: Option<timestamp::TimeBound>,
+
+    /// Output format, one of text, json, logfmt, csv, tsv, yaml or table
+    #[structopt(long, default_value = "text")]
+    output: OutputFormat,
+
+    /// Print each line over several lines, one field per line with nested objects indented
+    #[structopt(long)]
+    pretty: bool,
+
+    /// Format lines with a template such as '{time} [{level}] {msg} ({user.id:-?})', overrides --output
+    #[structopt(long)]
+    format: Option<template::Template>,
+
+    /// Print fields in aligned columns, same as --output table
+    #[structopt(long)]
+    table: bool,
+
+    /// Width of each column with --table, longer values are truncated
+    #[structopt(long, default_value = "20")]
+    column_width: usize,
+
+    /// Message keys. The first of these keys in the json line will be printed without its key, after the level and time.
+    #[structopt(short, long, use_delimiter = true, require_delimiter = true)]
+    message: Vec<String>,
+
+    /// Keys to print before all other keys, in the given order. Other keys are sorted alphabetically
+    #[structopt(long, use_delimiter = true, require_delimiter = true)]
+    first: Vec<String>,
+
+    /// Keys of stack traces, such as err, written on their own lines after the log line. Errors with a stack, as bunyan and pino write them, are written as their stack
+    #[structopt(long, use_delimiter = true, require_delimiter = true)]
+    stack: Vec<String>,
+
+    /// Print keys in the order they appear in the json line instead of sorting them alphabetically
+    #[structopt(long)]
+    original_order: bool,
+
+    /// Print nested objects as dotted keys, such as http.method=GET. --extract and --omit match the dotted keys
+    #[structopt(long)]
+    flatten: bool,
+
+    /// Truncate values longer than this many characters in text output, marking them with …
+    #[structopt(long)]
+    max_value_len: Option<usize>,
+
+    /// Print values in full on lines matching --highlight, even with --max-value-len
+    #[structopt(long)]
+    expand_highlighted: bool,
+
+    /// Levels of nested objects and arrays to print, deeper values are collapsed into a summary such as {…3 keys}
+    #[structopt(long)]
+    max_depth: Option<usize>,
+
+    /// Truncate text lines to the terminal width, dropping the fields sorted last first
+    #[structopt(long)]
+    fit: bool,
+
+    /// Terminal width used by --fit, detected when not set
+    #[structopt(long)]
+    width: Option<usize>,
+
+    /// Prefix each line with a colored label, to tell apart streams printed to the same terminal. Lines of several files are labelled with their file name, or the tag given as path:tag, after this label
+    #[structopt(long)]
+    label: Option<String>,
+
+    /// Prefix each line with its line number in the input, with text and table output
+    #[structopt(long)]
+    line_numbers: bool,
+
+    /// Color theme, either default, light, mono or the path of a theme file. Defaults to ~/.config/jaxe/theme.toml if it exists, otherwise to light or default depending on COLORFGBG
+    #[structopt(long)]
+    theme: Option<theme::Theme>,
+
+    /// Colors of levels, such as FATAL=magenta,NOTICE=cyan. Overrides the colors of the theme
+    #[structopt(long, use_delimiter = true, require_delimiter = true)]
+    level_color: Vec<theme::LevelColor>,
+
+    /// Color fields when a filter matches, such as 'status >= 500 => red'. Prefix the color with line: to color the whole line, or with a field name to color that field
+    #[structopt(long, number_of_values = 1)]
+    color_rule: Vec<theme::ColorRule>,
+
+    /// When to use colors: always, never or auto. Auto uses colors when writing to a terminal and NO_COLOR is not set
+    #[structopt(long, default_value = "auto")]
+    color: ColorWhen,
+
+    /// Disable colors, same as --color never
     #[structopt(short, long)]
     no_colors: bool,
+
+    /// Write nothing and exit at the first line that passes the filters. The exit status is 0 if a line passed, 1 otherwise
+    #[structopt(short, long)]
+    quiet: bool,
+
+    /// Only write the number of lines that passed the filters, for each file when there are several
+    #[structopt(short, long, conflicts_with = "quiet")]
+    count: bool,
+
+    /// Instead of the lines that passed the filters, write a table of how many there were of each level
+    #[structopt(long)]
+    stats: bool,
+
+    /// Instead of the lines that passed the filters, write an overview of the input: lines read, lines that could not be parsed, matches, the time they span, lines per level and the most frequent error messages
+    #[structopt(long)]
+    summary: bool,
+
+    /// Instead of the lines that passed the filters, write how many lines there were for each value of these fields, such as service,level, most frequent first
+    #[structopt(long, use_delimiter = true, require_delimiter = true)]
+    group_by: Vec<String>,
+
+    /// Instead of the lines that passed the filters, write statistics of a numeric field, such as duration_ms:sum,avg,p95. Statistics are sum, avg, min, max and percentiles such as p99. Computed for each group of --group-by if given
+    #[structopt(long, number_of_values = 1)]
+    agg: Vec<report::Aggregation>,
+
+    /// Instead of the lines that passed the filters, write the most frequent values of a field with their counts, 10 of them unless a number is given as in http_path:5
+    #[structopt(long, number_of_values = 1)]
+    top: Vec<report::Top>,
+
+    /// Instead of the lines that passed the filters, write every key seen, nested keys being flattened, with how many lines had it and the types of its values
+    #[structopt(long)]
+    keys: bool,
+
+    /// Instead of the lines that passed the filters, write a JSON Schema of them. Keys missing from some lines are optional and values of several types get a list of types
+    #[structopt(long)]
+    infer_schema: bool,
+
+    /// Instead of the lines that passed the filters, write the approximate number of distinct values of each key, nested keys being flattened, to find keys worth grouping by
+    #[structopt(long)]
+    cardinality: bool,
+
+    /// Instead of the lines that passed the filters, write a bar chart of how many there were in each interval of this length, such as 1m, by their time
+    #[structopt(long, parse(try_from_str = duration::parse_arg))]
+    histogram: Option<std::time::Duration>,
+
+    /// Instead of the lines that passed the filters, write each value of this field the first time it is seen
+    #[structopt(long)]
+    distinct: Option<String>,
+
+    /// Write reports such as --stats every interval too, such as 10s, not only at the end of the input. Meant for --follow
+    #[structopt(long, parse(try_from_str = duration::parse_arg))]
+    report_interval: Option<std::time::Duration>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
+    /// Log files to read, one after the other unless --merge-by-time is given. Directories are read recursively and glob patterns such as 'logs/**/*.json' are expanded. Files given as path:tag are labelled with the tag. Reads stdin when there are none, or for -
+    #[structopt(parse(from_os_str))]
+    files: Vec<PathBuf>,
 }
 
-fn level_to_color(level: &str) -> Color {
-    match level {
-        "TRACE" => Color::Magenta,
-        "DEBUG" => Color::Blue,
-        "INFO" => Color::Green,
-        "WARN" => Color::Yellow,
-        "ERROR" => Color::Red,
-        _ => Color::Red
-    }
+/// Where lines go besides the output, as set up by the options
+#[derive(Default)]
+struct Hooks {
+    server: Option<serve::Server>,
+    metrics: Option<metrics::Metrics>,
+    /// Actions for lines that are written, see --exec, --bell and --notify
+    triggers: Vec<exec::Trigger>,
+    reports: report::Reports,
+    distinct: Option<report::Distinct>,
+    rate: Option<rate::Rate>,
+    correlator: Option<correlate::Correlator>,
+    durations: Option<correlate::DurationBetween>,
+    traces: Option<trace::TraceTrees>,
+    replay: Option<replay::Replay>,
+    limiter: Option<rate::Limiter>,
+}
+
+/// Indentation of the lines of a --correlate group
+const CORRELATE_INDENT: usize = 2;
+
+/// Whether lines that pass the filters are only counted or reported, rather
+/// than written
+fn silent(opts: &Opt, hooks: &Hooks) -> bool {
+    opts.quiet || opts.count || ! hooks.reports.is_empty() || hooks.distinct.is_some()
 }
 
+fn write_formatted_line(opts: &Opt, mut line: Value, filters: &mut filters::Filters, highlights: &mut filters::Filters, output: &mut output::Printer, hooks: &mut Hooks) -> Result<bool> {
+    if let Some(preset) = &opts.preset {
+        line = preset.normalize(line);
+    }
 
+    transform::decode(&mut line, &opts.decode);
+    if let Value::Object(obj) = &mut line {
+        transform::parse_nested(obj, &opts.parse_nested);
+    }
+    transform::derive(&mut line, &opts.derive);
+    level::normalize_line(&mut line, opts);
 
+    // Before the filters, so starts are seen even if they are not written
+    if let Some(durations) = &mut hooks.durations {
+        durations.annotate(&mut line, opts)?;
+    }
+
+    if let Some(metrics) = &hooks.metrics {
+        metrics.record(&line, opts)?;
+    }
 
-fn write_formatted_line(opts: &Opt, line: Value, filters: &mut filters::Filters, output: &termcolor::BufferWriter) -> Result<()> {
     if ! filters.apply(&line)? {
-        return Ok(())
+        return Ok(false)
     }
 
-    let mut json = serde_json::from_value::<HashMap<String, Value>>(line)?;
+    if opts.min_level.is_some_and(|min| ! level::at_least(&line, opts, min)) {
+        return Ok(false)
+    }
 
-    for key in opts.omit.0.iter() {
-        log::debug!("Not writing key {} due to --omit", key);
-        json.remove(key);
+    if ! timestamp::in_window(&line, opts) {
+        return Ok(false)
     }
 
-    let mut buffer = output.buffer();
+    if ! filters.is_unique(&line)? {
+        return Ok(false)
+    }
 
-    for key in &opts.level {
-        if let Some(level) = json.get(key).and_then(|s| s.as_str()) {
-            buffer.set_color(ColorSpec::new().set_fg(Some(level_to_color(level))))?;
-            write!(&mut buffer, "{}", level.chars().nth(0).unwrap_or('?'))?;
-            buffer.set_color(ColorSpec::new().set_fg(None))?;
-            write!(&mut buffer, "|")?;
-            json.remove(key);
+    // Before the line goes anywhere, so redacted and hashed values don't
+    // reach reports, commands or clients of --serve
+    if let Value::Object(obj) = &mut line {
+        transform::redact(obj, &opts.redact, &opts.redact_mask);
+        transform::hash(obj, &opts.hash, &opts.hash_salt);
+    }
 
-            break;
-        }
+    // Keys are read in their original order, nested objects are written
+    // with sorted keys like the line itself unless --original-order is given
+    if ! opts.original_order {
+        line.sort_all_objects();
+    }
+
+    hooks.reports.record(&line, opts)?;
+
+    if let Some(value) = hooks.distinct.as_mut().and_then(|distinct| distinct.first_seen(&line)) {
+        let mut buffer = output.buffer();
+        writeln!(&mut buffer, "{}", value)?;
+        output.print(&buffer)?;
+    }
+
+    if silent(opts, hooks) {
+        return Ok(true)
+    }
+
+    if let Some(replay) = &mut hooks.replay {
+        replay.wait(&line, opts);
     }
 
-    for key in &opts.time {
-        if let Some(at) = json.get(key).and_then(|s| s.as_str()) {
-            buffer.set_color(ColorSpec::new().set_fg(None))?;
-            write!(&mut buffer, "{}|", at)?;
-            json.remove(key);
-            break;
+    if let Some(limiter) = &mut hooks.limiter {
+        let (admitted, dropped) = limiter.admit(&line, opts, std::time::Instant::now());
+        if let Some(dropped) = dropped {
+            output.write_separator(&dropped)?;
+        }
+        if ! admitted {
+            return Ok(true)
         }
     }
 
-    let mut keys: Vec<&String> = json.keys().collect();
-    keys.sort();
+    if let Some(metrics) = &hooks.metrics {
+        metrics.line_written();
+    }
+
+    if let Some(rate) = &hooks.rate {
+        rate.record(&line, opts);
+    }
 
-    // TODO: Extract should also support jq style expressions
-    for key in keys {
-        if ! opts.extract.0.is_empty() && ! opts.extract.0.contains(key) {
-            log::debug!("Not writing key {} due to --extract", key);
-            continue;
+    for trigger in &mut hooks.triggers {
+        trigger.fire(&line, opts)?;
+    }
+
+    if let Some(traces) = hooks.traces.as_mut().filter(|_| trace::TraceTrees::is_span(&line)) {
+        if let Some(trace) = traces.add(&line, opts) {
+            write_trace(&trace, output)?;
         }
+        return Ok(true)
+    }
 
-        let value: &Value = json.get(key).unwrap();
-        buffer.set_color(ColorSpec::new().set_fg(Some(Color::Blue)))?;
+    if let Some(correlator) = &mut hooks.correlator {
+        let groups = correlator.add(line, opts);
+        write_groups(opts, groups, filters, highlights, output, hooks)?;
+        return Ok(true)
+    }
 
-        write!(&mut buffer, "{}", key)?;
+    write_line(opts, line, filters, highlights, output, hooks)?;
+    Ok(true)
+}
 
-        if let Some(n) = value.as_str().and_then(|s| s.parse::<u64>().ok()) {
-            buffer.set_color(ColorSpec::new().set_fg(None).set_dimmed(true))?;
-            write!(&mut buffer, "=")?;
-            buffer.set_color(ColorSpec::new().set_fg(Some(Color::Red)).set_dimmed(true))?;
-            write!(&mut buffer, "{} ", n)?;
-        } else if let Some(s) = value.as_str() {
-            buffer.set_color(ColorSpec::new().set_fg(None).set_dimmed(true))?;
-            write!(&mut buffer, "=")?;
-            buffer.set_color(ColorSpec::new().set_fg(None).set_dimmed(false))?;
-            write!(&mut buffer, "{} ", s)?;
-        } else {
-            buffer.set_color(ColorSpec::new().set_fg(None).set_dimmed(true))?;
-            write!(&mut buffer, "=")?;
-            buffer.set_color(ColorSpec::new().set_fg(None))?;
-            write!(&mut buffer, "{} ", value)?;
+/// Writes groups of --correlate, each after a separator with its label and
+/// indented
+fn write_groups(opts: &Opt, groups: Vec<correlate::Group>, filters: &mut filters::Filters, highlights: &mut filters::Filters, output: &mut output::Printer, hooks: &mut Hooks) -> Result<()> {
+    for group in groups {
+        if let Some(label) = &group.label {
+            output.write_separator(label)?;
+            output.set_indent(CORRELATE_INDENT);
         }
+
+        for line in group.lines {
+            write_line(opts, line, filters, highlights, output, hooks)?;
+        }
+
+        output.set_indent(0);
     }
 
-    writeln!(&mut buffer, "")?;
-    output.print(&buffer)?;
+    Ok(())
+}
+
+/// Writes a trace of --trace-tree after a separator with its id
+fn write_trace(trace: &trace::Trace, output: &mut output::Printer) -> Result<()> {
+    output.write_separator(&format!("trace {}", trace.id))?;
+
+    for line in trace.render() {
+        let mut buffer = output.buffer();
+        output.write_prefix(&mut buffer)?;
+        writeln!(&mut buffer, "{}", line)?;
+        output.print(&buffer)?;
+    }
 
     Ok(())
 }
 
+/// Writes a line that passed the filters
+fn write_line(opts: &Opt, line: Value, filters: &mut filters::Filters, highlights: &mut filters::Filters, output: &mut output::Printer, hooks: &mut Hooks) -> Result<()> {
+    output.mark_gap(&line, opts)?;
+
+    let highlighted = ! opts.highlight.is_empty() && highlights.apply(&line)?;
+
+    let mut matches = filters.matches(&line);
+    if highlighted {
+        matches.extend(highlights.matches(&line));
+    }
+
+    // Clients of --serve filter the line with all its fields
+    let served = hooks.server.is_some().then(|| line.clone());
+    let mut json = serde_json::from_value::<Map<String, Value>>(line)?;
 
-fn main() -> io::Result<()> {
+    if opts.flatten {
+        json = transform::flatten(json);
+    }
+
+    json.retain(|key, _| {
+        let omit = opts.omit.matches(key);
+        if omit {
+            log::debug!("Not writing key {} due to --omit", key);
+        }
+        ! omit
+    });
+
+    for path in opts.omit.nested_paths(&json) {
+        log::debug!("Not writing {:?} due to --omit", path);
+        transform::remove_path(&mut json, &path);
+    }
+
+    // Keys extracted whole are kept whole, even with paths into them
+    let extracted: Vec<parser::EPath> = opts.extract.nested_paths(&json).into_iter()
+        .filter(|path| ! opts.extract.matches(&path.segments()[0]))
+        .collect();
+    transform::project_paths(&mut json, &extracted);
+
+    transform::rename(&mut json, &opts.rename);
+
+    if opts.omit_empty {
+        json.retain(|_, value| ! transform::is_empty(value));
+    }
+
+    if let (Some(server), Some(line)) = (&mut hooks.server, served) {
+        server.publish(opts, &line, &json)?;
+    }
+
+    if let Some(ref template) = opts.format {
+        return output::write_template(opts, template, json, output)
+    }
+
+    match opts.output {
+        OutputFormat::Text =>
+            output::write_text(opts, json, highlighted, &matches, output),
+        OutputFormat::Json =>
+            output::write_json(opts, json, output),
+        OutputFormat::Logfmt =>
+            output::write_logfmt(opts, json, output),
+        OutputFormat::Csv =>
+            output::write_delimited(opts, json, ',', output),
+        OutputFormat::Tsv =>
+            output::write_delimited(opts, json, '\t', output),
+        OutputFormat::Yaml =>
+            output::write_yaml(opts, json, output),
+        OutputFormat::Table =>
+            output::write_table(opts, json, highlighted, output),
+    }
+}
+
+
+/// Keeps io errors as they are, so that a closed stdout can be told apart
+fn io_error(err: anyhow::Error) -> io::Error {
+    err.downcast::<io::Error>().unwrap_or_else(io::Error::other)
+}
+
+/// Exits like grep: 0 when a line passed the filters, 1 when none did and 2
+/// on errors
+fn main() {
     pretty_env_logger::init();
 
-    let mut opts = Opt::from_args();
+    let code = match run() {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        // Stdout was closed, such as by head, after a line was written
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => 0,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            2
+        },
+    };
+
+    std::process::exit(code)
+}
+
+/// Writes the lines of the input, returning whether any passed the filters
+fn run() -> io::Result<bool> {
+    let mut opts = match Opt::from_iter_safe(std::env::args_os()) {
+        Ok(opts) => opts,
+        // Usage errors are errors, not a lack of matches
+        Err(err) if err.use_stderr() => {
+            eprintln!("{}", err.message);
+            std::process::exit(2)
+        },
+        Err(err) => err.exit(),
+    };
+
+    if opts.journal.is_some() && opts.preset.is_none() {
+        opts.preset = Some(preset::Preset::Journal);
+    }
+
+    if let Some(preset) = opts.preset.clone() {
+        preset.apply_defaults(&mut opts);
+    }
 
     if opts.time.is_empty() {
         opts.time.push("time".to_owned());
@@ -152,55 +731,443 @@ fn main() -> io::Result<()> {
         opts.level.push("level".to_owned());
     }
 
-    if let Some(e) = std::env::var("JAXE_OMIT").ok()  {
-        opts.omit = MultOpt(e.split(",").map(|s| s.to_owned()).collect());
+    if opts.table {
+        opts.output = OutputFormat::Table;
+    }
+
+    if opts.fit && opts.width.is_none() {
+        opts.width = terminal_size::terminal_size().map(|(Width(w), _)| w as usize)
+            .or_else(|| std::env::var("COLUMNS").ok()?.parse().ok());
+    }
+
+    if let Ok(e) = std::env::var("JAXE_OMIT") {
+        opts.omit = MultOpt::from_keys(split_keys(&e));
     }
 
-    if let Some(e) = std::env::var("JAXE_FILTER").ok()  {
+    if let Ok(e) = std::env::var("JAXE_FILTER") {
         opts.filter = vec![e.to_owned()];
     }
 
-    let mut line_buffer = String::new();
-    let stdin = io::stdin();
-    let mut handle =  stdin.lock();
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|v| ! v.is_empty());
+
+    let choice = match opts.color {
+        _ if opts.no_colors => ColorChoice::Never,
+        ColorWhen::Always => ColorChoice::Always,
+        ColorWhen::Never => ColorChoice::Never,
+        ColorWhen::Auto if no_color_env || ! io::stdout().is_terminal() => ColorChoice::Never,
+        ColorWhen::Auto => ColorChoice::Auto,
+    };
+
+    let bufwtr = BufferWriter::stdout(choice);
+
+    let mut theme = match opts.theme.clone() {
+        Some(theme) => theme,
+        None => theme::Theme::from_config_file().map_err(io::Error::other)?.unwrap_or_else(theme::Theme::detect),
+    };
+
+    for theme::LevelColor(level, color) in &opts.level_color {
+        theme.set_level_color(level, *color);
+    }
+
+    let palette = theme::Palette::detect();
+    theme.adapt(palette);
+    opts.color_rule.iter_mut().for_each(|rule| rule.adapt(palette));
+
+    let mut printer = output::Printer::new(bufwtr, theme);
+    printer.set_label(opts.label.clone());
+    let mut filters = filters::Filters::from_opts(&opts).map_err(io::Error::other)?;
+    let mut hooks = Hooks {
+        server: opts.serve.as_deref().map(serve::Server::start).transpose()?,
+        metrics: opts.metrics_listen.as_deref()
+            .map(|address| metrics::Metrics::start(address, &opts.metric_filter))
+            .transpose()
+            .map_err(io::Error::other)?,
+        triggers: vec![],
+        reports: report::Reports::from_opts(&opts).map_err(io::Error::other)?,
+        distinct: opts.distinct.as_deref().map(report::Distinct::new).transpose().map_err(io::Error::other)?,
+        rate: opts.rate.then(rate::Rate::start),
+        correlator: opts.correlate.as_deref()
+            .map(|field| correlate::Correlator::new(field, opts.correlate_window))
+            .transpose()
+            .map_err(io::Error::other)?,
+        durations: Some(&opts.duration_between).filter(|filters| ! filters.is_empty())
+            .map(|filters| correlate::DurationBetween::new(filters, opts.duration_key.as_deref()))
+            .transpose()
+            .map_err(io::Error::other)?,
+        traces: opts.trace_tree.then(trace::TraceTrees::default),
+        replay: opts.replay.then(|| replay::Replay::new(opts.speed)),
+        limiter: opts.rate_limit.map(rate::Limiter::new),
+    };
+    printer.set_rate(hooks.rate.clone());
+
+    let parse_filter = |filter: &Option<String>| filter.as_deref().map(parser::parse).transpose().map_err(io::Error::other);
+
+    if let Some(command) = opts.exec.take() {
+        hooks.triggers.push(exec::Trigger::new(exec::Action::Run(command), parse_filter(&opts.exec_on)?, opts.exec_interval));
+    }
+    if opts.bell.is_some() {
+        hooks.triggers.push(exec::Trigger::new(exec::Action::Bell, parse_filter(&opts.bell)?, exec::ALERT_INTERVAL));
+    }
+    if opts.notify.is_some() {
+        hooks.triggers.push(exec::Trigger::new(exec::Action::Notify, parse_filter(&opts.notify)?, exec::ALERT_INTERVAL));
+    }
+    let mut highlights = filters::Filters::highlights(&opts).map_err(io::Error::other)?;
+
+    let inputs = match &opts.command {
+        Some(Command::Diff { a, b, .. }) => vec![(a.clone(), None), (b.clone(), None)],
+        _ if opts.files.is_empty() => vec![(PathBuf::from("-"), None)],
+        _ => input::expand(&opts.files, opts.sort_by_mtime)?,
+    };
+    // Lines of several files are labelled with the file they are from, after
+    // --label if it is given
+    let labels: Option<Vec<String>> = (inputs.len() > 1 || inputs.iter().any(|(_, tag)| tag.is_some())).then(|| {
+        input::labels(&inputs).into_iter()
+            .map(|label| opts.label.as_ref().map_or_else(|| label.clone(), |prefix| format!("{}/{}", prefix, label)))
+            .collect()
+    });
+    let files: Vec<PathBuf> = inputs.into_iter().map(|(path, _)| path).collect();
+    let mut line_numbers = vec![0; files.len()];
+    let mut checkpoint = opts.checkpoint.as_deref().map(input::Checkpoint::load).transpose()?;
+    let mut offsets: Vec<u64> = files.iter().map(|path| checkpoint.as_ref().map_or(0, |c| c.offset(path))).collect();
+
+    let events: Box<dyn Iterator<Item = input::Event>> = if let Some(Command::K8s { target, namespace }) = &opts.command {
+        Box::new(input::kubectl_logs(target, namespace.as_deref())?.events())
+    } else if let Some(Command::Diff { a, b, by }) = &opts.command {
+        let diff = diff::Diff::new(&[a.clone(), b.clone()], by.as_deref(), &opts).map_err(io::Error::other)?;
+        let sources = files.iter().map(|path| input::open(path, 0)).collect::<io::Result<Vec<_>>>()?;
+        let opts = &opts;
 
-    let bufwtr = if opts.no_colors {
-        BufferWriter::stdout(ColorChoice::Never)
+        Box::new(input::concat(sources)
+            .filter(move |(index, line)| line.as_ref().map_or(true, |line| diff.is_new(*index, line, opts)))
+            .map(|(index, line)| input::Event::Line(index, line)))
+    } else if let Some(unit) = &opts.journal {
+        Box::new(input::journal(unit.as_deref())?.events())
+    } else if let Some(address) = &opts.listen {
+        Box::new(input::listen(address)?.events())
+    } else if opts.follow && ! opts.files.is_empty() {
+        let followed = files.iter().map(|path| input::Follow::new(path)).collect::<io::Result<Vec<_>>>()?;
+        Box::new(input::Followed::new(followed, opts.idle_marker))
     } else {
-        BufferWriter::stdout(ColorChoice::Auto)
+        let mut sources = files.iter().zip(&offsets).map(|(path, offset)| input::open(path, *offset)).collect::<io::Result<Vec<_>>>()?;
+
+        if opts.multiline {
+            sources = sources.into_iter().map(|source| Box::new(input::Records::new(source)) as input::Source).collect();
+        }
+
+        let lines = if opts.merge_by_time {
+            Box::new(input::MergeByTime::new(sources, &opts))
+        } else {
+            input::concat(sources)
+        };
+
+        Box::new(lines.map(|(index, line)| input::Event::Line(index, line)))
     };
 
-    let mut filters = filters::Filters::from_opts(&opts);
+    // Lines that passed the filters, by file
+    let mut counts = vec![0; files.len()];
+    let mut read_failed = false;
+    let silent = silent(&opts, &hooks);
+
+    for event in events {
+        let (index, line_buffer) = match event {
+            input::Event::Line(index, line) => (index, line),
+            // Groups of live input are written once their window is over,
+            // even when no other line comes
+            input::Event::Tick => {
+                if let Some(correlator) = &mut hooks.correlator {
+                    let groups = correlator.expire(std::time::Instant::now());
+                    write_groups(&opts, groups, &mut filters, &mut highlights, &mut printer, &mut hooks).map_err(io_error)?;
+                }
+                continue
+            },
+            input::Event::Idle(_) if silent => continue,
+            input::Event::Idle(quiet) => {
+                printer.write_separator(&format!("no output for {}", timestamp::span(TimeDelta::from_std(quiet).unwrap_or_default())))?;
+                continue
+            },
+        };
+
+        let mut line_buffer = match line_buffer {
+            Ok(line) => line,
+            // Other files, and the rest of the file after an invalid line,
+            // are still read
+            Err(err) => {
+                eprintln!("Error: {}: {}", files[index].display(), err);
+                read_failed = true;
 
-    loop {
-        match handle.read_line(&mut line_buffer) {
-            Err(_) | Ok(0) => {
-                log::debug!("Finished");
-                break;
+                if let Some(invalid) = err.get_ref().and_then(|err| err.downcast_ref::<input::InvalidLine>()) {
+                    offsets[index] += invalid.len as u64;
+                }
+                continue
             },
-            Ok(c) =>
-                log::debug!("read {} bytes", c)
+        };
+        log::debug!("read {} bytes", line_buffer.len());
+
+        if checkpoint.is_some() {
+            if ! line_buffer.ends_with('\n') {
+                continue
+            }
+
+            offsets[index] += line_buffer.len() as u64;
+        }
+
+        line_numbers[index] += 1;
+        if let Some(metrics) = &hooks.metrics {
+            metrics.line_read();
+        }
+        hooks.reports.line_read();
+        if opts.line_numbers {
+            printer.set_line_number(Some(line_numbers[index]));
         }
 
-        match serde_json::from_str(&line_buffer) {
-            Ok(json) =>
-                write_formatted_line(&opts, json, &mut filters, &bufwtr).unwrap(),
+        if let Some(labels) = &labels {
+            printer.set_label(Some(labels[index].clone()));
+        }
+
+        if let Some(Command::K8s { .. }) = &opts.command {
+            if let Some((source, len)) = input::kubectl_source(&line_buffer) {
+                if opts.label.is_none() {
+                    printer.set_label(Some(source.to_owned()));
+                }
+
+                line_buffer.drain(..len);
+            }
+        }
+
+        let (text, prefix) = input::strip_prefix(&line_buffer, &opts);
+
+        match serde_json::from_str(text) {
+            Ok(Value::Array(records)) =>
+                for record in records {
+                    if record.is_object() {
+                        counts[index] += write_formatted_line(&opts, record, &mut filters, &mut highlights, &mut printer, &mut hooks).map_err(io_error)? as u64;
+                        if opts.quiet && counts[index] > 0 {
+                            break
+                        }
+                    } else {
+                        log::debug!("Skipping array element that is not an object: {}", record);
+                    }
+                },
+            Ok(mut json) => {
+                if let Value::Object(obj) = &mut json {
+                    prefix.into_iter().for_each(|(key, value)| { obj.entry(key).or_insert(value); });
+                }
+
+                counts[index] += write_formatted_line(&opts, json, &mut filters, &mut highlights, &mut printer, &mut hooks).map_err(io_error)? as u64;
+            },
             Err(err) => {
                 log::debug!("Could not parse line as json: {:?}", err);
 
-                if ! opts.no_omit_json {
-                    let mut obuf = bufwtr.buffer();
-                    obuf.set_color(ColorSpec::new().set_fg(Some(Color::White)).set_dimmed(true))?;
+                if let Some(record) = plaintext::parse(text, opts.input) {
+                    counts[index] += write_formatted_line(&opts, Value::Object(record), &mut filters, &mut highlights, &mut printer, &mut hooks).map_err(io_error)? as u64;
+                    if opts.quiet && counts[index] > 0 {
+                        break
+                    }
+                    continue
+                }
+
+                if let Some(metrics) = &hooks.metrics {
+                    metrics.parse_failed();
+                }
+                hooks.reports.parse_failed();
+
+                if ! opts.no_omit_json && ! silent && opts.output == OutputFormat::Text && opts.format.is_none() {
+                    let mut obuf = printer.buffer();
+                    printer.write_prefix(&mut obuf)?;
+                    obuf.set_color(ColorSpec::new().set_fg(printer.theme.non_json).set_dimmed(true))?;
 
                     write!(&mut obuf, "{}", line_buffer)?;
 
-                    bufwtr.print(&mut obuf)?;
+                    printer.print(&obuf)?;
                 }
             }
         }
 
-        line_buffer.clear()
+        if opts.quiet && counts[index] > 0 {
+            break
+        }
     }
 
-    Ok(())
+    log::debug!("Finished");
+
+    if let Some(mut correlator) = hooks.correlator.take() {
+        write_groups(&opts, correlator.flush(), &mut filters, &mut highlights, &mut printer, &mut hooks).map_err(io_error)?;
+    }
+
+    if let Some(mut traces) = hooks.traces.take() {
+        for trace in traces.flush() {
+            write_trace(&trace, &mut printer).map_err(io_error)?;
+        }
+    }
+
+    if let Some(dropped) = hooks.limiter.as_mut().and_then(rate::Limiter::flush) {
+        printer.write_separator(&dropped)?;
+    }
+
+    // Clears the status line of --rate
+    if let Some(rate) = &hooks.rate {
+        rate.suspend();
+    }
+
+    hooks.reports.write(&mut io::stdout().lock())?;
+
+    if opts.count {
+        let mut stdout = io::stdout().lock();
+        for (path, count) in files.iter().zip(&counts) {
+            if files.len() > 1 {
+                write!(stdout, "{}:", path.display())?;
+            }
+            writeln!(stdout, "{}", count)?;
+        }
+    }
+
+    if let Some(ref mut checkpoint) = checkpoint {
+        for (path, offset) in files.iter().zip(offsets) {
+            if path != Path::new("-") {
+                checkpoint.set(path, offset);
+            }
+        }
+
+        checkpoint.save()?;
+    }
+
+    if read_failed {
+        return Err(io::Error::other("Some lines could not be read"))
+    }
+
+    Ok(counts.iter().any(|&count| count > 0))
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{TcpListener, TcpStream};
+    use std::io::Read;
+    use std::time::Duration;
+    use serde_json::json;
+    use super::*;
+
+    /// Writes `line` with `opts` and `hooks`, returning what was printed
+    fn write(opts: &Opt, line: Value, hooks: &mut Hooks) -> String {
+        let mut printer = output::Printer::capture(theme::Theme::default());
+        let mut filters = filters::Filters::from_opts(opts).unwrap();
+        let mut highlights = filters::Filters::highlights(opts).unwrap();
+
+        write_formatted_line(opts, line, &mut filters, &mut highlights, &mut printer, hooks).unwrap();
+        printer.take_captured()
+    }
+
+    #[test]
+    fn redact_reports_test() {
+        let opts = Opt::from_iter(["jaxe", "-l", "level", "-t", "time", "-m", "msg", "--redact", "password", "--top", "password"]);
+        let mut hooks = Hooks { reports: report::Reports::from_opts(&opts).unwrap(), ..Hooks::default() };
+
+        write(&opts, json!({"msg": "login", "password": "hunter2"}), &mut hooks);
+
+        let mut report = vec![];
+        hooks.reports.write(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.contains("***"));
+        assert!(! report.contains("hunter2"));
+
+        let opts = Opt::from_iter(["jaxe", "-l", "level", "-t", "time", "-m", "msg", "--redact", "password", "--distinct", "password"]);
+        let mut hooks = Hooks { distinct: Some(report::Distinct::new("password").unwrap()), ..Hooks::default() };
+
+        assert_eq!(write(&opts, json!({"msg": "login", "password": "hunter2"}), &mut hooks), "***\n");
+    }
+
+    #[test]
+    fn hash_reports_test() {
+        let opts = Opt::from_iter(["jaxe", "-l", "level", "-t", "time", "-m", "msg", "--hash", "user", "--group-by", "user"]);
+        let mut hooks = Hooks { reports: report::Reports::from_opts(&opts).unwrap(), ..Hooks::default() };
+
+        write(&opts, json!({"msg": "login", "user": "alice"}), &mut hooks);
+        write(&opts, json!({"msg": "login", "user": "alice"}), &mut hooks);
+
+        let mut report = vec![];
+        hooks.reports.write(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        // Equal values still fall in one group
+        assert_eq!(report.lines().count(), 2);
+        assert!(! report.contains("alice"));
+    }
+
+    #[test]
+    fn extract_test() {
+        let opts = Opt::from_iter(["jaxe", "-l", "level", "-t", "time", "-m", "msg", "--output", "json", "--extract", "user.id,req"]);
+        let line = json!({"msg": "login", "user": {"id": 1, "name": "alice"}, "req": {"id": 2, "path": "/"}});
+        assert_eq!(write(&opts, line.clone(), &mut Hooks::default()), "{\"req\":{\"id\":2,\"path\":\"/\"},\"user\":{\"id\":1}}\n");
+
+        // Keys extracted whole stay whole
+        let opts = Opt::from_iter(["jaxe", "-l", "level", "-t", "time", "-m", "msg", "--output", "json", "--extract", "user,user.id"]);
+        assert_eq!(write(&opts, line, &mut Hooks::default()), "{\"user\":{\"id\":1,\"name\":\"alice\"}}\n");
+    }
+
+    #[test]
+    fn nested_order_test() {
+        let line = json!({"msg": "hi", "user": {"name": "alice", "id": 1}});
+
+        let opts = Opt::from_iter(["jaxe", "-l", "level", "-t", "time", "-m", "msg", "--output", "json"]);
+        assert_eq!(write(&opts, line.clone(), &mut Hooks::default()), "{\"msg\":\"hi\",\"user\":{\"id\":1,\"name\":\"alice\"}}\n");
+
+        let opts = Opt::from_iter(["jaxe", "-l", "level", "-t", "time", "-m", "msg", "--output", "json", "--original-order"]);
+        assert_eq!(write(&opts, line, &mut Hooks::default()), "{\"msg\":\"hi\",\"user\":{\"name\":\"alice\",\"id\":1}}\n");
+    }
+
+    #[test]
+    fn redact_exec_test() {
+        let path = std::env::temp_dir().join(format!("jaxe-redact-{}", std::process::id()));
+        let opts = Opt::from_iter(["jaxe", "-l", "level", "-t", "time", "-m", "msg", "--redact", "password"]);
+        let command = format!("sh -c 'echo \"$0\" > {}' {{password}}", path.display()).parse().unwrap();
+        let mut hooks = Hooks {
+            triggers: vec![exec::Trigger::new(exec::Action::Run(command), None, Duration::ZERO)],
+            ..Hooks::default()
+        };
+
+        write(&opts, json!({"msg": "login", "password": "hunter2"}), &mut hooks);
+
+        // Commands are not waited for
+        let mut written = String::new();
+        for _ in 0..100 {
+            written = std::fs::read_to_string(&path).unwrap_or_default();
+            if written.ends_with('\n') {
+                break
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(written, "***\n");
+    }
+
+    #[test]
+    fn redact_serve_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let opts = Opt::from_iter(["jaxe", "-l", "level", "-t", "time", "-m", "msg", "--redact", "password"]);
+        let mut hooks = Hooks { server: Some(serve::Server::serve(listener)), ..Hooks::default() };
+
+        // Clients can't filter on the redacted value either
+        let mut clients = [r#"password == "hunter2""#, r#"password == "***""#].map(|filter| {
+            let mut client = TcpStream::connect(address).unwrap();
+            let query: String = percent_encoding::utf8_percent_encode(filter, percent_encoding::NON_ALPHANUMERIC).collect();
+            write!(client, "GET /events?format=json&filter={} HTTP/1.1\r\n\r\n", query).unwrap();
+            client
+        });
+        while hooks.server.as_ref().unwrap().clients() < clients.len() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        write(&opts, json!({"msg": "login", "password": "hunter2"}), &mut hooks);
+
+        // Reads what was sent until the connection is quiet
+        let mut responses = clients.iter_mut().map(|client| {
+            let mut response = vec![];
+            client.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+            let _ = client.read_to_end(&mut response);
+            String::from_utf8(response).unwrap()
+        });
+
+        assert!(responses.next().unwrap().ends_with("\r\n\r\n"));
+        assert!(responses.next().unwrap().ends_with("\r\n\r\ndata: {\"msg\":\"login\",\"password\":\"***\"}\n\n"));
+    }
 }
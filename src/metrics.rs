@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::Opt;
+use crate::cli::KeyValue;
+use crate::parser::{self, EvalContext, Exp};
+use crate::serve;
+
+/// Counters of the lines read, served in the Prometheus text format on
+/// `GET /metrics`, see --metrics-listen
+pub (crate) struct Metrics {
+    counters: Arc<Mutex<Counters>>,
+    /// Filters of --metric-filter, with their names
+    filters: Vec<(String, Exp)>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    lines_read: u64,
+    parse_failures: u64,
+    lines_written: u64,
+    levels: BTreeMap<String, u64>,
+    /// Matches of each filter, in the order of `Metrics::filters`
+    matches: Vec<u64>,
+}
+
+impl Metrics {
+    /// Serves the metrics on `address`. `:PORT` listens on localhost, see
+    /// `serve::bind`.
+    pub (crate) fn start(address: &str, filters: &[KeyValue]) -> Result<Metrics> {
+        let filters = filters.iter()
+            .map(|KeyValue(name, filter)| Ok((name.clone(), parser::parse(filter)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let listener = serve::bind(address)?;
+
+        let counters = Arc::new(Mutex::new(Counters { matches: vec![0; filters.len()], ..Counters::default() }));
+        let names: Vec<String> = filters.iter().map(|(name, _)| name.clone()).collect();
+
+        let served = counters.clone();
+        let names: Arc<[String]> = names.into();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (served, names) = (served.clone(), names.clone());
+
+                // Each in its own thread, so a slow client doesn't hold up
+                // the next scrapes
+                thread::spawn(move || {
+                    let scraped = stream.map_err(anyhow::Error::from)
+                        .and_then(|stream| scrape(stream, &served, &names));
+
+                    if let Err(err) = scraped {
+                        log::debug!("Could not serve metrics: {}", err);
+                    }
+                });
+            }
+        });
+
+        Ok(Metrics { counters, filters })
+    }
+
+    fn update(&self, update: impl FnOnce(&mut Counters)) {
+        if let Ok(mut counters) = self.counters.lock() {
+            update(&mut counters);
+        }
+    }
+
+    pub (crate) fn line_read(&self) {
+        self.update(|counters| counters.lines_read += 1)
+    }
+
+    pub (crate) fn parse_failed(&self) {
+        self.update(|counters| counters.parse_failures += 1)
+    }
+
+    pub (crate) fn line_written(&self) {
+        self.update(|counters| counters.lines_written += 1)
+    }
+
+    /// Counts the level of `line` and the filters it matches
+    pub (crate) fn record(&self, line: &Value, opts: &Opt) -> Result<()> {
+        let ctx = EvalContext { epsilon: opts.epsilon };
+        let matched = self.filters.iter()
+            .map(|(_, filter)| parser::filter_with(filter, line, &ctx))
+            .collect::<Result<Vec<bool>>>()?;
+
+        let level = opts.level.iter().find_map(|key| line.get(key)?.as_str());
+
+        self.update(|counters| {
+            if let Some(level) = level {
+                *counters.levels.entry(level.to_owned()).or_default() += 1;
+            }
+
+            for (count, matched) in counters.matches.iter_mut().zip(matched) {
+                *count += matched as u64;
+            }
+        });
+
+        Ok(())
+    }
+}
+
+fn scrape(mut stream: TcpStream, counters: &Mutex<Counters>, names: &[String]) -> Result<()> {
+    match serve::read_request(&stream)?.as_deref() {
+        Some("/metrics") => (),
+        _ => return serve::respond(&mut stream, "404 Not Found", "Metrics are served on GET /metrics"),
+    }
+
+    let text = match counters.lock() {
+        Ok(counters) => render(&counters, names),
+        Err(_) => return serve::respond(&mut stream, "500 Internal Server Error", "Metrics were poisoned"),
+    };
+
+    serve::respond(&mut stream, "200 OK", &text)
+}
+
+/// The counters in the Prometheus text format
+fn render(counters: &Counters, names: &[String]) -> String {
+    let mut text = String::new();
+
+    let mut counter = |name: &str, help: &str, values: Vec<(Option<(&str, &str)>, u64)>| {
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} counter", name);
+
+        for (label, value) in values {
+            match label {
+                Some((key, label)) => { let _ = writeln!(text, "{}{{{}=\"{}\"}} {}", name, key, escape(label), value); },
+                None => { let _ = writeln!(text, "{} {}", name, value); },
+            }
+        }
+    };
+
+    counter("jaxe_lines_read_total", "Lines read from the input", vec![(None, counters.lines_read)]);
+    counter("jaxe_parse_failures_total", "Lines that are neither json nor another known format", vec![(None, counters.parse_failures)]);
+    counter("jaxe_lines_written_total", "Lines that passed the filters", vec![(None, counters.lines_written)]);
+    counter("jaxe_level_lines_total", "Lines read with each level",
+        counters.levels.iter().map(|(level, count)| (Some(("level", level.as_str())), *count)).collect());
+    counter("jaxe_filter_matches_total", "Lines read matching each --metric-filter",
+        names.iter().zip(&counters.matches).map(|(name, count)| (Some(("filter", name.as_str())), *count)).collect());
+
+    text
+}
+
+/// Escapes a label value of the Prometheus text format
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use structopt::StructOpt;
+    use super::*;
+
+    #[test]
+    fn render_test() {
+        let opts = Opt::from_iter(["jaxe", "-l", "level"]);
+        let metrics = Metrics::start("127.0.0.1:0", &[KeyValue("errors".into(), "status >= 500".into())]).unwrap();
+
+        metrics.line_read();
+        metrics.record(&json!({"level": "ERROR", "status": 503}), &opts).unwrap();
+        metrics.line_read();
+        metrics.record(&json!({"level": "INFO", "status": 200}), &opts).unwrap();
+        metrics.line_written();
+        metrics.line_read();
+        metrics.parse_failed();
+
+        let text = render(&metrics.counters.lock().unwrap(), &["errors".to_owned()]);
+
+        assert!(text.contains("# TYPE jaxe_lines_read_total counter\njaxe_lines_read_total 3\n"));
+        assert!(text.contains("\njaxe_parse_failures_total 1\n"));
+        assert!(text.contains("\njaxe_lines_written_total 1\n"));
+        assert!(text.contains("\njaxe_level_lines_total{level=\"ERROR\"} 1\njaxe_level_lines_total{level=\"INFO\"} 1\n"));
+        assert!(text.contains("\njaxe_filter_matches_total{filter=\"errors\"} 1\n"));
+    }
+
+    #[test]
+    fn escape_test() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
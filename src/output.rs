@@ -0,0 +1,1009 @@
+use std::borrow::Cow;
+use std::io::Write;
+use std::str::FromStr;
+use termcolor::{Buffer, BufferWriter, WriteColor, Color, ColorChoice, ColorSpec};
+use serde_json::{Map, Value};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+use anyhow::{anyhow, Result};
+
+use crate::Opt;
+use crate::cli;
+use crate::parser;
+use crate::parser::Match;
+use crate::rate::Rate;
+use crate::template::Template;
+use crate::timestamp;
+use chrono::{DateTime, FixedOffset};
+use crate::theme::{self, RuleColors, Theme};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub (crate) enum OutputFormat {
+    /// Human readable, colored `key=value` line
+    Text,
+    /// The json of the line, projected with --extract/--omit
+    Json,
+    /// `key=value` pairs quoted according to logfmt
+    Logfmt,
+    /// Comma separated values, with a header row
+    Csv,
+    /// Tab separated values, with a header row
+    Tsv,
+    /// One yaml document per line, projected with --extract/--omit
+    Yaml,
+    /// Fields in aligned, fixed width columns
+    Table,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "logfmt" => Ok(OutputFormat::Logfmt),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            "yaml" => Ok(OutputFormat::Yaml),
+            "table" => Ok(OutputFormat::Table),
+            _ => Err(anyhow!("Unknown output format: {}", src)),
+        }
+    }
+}
+
+/// Writes formatted lines to stdout, keeping any state needed across lines
+pub (crate) struct Printer {
+    writer: BufferWriter,
+    /// Columns of csv/tsv/table output, set once the header row is written
+    columns: Option<Vec<String>>,
+    /// Written before each line, to tell where it came from
+    prefix: LinePrefix,
+    pub (crate) theme: Theme,
+    /// Time of the last line written, see --delta
+    last_time: Option<DateTime<FixedOffset>>,
+    /// Time of the last line seen by --mark-gaps
+    gap_time: Option<DateTime<FixedOffset>>,
+    /// Lines printed by a printer made with `capture`, instead of being
+    /// written to stdout
+    captured: Option<Vec<u8>>,
+    /// Status line of --rate, cleared before printing
+    rate: Option<Rate>,
+}
+
+#[derive(Debug, Default)]
+struct LinePrefix {
+    label: Option<String>,
+    line_number: Option<usize>,
+    /// Spaces before the label, see --correlate
+    indent: usize,
+}
+
+impl Printer {
+    pub (crate) fn new(writer: BufferWriter, theme: Theme) -> Printer {
+        Printer { writer, columns: None, prefix: LinePrefix::default(), theme, last_time: None, gap_time: None, captured: None, rate: None }
+    }
+
+    /// A printer keeping the lines it prints, without colors, see
+    /// `take_captured`
+    pub (crate) fn capture(theme: Theme) -> Printer {
+        Printer { captured: Some(vec![]), ..Printer::new(BufferWriter::stdout(ColorChoice::Never), theme) }
+    }
+
+    /// The lines printed since the last call
+    pub (crate) fn take_captured(&mut self) -> String {
+        let captured = self.captured.as_mut().map(std::mem::take).unwrap_or_default();
+        String::from_utf8_lossy(&captured).into_owned()
+    }
+
+    /// Sets the label written before every line, such as its source
+    pub (crate) fn set_label(&mut self, label: Option<String>) {
+        self.prefix.label = label;
+    }
+
+    pub (crate) fn set_rate(&mut self, rate: Option<Rate>) {
+        self.rate = rate;
+    }
+
+    /// Sets the number of spaces written before lines
+    pub (crate) fn set_indent(&mut self, indent: usize) {
+        self.prefix.indent = indent;
+    }
+
+    /// Sets the input line number written before the next line
+    pub (crate) fn set_line_number(&mut self, line_number: Option<usize>) {
+        self.prefix.line_number = line_number;
+    }
+
+    /// Writes the label and line number of the current line, if any, to
+    /// `buffer`. Returns the number of columns written. Only human readable
+    /// output gets it, so json, logfmt, csv, tsv and yaml lines stay valid.
+    pub (crate) fn write_prefix(&self, buffer: &mut Buffer) -> std::io::Result<usize> {
+        write_prefix(buffer, &self.prefix)
+    }
+
+    /// Writes a separator before `line` when its time is more than
+    /// --mark-gaps after the previous line. Only human readable output
+    /// formats get separators.
+    pub (crate) fn mark_gap(&mut self, line: &Value, opts: &Opt) -> Result<()> {
+        let Some(threshold) = opts.mark_gaps else { return Ok(()) };
+
+        if ! matches!(opts.output, OutputFormat::Text | OutputFormat::Table) {
+            return Ok(())
+        }
+
+        let Some(time) = timestamp::of_line(line, opts) else { return Ok(()) };
+        let last = self.gap_time.replace(time);
+
+        let gap = match last {
+            Some(last) => time.signed_duration_since(last),
+            None => return Ok(()),
+        };
+
+        if gap.to_std().is_ok_and(|gap| gap > threshold) {
+            self.write_separator(&format!("{} gap", timestamp::span(gap)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a dimmed separator line with `text` in the middle
+    pub (crate) fn write_separator(&mut self, text: &str) -> std::io::Result<()> {
+        let mut buffer = self.buffer();
+        self.write_prefix(&mut buffer)?;
+        buffer.set_color(ColorSpec::new().set_fg(self.theme.non_json).set_dimmed(true))?;
+        write!(&mut buffer, "──── {} ────", text)?;
+        buffer.reset()?;
+        writeln!(&mut buffer)?;
+        self.print(&buffer)?;
+
+        Ok(())
+    }
+
+    pub (crate) fn buffer(&self) -> Buffer {
+        self.writer.buffer()
+    }
+
+    pub (crate) fn print(&mut self, buffer: &Buffer) -> std::io::Result<()> {
+        let _suspended = self.rate.as_ref().and_then(Rate::suspend);
+
+        match &mut self.captured {
+            Some(captured) => {
+                captured.extend_from_slice(buffer.as_slice());
+                Ok(())
+            },
+            None => self.writer.print(buffer),
+        }
+    }
+}
+
+/// A color for `label`, the same label always gets the same color
+fn label_color(label: &str) -> Color {
+    const COLORS: [Color; 5] = [Color::Cyan, Color::Magenta, Color::Green, Color::Yellow, Color::Blue];
+
+    let hash = label.bytes().fold(0usize, |h, b| h.wrapping_mul(31).wrapping_add(b as usize));
+    COLORS[hash % COLORS.len()]
+}
+
+fn write_prefix(buffer: &mut Buffer, prefix: &LinePrefix) -> std::io::Result<usize> {
+    write!(buffer, "{:1$}", "", prefix.indent)?;
+
+    if let Some(label) = &prefix.label {
+        buffer.set_color(ColorSpec::new().set_fg(Some(label_color(label))))?;
+        write!(buffer, "[{}] ", label)?;
+    }
+
+    if let Some(n) = prefix.line_number {
+        buffer.set_color(ColorSpec::new().set_dimmed(true))?;
+        write!(buffer, "{}: ", n)?;
+    }
+
+    buffer.reset()?;
+
+    Ok(prefix_width(prefix))
+}
+
+/// The number of columns `write_prefix` writes
+fn prefix_width(prefix: &LinePrefix) -> usize {
+    prefix.indent
+        + prefix.label.as_ref().map_or(0, |label| text_width(label) + 3)
+        + prefix.line_number.map_or(0, |n| format!("{}: ", n).len())
+}
+
+fn color_spec(highlighted: bool) -> ColorSpec {
+    let mut spec = ColorSpec::new();
+    spec.set_bold(highlighted).set_underline(highlighted);
+    spec
+}
+
+/// Writes `text` using `spec`, with any occurrence of `needles` highlighted
+fn write_with_matches(buffer: &mut Buffer, text: &str, needles: &[&str], spec: &ColorSpec, theme: &Theme) -> Result<()> {
+    let mut rest = text;
+
+    loop {
+        let next = needles.iter()
+            .filter(|n| ! n.is_empty())
+            .filter_map(|n| rest.find(n).map(|i| (i, n.len())))
+            .min();
+
+        buffer.set_color(spec)?;
+
+        match next {
+            Some((start, len)) => {
+                write!(buffer, "{}", &rest[..start])?;
+                let mut matched = spec.clone();
+                matched.set_fg(theme.match_fg).set_bg(theme.match_bg).set_dimmed(false);
+                if theme.match_bg.is_none() {
+                    matched.set_bold(true).set_underline(true);
+                }
+                buffer.set_color(&matched)?;
+                write!(buffer, "{}", &rest[start..start + len])?;
+                rest = &rest[start + len..];
+            },
+            None => {
+                write!(buffer, "{}", rest)?;
+                return Ok(())
+            }
+        }
+    }
+}
+
+/// Substrings to highlight in the value at `path`
+fn needles_for<'a>(matches: &'a [Match], path: &[&String]) -> Vec<&'a str> {
+    matches.iter()
+        .filter(|m| m.path.len() >= path.len() && m.path.iter().zip(path).all(|(a, b)| a == *b))
+        .map(|m| m.substring.as_str())
+        .collect()
+}
+
+/// Renders `value` as json, objects and arrays nested deeper than `depth`
+/// levels are collapsed into a summary such as `{…3 keys}`
+fn render_json(value: &Value, depth: Option<usize>) -> String {
+    let nested = depth.map(|d| d.saturating_sub(1));
+
+    match value {
+        Value::Object(obj) if depth == Some(0) && ! obj.is_empty() =>
+            format!("{{…{} key{}}}", obj.len(), if obj.len() == 1 { "" } else { "s" }),
+        Value::Array(arr) if depth == Some(0) && ! arr.is_empty() =>
+            format!("[…{} item{}]", arr.len(), if arr.len() == 1 { "" } else { "s" }),
+        Value::Object(obj) if depth.is_some() => {
+            let fields: Vec<String> = obj.iter()
+                .map(|(k, v)| format!("{}:{}", Value::String(k.clone()), render_json(v, nested)))
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        },
+        Value::Array(arr) if depth.is_some() => {
+            let items: Vec<String> = arr.iter().map(|v| render_json(v, nested)).collect();
+            format!("[{}]", items.join(","))
+        },
+        other =>
+            other.to_string(),
+    }
+}
+
+/// Shortens `text` to at most `max` columns, ending it with `…` if it was
+/// cut
+fn truncate(text: &str, max: usize) -> Cow<'_, str> {
+    if text_width(text) <= max {
+        return Cow::Borrowed(text)
+    }
+
+    let mut truncated = String::new();
+    let mut width = 0;
+
+    for c in text.chars() {
+        width += c.width().unwrap_or(0);
+        if width + 1 > max {
+            break
+        }
+        truncated.push(c);
+    }
+
+    if max > 0 {
+        truncated.push('…');
+    }
+
+    Cow::Owned(truncated)
+}
+
+/// Number of terminal columns used by `text`, wide characters such as CJK
+/// or emoji take two columns and combining characters none
+fn text_width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+/// How the values of a line are written in text output
+#[derive(Clone, Copy)]
+struct Style<'a> {
+    theme: &'a Theme,
+    /// Levels of nested values to expand, see --max-depth
+    depth: Option<usize>,
+    /// Values longer than this are truncated, see --max-value-len
+    max_len: Option<usize>,
+    /// Whether the line matched --highlight
+    highlighted: bool,
+    /// Overrides the color of values, see --color-rule
+    color: Option<Color>,
+    /// Whether the field is written dimmed, see --dim
+    dimmed: bool,
+}
+
+/// The text written for `value` by `write_value`
+fn value_text(value: &Value, depth: Option<usize>, max_len: Option<usize>) -> String {
+    let text = match value {
+        Value::String(s) => s.parse::<u64>().map(|n| n.to_string()).unwrap_or_else(|_| s.clone()),
+        other => render_json(other, depth),
+    };
+
+    match max_len {
+        Some(max) => truncate(&text, max).into_owned(),
+        None => text,
+    }
+}
+
+fn write_value(buffer: &mut Buffer, value: &Value, style: Style, needles: &[&str]) -> Result<()> {
+    let text = value_text(value, style.depth, style.max_len);
+    let mut spec = color_spec(style.highlighted);
+
+    if value.as_str().is_some_and(|s| s.parse::<u64>().is_ok()) {
+        spec.set_fg(style.theme.number).set_dimmed(true);
+    } else if value.is_string() {
+        spec.set_dimmed(false);
+    }
+
+    if style.color.is_some() {
+        spec.set_fg(style.color).set_dimmed(false);
+    }
+
+    if style.dimmed {
+        spec.set_dimmed(true);
+    }
+
+    write_with_matches(buffer, &text, needles, &spec, style.theme)
+}
+
+/// Writes a `key: value` line indented by the depth of `path`, nested
+/// objects are written one field per line, indented one level deeper,
+/// until `depth` levels have been expanded
+fn write_pretty_field(buffer: &mut Buffer, path: &[&String], value: &Value, style: Style, matches: &[Match]) -> Result<()> {
+    let key = path.last().unwrap();
+    let highlighted = style.highlighted;
+
+    buffer.set_color(color_spec(highlighted).set_fg(None))?;
+    write!(buffer, "{:indent$}", "", indent = path.len() * 2)?;
+    buffer.set_color(color_spec(highlighted).set_fg(style.theme.key).set_dimmed(style.dimmed))?;
+    write!(buffer, "{}", key)?;
+    buffer.set_color(color_spec(highlighted).set_fg(None).set_dimmed(true))?;
+    write!(buffer, ":")?;
+
+    match value {
+        Value::Object(obj) if ! obj.is_empty() && style.depth != Some(0) => {
+            writeln!(buffer)?;
+
+            for (k, v) in obj {
+                let mut nested = path.to_vec();
+                nested.push(k);
+                write_pretty_field(buffer, &nested, v, Style { depth: style.depth.map(|d| d - 1), ..style }, matches)?;
+            }
+        },
+        _ => {
+            write!(buffer, " ")?;
+            write_value(buffer, value, style, &needles_for(matches, path))?;
+            writeln!(buffer)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub (crate) fn write_text(opts: &Opt, mut json: Map<String, Value>, highlighted: bool, matches: &[Match], output: &mut Printer) -> Result<()> {
+    let mut buffer = output.buffer();
+    let mut room = if opts.fit && ! opts.pretty { opts.width } else { None };
+
+    let prefix_width = output.write_prefix(&mut buffer)?;
+    room = room.map(|r| r.saturating_sub(prefix_width));
+
+    let colors = if opts.color_rule.is_empty() {
+        RuleColors::default()
+    } else {
+        let ctx = parser::EvalContext { epsilon: opts.epsilon };
+        theme::rule_colors(&opts.color_rule, &Value::Object(json.clone()), &ctx)?
+    };
+
+    for key in &opts.level {
+        if let Some(level) = json.get(key).and_then(|s| s.as_str()) {
+            buffer.set_color(color_spec(highlighted).set_fg(colors.field(key).or(output.theme.level_color(level))))?;
+            write!(&mut buffer, "{}", level.chars().next().unwrap_or('?'))?;
+            buffer.set_color(color_spec(highlighted).set_fg(None))?;
+            write!(&mut buffer, "|")?;
+            room = room.map(|r| r.saturating_sub(2));
+            json.shift_remove(key);
+
+            break;
+        }
+    }
+
+    for key in &opts.time {
+        if let Some(at) = json.get(key).and_then(|t| timestamp::display(t, opts)) {
+            buffer.set_color(color_spec(highlighted).set_fg(colors.field(key)))?;
+            write!(&mut buffer, "{}|", at)?;
+            room = room.map(|r| r.saturating_sub(text_width(&at) + 1));
+
+            if opts.delta {
+                let time = timestamp::parse_value(&json[key]);
+
+                if let (Some(last), Some(time)) = (output.last_time, time) {
+                    let delta = timestamp::delta(time.signed_duration_since(last));
+                    buffer.set_color(color_spec(highlighted).set_fg(None).set_dimmed(true))?;
+                    write!(&mut buffer, "{}", delta)?;
+                    buffer.set_color(color_spec(highlighted).set_fg(None))?;
+                    write!(&mut buffer, "|")?;
+                    room = room.map(|r| r.saturating_sub(text_width(&delta) + 1));
+                }
+
+                output.last_time = time.or(output.last_time);
+            }
+
+            json.shift_remove(key);
+            break;
+        }
+    }
+
+    if let Some((key, message)) = take_first(&mut json, &opts.message) {
+        let needles = needles_for(matches, &[&key]);
+        let mut text = match message {
+            Value::String(s) => s,
+            other => other.to_string(),
+        };
+
+        if let Some(left) = room {
+            text = truncate(&text, left.saturating_sub(1)).into_owned();
+            room = Some(left.saturating_sub(text_width(&text) + 1));
+        }
+
+        write_with_matches(&mut buffer, &text, &needles, color_spec(highlighted).set_fg(colors.field(&key)).set_bold(true), &output.theme)?;
+        write!(&mut buffer, " ")?;
+    }
+
+    let stack = take_stack(&mut json, &opts.stack);
+
+    if opts.pretty && ! buffer.is_empty() {
+        writeln!(&mut buffer)?;
+    }
+
+    let style = Style {
+        theme: &output.theme,
+        depth: opts.max_depth,
+        max_len: if highlighted && opts.expand_highlighted { None } else { opts.max_value_len },
+        highlighted,
+        color: None,
+        dimmed: false,
+    };
+
+    let mut keys: Vec<&String> = json.keys().collect();
+    sort_keys(opts, &mut keys);
+
+    for key in keys {
+        if ! extracted(opts, key) {
+            log::debug!("Not writing key {} due to --extract", key);
+            continue;
+        }
+
+        let value: &Value = json.get(key).unwrap();
+        let field_style = Style { color: colors.field(key), dimmed: opts.dim.matches(key), ..style };
+
+        if opts.pretty {
+            write_pretty_field(&mut buffer, &[key], value, field_style, matches)?;
+        } else {
+            let width = text_width(key) + 1 + text_width(&value_text(value, style.depth, style.max_len)) + 1;
+
+            // Keeps one column for the … marking dropped fields
+            if room.is_some_and(|left| left <= width) {
+                log::debug!("Dropping key {} and following keys due to --fit", key);
+                buffer.set_color(color_spec(highlighted).set_fg(None).set_dimmed(true))?;
+                write!(&mut buffer, "…")?;
+                break;
+            }
+            room = room.map(|left| left - width);
+
+            buffer.set_color(color_spec(highlighted).set_fg(colors.line.or(output.theme.key)).set_dimmed(field_style.dimmed))?;
+            write!(&mut buffer, "{}", key)?;
+            buffer.set_color(color_spec(highlighted).set_fg(None).set_dimmed(true))?;
+            write!(&mut buffer, "=")?;
+            write_value(&mut buffer, value, field_style, &needles_for(matches, &[key]))?;
+            write!(&mut buffer, " ")?;
+        }
+    }
+
+    if ! opts.pretty {
+        writeln!(&mut buffer)?;
+    }
+
+    if let Some(stack) = stack {
+        buffer.set_color(color_spec(highlighted).set_fg(None).set_dimmed(true))?;
+        for line in stack.lines() {
+            writeln!(&mut buffer, "    {}", line.trim_end())?;
+        }
+        buffer.set_color(color_spec(highlighted).set_fg(None))?;
+    }
+
+    output.print(&buffer)?;
+
+    Ok(())
+}
+
+
+
+fn project(opts: &Opt, json: Map<String, Value>) -> Map<String, Value> {
+    let mut projected: Vec<(String, Value)> = json.into_iter()
+        .filter(|(key, _)| extracted(opts, key))
+        .collect();
+
+    if ! opts.original_order {
+        projected.sort_by(|(a, _), (b, _)| a.cmp(b));
+    }
+
+    projected.into_iter().collect()
+}
+
+pub (crate) fn write_json(opts: &Opt, json: Map<String, Value>, output: &mut Printer) -> Result<()> {
+    let projected = project(opts, json);
+
+    let mut buffer = output.buffer();
+    serde_json::to_writer(&mut buffer, &projected)?;
+    writeln!(&mut buffer)?;
+    output.print(&buffer)?;
+
+    Ok(())
+}
+
+/// Sorts keys alphabetically, or keeps them in their original order with
+/// --original-order, except keys given to --first which come first, in
+/// the order they were given
+fn sort_keys<K: AsRef<str>>(opts: &Opt, keys: &mut [K]) {
+    let rank = |key: &K| opts.first.iter().position(|f| f == key.as_ref()).unwrap_or(usize::MAX);
+
+    if opts.original_order {
+        keys.sort_by_key(rank);
+    } else {
+        keys.sort_by(|a, b| rank(a).cmp(&rank(b)).then_with(|| a.as_ref().cmp(b.as_ref())));
+    }
+}
+
+/// Whether `key` is selected by --extract, keys renamed with --rename are
+/// selected when their original key or path is
+fn extracted(opts: &Opt, key: &str) -> bool {
+    opts.extract.0.is_empty()
+        || opts.extract.selects(key)
+        || opts.rename.iter().any(|r| r.1 == key && opts.extract.selects(&r.0))
+}
+
+/// Removes and returns the first of `keys` present in `json`
+fn take_first(json: &mut Map<String, Value>, keys: &[String]) -> Option<(String, Value)> {
+    keys.iter().find_map(|k| json.shift_remove_entry(k))
+}
+
+/// Removes and returns the first stack trace of `keys`, a string or the
+/// `stack` of an error object. The error name, type and message are left
+/// out as the stack starts with them, other fields of the error are kept.
+fn take_stack(json: &mut Map<String, Value>, keys: &[String]) -> Option<String> {
+    keys.iter().find_map(|key| match json.get_mut(key)? {
+        Value::String(stack) if stack.contains('\n') => {
+            let stack = std::mem::take(stack);
+            json.shift_remove(key);
+            Some(stack)
+        },
+        Value::Object(error) if error.get("stack").is_some_and(Value::is_string) => {
+            let stack = error.shift_remove("stack")?.as_str()?.to_owned();
+            error.shift_remove("message");
+            error.shift_remove("name");
+            error.shift_remove("type");
+
+            if error.is_empty() {
+                json.shift_remove(key);
+            }
+            Some(stack)
+        },
+        _ => None,
+    })
+}
+
+fn logfmt_value(value: &Value) -> String {
+    let raw = match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+
+    let needs_quotes = raw.is_empty() || raw.chars().any(|c| c == ' ' || c == '=' || c == '"' || c == '\\' || c.is_control());
+
+    if needs_quotes {
+        logfmt_quote(&raw)
+    } else {
+        raw
+    }
+}
+
+/// Replaces the characters logfmt keys can't have, spaces, `=`, quotes and
+/// control characters, with `_`, as keys can't be quoted
+fn logfmt_key(key: &str) -> String {
+    if key.is_empty() {
+        return "_".to_owned()
+    }
+
+    key.chars().map(|c| if c == ' ' || c == '=' || c == '"' || c.is_control() { '_' } else { c }).collect()
+}
+
+/// Quotes `raw`, escaping quotes, backslashes and control characters but
+/// keeping any other character as is
+fn logfmt_quote(raw: &str) -> String {
+    let mut quoted = String::with_capacity(raw.len() + 2);
+    quoted.push('"');
+
+    for c in raw.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+pub (crate) fn write_logfmt(opts: &Opt, mut json: Map<String, Value>, output: &mut Printer) -> Result<()> {
+    let mut pairs = vec![];
+
+    pairs.extend(take_first(&mut json, &opts.level));
+    pairs.extend(take_first(&mut json, &opts.time));
+    pairs.extend(take_first(&mut json, &opts.message));
+
+    let mut keys: Vec<String> = json.keys()
+        .filter(|key| extracted(opts, key))
+        .cloned()
+        .collect();
+    sort_keys(opts, &mut keys);
+
+    for key in keys {
+        let value = json.shift_remove(&key).unwrap();
+        pairs.push((key, value));
+    }
+
+    let line: Vec<String> = pairs.iter()
+        .map(|(key, value)| format!("{}={}", logfmt_key(key), logfmt_value(value)))
+        .collect();
+
+    let mut buffer = output.buffer();
+    writeln!(&mut buffer, "{}", line.join(" "))?;
+    output.print(&buffer)?;
+
+    Ok(())
+}
+
+pub (crate) fn write_yaml(opts: &Opt, json: Map<String, Value>, output: &mut Printer) -> Result<()> {
+    let projected = project(opts, json);
+
+    let mut buffer = output.buffer();
+    writeln!(&mut buffer, "---")?;
+    write!(&mut buffer, "{}", serde_yaml::to_string(&projected)?)?;
+    output.print(&buffer)?;
+
+    Ok(())
+}
+
+/// Renders the line with the --format template. `{level}`, `{time}` and
+/// `{message}` refer to the first of the --level, --time and --message
+/// keys, unless the line has a key with that name.
+pub (crate) fn write_template(opts: &Opt, template: &Template, json: Map<String, Value>, output: &mut Printer) -> Result<()> {
+    let mut line = json;
+
+    for (name, keys) in [("level", &opts.level), ("time", &opts.time), ("message", &opts.message)] {
+        if ! line.contains_key(name) {
+            if let Some(value) = keys.iter().find_map(|k| line.get(k)).cloned() {
+                line.insert(name.to_owned(), value);
+            }
+        }
+    }
+
+    let mut buffer = output.buffer();
+    output.write_prefix(&mut buffer)?;
+    writeln!(&mut buffer, "{}", template.render(&Value::Object(line)))?;
+    output.print(&buffer)?;
+
+    Ok(())
+}
+
+fn delimited_value(value: Option<&Value>, delimiter: char) -> String {
+    let raw = match value {
+        None | Some(Value::Null) => return String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    };
+
+    if delimiter == '\t' {
+        // tsv has no quoting, special characters are escaped instead
+        raw.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+    } else if raw.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", raw.replace('"', "\"\""))
+    } else {
+        raw
+    }
+}
+
+/// Columns for csv/tsv/table output, excluding `skip`. These are the
+/// --extract keys, with wildcards expanded against the keys of `json`, or
+/// all the keys of `json` when --extract is not used.
+fn columns_for(opts: &Opt, json: &Map<String, Value>, skip: &[String]) -> Vec<String> {
+    let mut columns: Vec<String> = vec![];
+
+    let mut add = |keys: Vec<String>| {
+        for key in keys {
+            if ! skip.contains(&key) && ! columns.contains(&key) {
+                columns.push(key);
+            }
+        }
+    };
+
+    if opts.extract.0.is_empty() {
+        let mut keys: Vec<String> = json.keys().cloned().collect();
+        sort_keys(opts, &mut keys);
+        add(keys);
+    }
+
+    for pattern in opts.extract.0.iter().map(cli::KeyPattern::text) {
+        if cli::is_glob(pattern) {
+            let mut keys: Vec<String> = json.keys().filter(|k| cli::glob_match(pattern, k)).cloned().collect();
+            sort_keys(opts, &mut keys);
+            add(keys);
+        } else {
+            add(vec![pattern.to_owned()]);
+        }
+    }
+
+    columns
+}
+
+/// Value of a column, which can be a key or a nested path such as `user.id`
+fn lookup_column<'a>(json: &'a Map<String, Value>, column: &str) -> Option<&'a Value> {
+    json.get(column).or_else(|| parser::parse_path(column).ok()?.lookup_in(json))
+}
+
+/// Writes csv or tsv rows. Columns are chosen from the first line written,
+/// so every row has the same shape as the header.
+pub (crate) fn write_delimited(opts: &Opt, json: Map<String, Value>, delimiter: char, output: &mut Printer) -> Result<()> {
+    let mut buffer = output.buffer();
+
+    let columns = match output.columns {
+        Some(ref columns) => columns,
+        None => {
+            let columns = columns_for(opts, &json, &[]);
+
+            let header: Vec<String> = columns.iter()
+                .map(|c| delimited_value(Some(&Value::String(c.clone())), delimiter))
+                .collect();
+            writeln!(&mut buffer, "{}", header.join(&delimiter.to_string()))?;
+
+            output.columns.insert(columns)
+        }
+    };
+
+    let row: Vec<String> = columns.iter()
+        .map(|c| delimited_value(lookup_column(&json, c), delimiter))
+        .collect();
+    writeln!(&mut buffer, "{}", row.join(&delimiter.to_string()))?;
+
+    output.print(&buffer)?;
+
+    Ok(())
+}
+
+/// Truncates `text` with an ellipsis, or pads it with spaces, to exactly `width` characters
+fn fit(text: &str, width: usize) -> String {
+    let truncated = truncate(text, width);
+    let len = text_width(&truncated);
+
+    format!("{}{:pad$}", truncated, "", pad = width - len)
+}
+
+/// Writes fields in aligned columns, like `ps`. Columns are chosen as with
+/// csv output, with the level and time keys first.
+pub (crate) fn write_table(opts: &Opt, json: Map<String, Value>, highlighted: bool, output: &mut Printer) -> Result<()> {
+    let mut buffer = output.buffer();
+    let width = opts.column_width;
+
+    let columns = match output.columns {
+        Some(ref columns) => columns,
+        None => {
+            let mut columns: Vec<String> = vec![];
+            columns.extend(opts.level.iter().find(|k| json.contains_key(*k)).cloned());
+            columns.extend(opts.time.iter().find(|k| json.contains_key(*k)).cloned());
+
+            let rest = columns_for(opts, &json, &columns);
+            columns.extend(rest);
+
+            // Lined up with the rows, which start with the prefix
+            write!(&mut buffer, "{:1$}", "", prefix_width(&output.prefix))?;
+            buffer.set_color(ColorSpec::new().set_bold(true))?;
+            let header: Vec<String> = columns.iter().map(|c| fit(c, width)).collect();
+            writeln!(&mut buffer, "{}", header.join(" ").trim_end())?;
+
+            output.columns.insert(columns)
+        }
+    };
+
+    write_prefix(&mut buffer, &output.prefix)?;
+
+    for (i, column) in columns.iter().enumerate() {
+        let value = lookup_column(&json, column);
+        let time = value.filter(|_| opts.time.contains(column)).and_then(|t| timestamp::display(t, opts));
+
+        let text = match (time, value) {
+            (Some(time), _) => time,
+            (None, None | Some(Value::Null)) => String::new(),
+            (None, Some(Value::String(s))) => s.clone(),
+            (None, Some(other)) => other.to_string(),
+        };
+
+        let color = if opts.level.contains(column) { output.theme.level_color(&text) } else { None };
+
+        buffer.set_color(color_spec(highlighted).set_fg(color))?;
+        if i > 0 {
+            write!(&mut buffer, " ")?;
+        }
+        write!(&mut buffer, "{}", fit(&text, width))?;
+    }
+
+    buffer.set_color(&ColorSpec::new())?;
+    writeln!(&mut buffer)?;
+    output.print(&buffer)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use structopt::StructOpt;
+    use super::*;
+
+    #[test]
+    fn prefix_test() {
+        let opts = Opt::from_iter(["jaxe", "-l", "level", "-t", "time", "-m", "msg"]);
+        let mut printer = Printer::capture(Theme::default());
+        printer.set_label(Some("api".to_owned()));
+        printer.set_line_number(Some(7));
+
+        let json = || json!({"a": 1}).as_object().unwrap().clone();
+
+        write_text(&opts, json(), false, &[], &mut printer).unwrap();
+        assert_eq!(printer.take_captured(), "[api] 7: a=1 \n");
+
+        write_json(&opts, json(), &mut printer).unwrap();
+        write_logfmt(&opts, json(), &mut printer).unwrap();
+        write_delimited(&opts, json(), ',', &mut printer).unwrap();
+        write_delimited(&opts, json(), ',', &mut printer).unwrap();
+        assert_eq!(printer.take_captured(), "{\"a\":1}\na=1\na\n1\n1\n");
+
+        let opts = Opt::from_iter(["jaxe", "-l", "level", "-t", "time", "-m", "msg", "--output", "table", "--column-width", "4"]);
+        let mut printer = Printer::capture(Theme::default());
+        printer.set_label(Some("svc".to_owned()));
+
+        let row = |a: u32, b: &str| json!({"a": a, "b": b}).as_object().unwrap().clone();
+        write_table(&opts, row(1, "x"), false, &mut printer).unwrap();
+        write_table(&opts, row(22, "y"), false, &mut printer).unwrap();
+        assert_eq!(printer.take_captured(), "      a    b\n[svc] 1    x   \n[svc] 22   y   \n");
+    }
+
+    #[test]
+    fn logfmt_value_test() {
+        assert_eq!(logfmt_value(&json!("simple")), "simple");
+        assert_eq!(logfmt_value(&json!(204)), "204");
+        assert_eq!(logfmt_value(&json!("")), "\"\"");
+        assert_eq!(logfmt_value(&json!("http request")), "\"http request\"");
+        assert_eq!(logfmt_value(&json!("say \"hi\"\n")), "\"say \\\"hi\\\"\\n\"");
+        assert_eq!(logfmt_value(&json!({"a": 1})), "\"{\\\"a\\\":1}\"");
+        assert_eq!(logfmt_value(&json!("C:\\tmp\tx")), r#""C:\\tmp\tx""#);
+        assert_eq!(logfmt_value(&json!("e\u{301} café \u{200b}=")), "\"e\u{301} café \u{200b}=\"");
+        assert_eq!(logfmt_value(&json!("bell\u{7}")), r#""bell\u0007""#);
+    }
+
+    #[test]
+    fn logfmt_key_test() {
+        assert_eq!(logfmt_key("http.method"), "http.method");
+        assert_eq!(logfmt_key("a b"), "a_b");
+        assert_eq!(logfmt_key("c=d"), "c_d");
+        assert_eq!(logfmt_key("say \"hi\"\n"), "say__hi__");
+        assert_eq!(logfmt_key(""), "_");
+
+        let opts = Opt::from_iter(["jaxe", "-l", "level", "-t", "time", "-m", "msg"]);
+        let mut printer = Printer::capture(Theme::default());
+        write_logfmt(&opts, json!({"a b": 1, "c=d": "x y"}).as_object().unwrap().clone(), &mut printer).unwrap();
+        let line = printer.take_captured();
+
+        assert_eq!(line, "a_b=1 c_d=\"x y\"\n");
+        assert_eq!(crate::plaintext::logfmt(&line), json!({"a_b": "1", "c_d": "x y"}).as_object().cloned());
+    }
+
+    #[test]
+    fn delimited_value_test() {
+        assert_eq!(delimited_value(Some(&json!("simple")), ','), "simple");
+        assert_eq!(delimited_value(None, ','), "");
+        assert_eq!(delimited_value(Some(&json!(null)), ','), "");
+        assert_eq!(delimited_value(Some(&json!("a,b")), ','), "\"a,b\"");
+        assert_eq!(delimited_value(Some(&json!("say \"hi\"")), ','), "\"say \"\"hi\"\"\"");
+        assert_eq!(delimited_value(Some(&json!("a,b")), '\t'), "a,b");
+        assert_eq!(delimited_value(Some(&json!("a\tb\nc")), '\t'), "a\\tb\\nc");
+    }
+
+    #[test]
+    fn truncate_test() {
+        assert_eq!(truncate("abc", 5), "abc");
+        assert_eq!(truncate("abcdef", 5), "abcd…");
+        assert_eq!(truncate("ééééé", 5), "ééééé");
+        assert_eq!(truncate("e\u{301}e\u{301}", 2), "e\u{301}e\u{301}");
+        assert_eq!(truncate("日本語テキスト", 6), "日本…");
+        assert_eq!(truncate("abc", 0), "");
+    }
+
+    #[test]
+    fn label_color_test() {
+        assert_eq!(label_color("api"), label_color("api"));
+        assert_ne!(label_color("api"), label_color("db"));
+    }
+
+    #[test]
+    fn fit_test() {
+        assert_eq!(fit("abc", 5), "abc  ");
+        assert_eq!(fit("abcde", 5), "abcde");
+        assert_eq!(fit("abcdef", 5), "abcd…");
+        assert_eq!(fit("日本", 5), "日本 ");
+        assert_eq!(fit("日本語", 5), "日本…");
+        assert_eq!(fit("🦀🦀🦀", 4), "🦀… ");
+    }
+
+    #[test]
+    fn sort_keys_test() {
+        let opts = Opt::from_iter(["jaxe", "--first", "msg,err"]);
+        let mut keys = vec!["b", "err", "a", "msg"];
+
+        sort_keys(&opts, &mut keys);
+
+        assert_eq!(keys, vec!["msg", "err", "a", "b"]);
+    }
+
+    #[test]
+    fn take_stack_test() {
+        let stack = "TypeError: boom\n    at handler (app.js:12:5)";
+        let mut json = json!({"err": {"name": "TypeError", "message": "boom", "stack": stack, "code": "E1"}, "trace": "one line"})
+            .as_object().unwrap().clone();
+        let keys = vec!["trace".to_owned(), "err".to_owned()];
+
+        assert_eq!(take_stack(&mut json, &keys).as_deref(), Some(stack));
+        assert_eq!(Value::Object(json.clone()), json!({"err": {"code": "E1"}, "trace": "one line"}));
+        assert_eq!(take_stack(&mut json, &keys), None);
+    }
+
+    #[test]
+    fn sort_keys_original_order_test() {
+        let opts = Opt::from_iter(["jaxe", "--original-order", "--first", "msg"]);
+        let mut keys = vec!["b", "err", "a", "msg"];
+
+        sort_keys(&opts, &mut keys);
+
+        assert_eq!(keys, vec!["msg", "b", "err", "a"]);
+    }
+
+    #[test]
+    fn render_json_test() {
+        let value = json!({"a": {"b": {"c": 1}, "d": [1, 2, 3]}, "e": "x"});
+
+        assert_eq!(render_json(&value, None), value.to_string());
+        assert_eq!(render_json(&value, Some(0)), "{…2 keys}");
+        assert_eq!(render_json(&value, Some(1)), "{\"a\":{…2 keys},\"e\":\"x\"}");
+        assert_eq!(render_json(&value, Some(2)), "{\"a\":{\"b\":{…1 key},\"d\":[…3 items]},\"e\":\"x\"}");
+        assert_eq!(render_json(&json!([["x"]]), Some(1)), "[[…1 item]]");
+    }
+}
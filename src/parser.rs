@@ -4,38 +4,77 @@ use nom::bytes::complete::tag;
 use nom::character::complete::{multispace0, char};
 use nom::multi::separated_list1;
 use nom::sequence::{tuple, delimited, separated_pair};
-use serde_json::Value;
+use serde_json::{Map, Value};
 use nom::InputTakeAtPosition;
 use anyhow::Result;
 use anyhow::anyhow;
 use anyhow::bail;
 
 use nom_locate::LocatedSpan;
+use std::cmp::Ordering;
+
+use crate::duration;
+use crate::timestamp;
 
 type Span<'a> = LocatedSpan<&'a str>;
 
-fn path_segment(input: Span) -> IResult<Span, String> {
-    let (rest, v) = input.split_at_position_complete(|item| ! item.is_alphanum() && item != '_' && item != '-')?;
+fn unquoted_path_segment(input: Span) -> IResult<Span, String> {
+    let (rest, v) = input.split_at_position1_complete(|item| ! item.is_alphanum() && item != '_' && item != '-', nom::error::ErrorKind::AlphaNumeric)?;
     Ok((rest, v.to_string()))
 }
 
+// Quoted segments can contain any character, `\"` and `\\` are used to
+// escape quotes and backslashes
+fn quoted_path_segment(input: Span) -> IResult<Span, String> {
+    let (mut rest, _) = char('"')(input)?;
+    let mut segment = String::new();
+
+    loop {
+        let mut chars = rest.fragment().chars();
+
+        match chars.next() {
+            Some('"') => {
+                let (rest, _) = char('"')(rest)?;
+                return Ok((rest, segment))
+            },
+            Some('\\') => {
+                let escaped = chars.next()
+                    .filter(|c| *c == '"' || *c == '\\')
+                    .ok_or_else(|| nom::Err::Error(nom::error::Error::new(rest, nom::error::ErrorKind::Escaped)))?;
+                segment.push(escaped);
+                rest = nom::bytes::complete::take(2usize)(rest)?.0;
+            },
+            Some(c) => {
+                segment.push(c);
+                rest = nom::bytes::complete::take(1usize)(rest)?.0;
+            },
+            None =>
+                return Err(nom::Err::Error(nom::error::Error::new(rest, nom::error::ErrorKind::Char)))
+        }
+    }
+}
+
+fn path_segment(input: Span) -> IResult<Span, String> {
+    branch::alt((quoted_path_segment, unquoted_path_segment))(input)
+}
+
 fn path(input: Span) -> IResult<Span, EPath> {
     let (rest, matched) = separated_list1(tag("."), path_segment)(input)?;
     Ok((rest, EPath(matched)))
 }
 
-fn unquoted_value(input: Span) -> IResult<Span, &str> {
+fn unquoted_value(input: Span<'_>) -> IResult<Span<'_>, &str> {
     let (rest, v) = input.split_at_position_complete(|item| item.is_whitespace() || item == ',' || item == ')' || item == '"' )?;
     Ok((rest, v.fragment()))
 }
 
-fn end_quoted_string(input: Span) -> IResult<Span, &str> {
+fn end_quoted_string(input: Span<'_>) -> IResult<Span<'_>, &str> {
     let (rest, v) = input.split_at_position_complete(|item| item == '"' )?;
     Ok((rest, v.fragment()))
 }
 
 // TODO: This is not right... Would need to parse escaped chars etc
-fn value(input: Span) -> IResult<Span, &str> {
+fn value(input: Span<'_>) -> IResult<Span<'_>, &str> {
     branch::alt((
         delimited(char('"'), end_quoted_string, char('"')),
         unquoted_value,
@@ -52,8 +91,26 @@ fn operation_not_equals(input: Span) -> IResult<Span, Exp> {
     Ok((input, Exp::NotEquals(path, value.to_string())))
 }
 
+fn compare_op(input: Span) -> IResult<Span, CmpOp> {
+    let (rest, op) = branch::alt((tag(">="), tag("<="), tag(">"), tag("<")))(input)?;
+
+    let op = match *op.fragment() {
+        ">=" => CmpOp::GreaterOrEqual,
+        "<=" => CmpOp::LessOrEqual,
+        ">" => CmpOp::Greater,
+        _ => CmpOp::Less,
+    };
+
+    Ok((rest, op))
+}
+
+fn operation_compare(input: Span) -> IResult<Span, Exp> {
+    let (input, (path, _, op, _, value)) = tuple((path, multispace0, compare_op, multispace0, value))(input)?;
+    Ok((input, Exp::Compare(path, op, value.to_owned())))
+}
+
 fn operation(input: Span) -> IResult<Span, Exp> {
-    operation_not_equals(input).or(operation_equals(input))
+    branch::alt((operation_not_equals, operation_equals, operation_compare))(input)
 }
 
 fn exists(input: Span) -> IResult<Span, Exp> {
@@ -93,6 +150,18 @@ fn contains(input: Span) -> IResult<Span, Exp> {
     Ok((input, Exp::Contains(path, val.into())))
 }
 
+/// Parses a standalone path such as `http.status` or `labels."app/name"`
+pub (crate) fn parse_path(input: &str) -> Result<EPath> {
+    let span = Span::new(input);
+    let (rest, path) = path(span).map_err(|err| anyhow!("Could not parse path: {}", err))?;
+
+    if rest.len() != 0 {
+        bail!("Could not parse the complete path: {}, left over: {}", input, rest)
+    }
+
+    Ok(path)
+}
+
 pub (crate) fn parse(input: &str) -> Result<Exp> {
     let input = Span::new(input);
     let (rest, op) = exp(input).map_err(|err| anyhow!("Could not parse filter: {}", err))?;
@@ -104,15 +173,160 @@ pub (crate) fn parse(input: &str) -> Result<Exp> {
     Ok(op)
 }
 
+fn arith_number(input: Span) -> IResult<Span, Arith> {
+    let (rest, n) = nom::combinator::recognize(tuple((
+        nom::combinator::opt(char('-')),
+        nom::character::complete::digit1,
+        nom::combinator::opt(tuple((char('.'), nom::character::complete::digit1))),
+    )))(input)?;
+
+    // Only digits were recognized, so this always parses
+    Ok((rest, Arith::Number(n.fragment().parse().unwrap())))
+}
+
+fn arith_string(input: Span) -> IResult<Span, Arith> {
+    let (rest, s) = delimited(char('"'), end_quoted_string, char('"'))(input)?;
+    Ok((rest, Arith::Str(s.to_owned())))
+}
+
+fn arith_factor(input: Span) -> IResult<Span, Arith> {
+    branch::alt((
+        delimited(tuple((char('('), multispace0)), arith_exp, tuple((multispace0, char(')')))),
+        arith_string,
+        arith_number,
+        nom::combinator::map(path, Arith::Path),
+    ))(input)
+}
+
+fn arith_binary<'a>(
+    operand: fn(Span<'a>) -> IResult<Span<'a>, Arith>,
+    ops: &'static str,
+) -> impl FnMut(Span<'a>) -> IResult<Span<'a>, Arith> {
+    move |input| {
+        let (mut input, mut left) = operand(input)?;
+
+        loop {
+            let op: IResult<Span, (Span, char, Span)> = tuple((multispace0, nom::character::complete::one_of(ops), multispace0))(input);
+
+            match op {
+                Ok((rest, (_, op, _))) => {
+                    let (rest, right) = operand(rest)?;
+                    left = Arith::Op(Box::new(left), op, Box::new(right));
+                    input = rest;
+                },
+                Err(_) =>
+                    return Ok((input, left))
+            }
+        }
+    }
+}
+
+fn arith_term(input: Span) -> IResult<Span, Arith> {
+    arith_binary(arith_factor, "*/")(input)
+}
+
+fn arith_exp(input: Span) -> IResult<Span, Arith> {
+    arith_binary(arith_term, "+-")(input)
+}
+
+/// Parses an arithmetic expression such as `duration_ms / 1000` or
+/// `host + ":" + port`
+pub (crate) fn parse_arith(input: &str) -> Result<Arith> {
+    let span = Span::new(input.trim());
+    let (rest, exp) = arith_exp(span).map_err(|err| anyhow!("Could not parse expression: {}", err))?;
+
+    if rest.len() != 0 {
+        bail!("Could not parse the complete expression: {}, left over: {}", input, rest)
+    }
+
+    Ok(exp)
+}
+
+/// Arithmetic over values of a json line, used to derive new fields
+#[derive(Debug, PartialEq)]
+pub enum Arith {
+    Path(EPath),
+    Number(f64),
+    Str(String),
+    Op(Box<Arith>, char, Box<Arith>),
+}
+
+fn arith_number_value(n: f64) -> Option<Value> {
+    if n.fract() == 0.0 && n.abs() < 9007199254740992.0 {
+        Some(Value::from(n as i64))
+    } else {
+        serde_json::Number::from_f64(n).map(Value::Number)
+    }
+}
+
+/// Evaluates `exp` against `target`. `+` concatenates when either side is
+/// not a number, other operators need numbers on both sides. Returns `None`
+/// when a path does not exist or the operation is not possible.
+pub (crate) fn eval_arith(exp: &Arith, target: &Value) -> Option<Value> {
+    match exp {
+        Arith::Path(path) =>
+            descend_to(path, target).filter(|v| ! v.is_null()).cloned(),
+        Arith::Number(n) =>
+            arith_number_value(*n),
+        Arith::Str(s) =>
+            Some(Value::String(s.clone())),
+        Arith::Op(left, op, right) => {
+            let left = string_value(&eval_arith(left, target)?)?;
+            let right = string_value(&eval_arith(right, target)?)?;
+
+            match (parse_number(&left), parse_number(&right), op) {
+                (Some(l), Some(r), '+') => arith_number_value(l + r),
+                (Some(l), Some(r), '-') => arith_number_value(l - r),
+                (Some(l), Some(r), '*') => arith_number_value(l * r),
+                (Some(l), Some(r), '/') if r != 0.0 => arith_number_value(l / r),
+                (_, _, '+') => Some(Value::String(left + &right)),
+                _ => None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
 pub (crate) fn filter(exp: &Exp, target: &Value) -> Result<bool> {
-    let evalued = eval(exp, target);
+    filter_with(exp, target, &EvalContext::default())
+}
+
+pub (crate) fn filter_with(exp: &Exp, target: &Value, ctx: &EvalContext) -> Result<bool> {
+    let evalued = eval_with(exp, target, ctx);
     let as_bool = evalued.as_bool().unwrap_or(false);
     Ok(as_bool)
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct EPath(Vec<String>);
 
+impl EPath {
+    /// Builds a JSON Pointer (RFC 6901) for this path, escaping `~` and `/`
+    pub (crate) fn pointer(&self) -> String {
+        self.0.iter()
+            .map(|s| format!("/{}", s.replace('~', "~0").replace('/', "~1")))
+            .collect()
+    }
+
+    pub (crate) fn new(segments: Vec<String>) -> EPath {
+        EPath(segments)
+    }
+
+    pub (crate) fn segments(&self) -> &[String] {
+        &self.0
+    }
+
+    pub (crate) fn lookup<'a>(&self, target: &'a Value) -> Option<&'a Value> {
+        descend_to(self, target)
+    }
+
+    /// Same as [`EPath::lookup`], for a json object that is not wrapped in a `Value`
+    pub (crate) fn lookup_in<'a>(&self, target: &'a Map<String, Value>) -> Option<&'a Value> {
+        let (first, rest) = self.0.split_first()?;
+        target.get(first)?.pointer(&EPath(rest.to_vec()).pointer())
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Exp {
     Equals(EPath, String),
@@ -121,13 +335,20 @@ pub enum Exp {
     Not(Box<Exp>),
     And(Vec<Exp>),
     Or(Vec<Exp>),
-    Contains(EPath, String)
+    Contains(EPath, String),
+    Compare(EPath, CmpOp, String),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CmpOp {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
 }
 
 fn descend_to<'a>(path: &EPath, target: &'a Value) -> Option<&'a Value> {
-    let mut pointer = path.0.join("/");
-    pointer.insert_str(0, "/");
-    target.pointer(&pointer)
+    target.pointer(&path.pointer())
 }
 
 fn string_value(value: &Value) -> Option<String> {
@@ -143,11 +364,31 @@ fn string_value(value: &Value) -> Option<String> {
     }
 }
 
-pub fn eval_equals<'a>(path: &EPath, value: &str, target: &'a Value) -> &'a Value {
+/// Settings affecting how expressions are evaluated
+#[derive(Debug, Default)]
+pub (crate) struct EvalContext {
+    /// Maximum difference for two numbers to be considered equal
+    pub (crate) epsilon: f64,
+}
+
+fn parse_number(value: &str) -> Option<f64> {
+    value.parse::<f64>().ok().filter(|n| n.is_finite())
+}
+
+/// Values that both parse as numbers are compared numerically, so `1 ==
+/// 1.0` and `007 == 7` hold, everything else is compared as strings
+fn values_equal(left: &str, right: &str, ctx: &EvalContext) -> bool {
+    match (parse_number(left), parse_number(right)) {
+        (Some(l), Some(r)) => (l - r).abs() <= ctx.epsilon,
+        _ => left == right,
+    }
+}
+
+pub fn eval_equals<'a>(path: &EPath, value: &str, target: &'a Value, ctx: &EvalContext) -> &'a Value {
     let path_value = descend_to(path, target).unwrap_or(&Value::Null);
 
     if let Some(p) = string_value(path_value) {
-        if p == value {
+        if values_equal(&p, value, ctx) {
             &Value::Bool(true)
         } else {
             &Value::Bool(false)
@@ -157,8 +398,8 @@ pub fn eval_equals<'a>(path: &EPath, value: &str, target: &'a Value) -> &'a Valu
     }
 }
 
-pub fn eval_not_equals<'a>(path: &EPath, value: &str, target: &'a Value) -> &'a Value {
-    if *eval_equals(path, value, target) == Value::Bool(true) {
+pub fn eval_not_equals<'a>(path: &EPath, value: &str, target: &'a Value, ctx: &EvalContext) -> &'a Value {
+    if *eval_equals(path, value, target, ctx) == Value::Bool(true) {
         &Value::Bool(false)
     } else {
         &Value::Bool(true)
@@ -175,20 +416,20 @@ pub fn eval_exists<'a>(path: &EPath, target: &'a Value) -> &'a Value {
     }
 }
 
-fn eval_not<'a>(exp: &Exp, target: &'a Value) -> &'a Value {
-    let new_val = eval(exp, target);
-    if new_val.as_bool().unwrap_or(false) == true {
+fn eval_not<'a>(exp: &Exp, target: &'a Value, ctx: &EvalContext) -> &'a Value {
+    let new_val = eval_with(exp, target, ctx);
+    if new_val.as_bool().unwrap_or(false) {
         &Value::Bool(false)
     } else {
         &Value::Bool(true)
     }
 }
 
-fn eval_and<'a>(conditions: &Vec<Exp>, target: &'a Value) -> &'a Value {
+fn eval_and<'a>(conditions: &Vec<Exp>, target: &'a Value, ctx: &EvalContext) -> &'a Value {
     for cond in conditions {
-        let left_val = eval(cond, target);
+        let left_val = eval_with(cond, target, ctx);
 
-        if left_val.as_bool().unwrap_or(false) == false {
+        if ! left_val.as_bool().unwrap_or(false) {
             return &Value::Bool(false)
         }
     };
@@ -196,9 +437,9 @@ fn eval_and<'a>(conditions: &Vec<Exp>, target: &'a Value) -> &'a Value {
     &Value::Bool(true)
 }
 
-fn eval_or<'a>(conditions: &Vec<Exp>, target: &'a Value) -> &'a Value {
+fn eval_or<'a>(conditions: &Vec<Exp>, target: &'a Value, ctx: &EvalContext) -> &'a Value {
     for cond in conditions {
-        let left_val = eval(cond, target);
+        let left_val = eval_with(cond, target, ctx);
 
         if left_val.as_bool().unwrap_or(false) {
             return &Value::Bool(true)
@@ -222,23 +463,103 @@ fn eval_contains<'a>(path: &EPath, val: &String, target: &'a Value) -> &'a Value
     }
 }
 
+/// A substring that made a `contains` expression match, and the path of
+/// the value it was found in
+#[derive(Debug, PartialEq)]
+pub (crate) struct Match {
+    pub (crate) path: Vec<String>,
+    pub (crate) substring: String,
+}
+
+/// Collects the substrings matched by `contains` expressions in `exp`.
+/// Matches under `not` are ignored, as they are not why the line matched.
+pub (crate) fn matches(exp: &Exp, target: &Value, ctx: &EvalContext) -> Vec<Match> {
+    match exp {
+        Exp::Contains(path, val) if *eval_contains(path, val, target) == Value::Bool(true) =>
+            vec![Match { path: path.0.clone(), substring: val.clone() }],
+        Exp::And(conditions) | Exp::Or(conditions) =>
+            conditions.iter()
+                .filter(|c| *eval_with(c, target, ctx) == Value::Bool(true))
+                .flat_map(|c| matches(c, target, ctx))
+                .collect(),
+        _ =>
+            vec![]
+    }
+}
+
+/// The paths `exp` looks at
+pub (crate) fn paths(exp: &Exp) -> Vec<&EPath> {
+    match exp {
+        Exp::Equals(path, _) | Exp::NotEquals(path, _) | Exp::Exists(path) | Exp::Contains(path, _) | Exp::Compare(path, _, _) =>
+            vec![path],
+        Exp::Not(exp) =>
+            paths(exp),
+        Exp::And(conditions) | Exp::Or(conditions) =>
+            conditions.iter().flat_map(paths).collect(),
+    }
+}
+
+/// Compares two values as durations when both have a unit (`154ms`,
+/// `2s`), otherwise as numbers, otherwise as timestamps. Values that are
+/// none of these cannot be ordered.
+fn compare_values(left: &str, right: &str) -> Option<Ordering> {
+    if let (Some(l), Some(r)) = (duration::parse(left), duration::parse(right)) {
+        return Some(l.cmp(&r))
+    }
+
+    if let (Ok(l), Ok(r)) = (left.parse::<f64>(), right.parse::<f64>()) {
+        return l.partial_cmp(&r)
+    }
+
+    let l = timestamp::parse(left)?;
+    let r = timestamp::parse(right)?;
+    Some(l.cmp(&r))
+}
+
+fn eval_compare<'a>(path: &EPath, op: &CmpOp, value: &str, target: &'a Value) -> &'a Value {
+    let path_value = descend_to(path, target).unwrap_or(&Value::Null);
+
+    let ordering = string_value(path_value).and_then(|p| compare_values(&p, value));
+
+    let res = match (ordering, op) {
+        (Some(o), CmpOp::Greater) => o == Ordering::Greater,
+        (Some(o), CmpOp::GreaterOrEqual) => o != Ordering::Less,
+        (Some(o), CmpOp::Less) => o == Ordering::Less,
+        (Some(o), CmpOp::LessOrEqual) => o != Ordering::Greater,
+        (None, _) => false,
+    };
+
+    if res {
+        &Value::Bool(true)
+    } else {
+        &Value::Bool(false)
+    }
+}
+
+#[cfg(test)]
 pub fn eval<'a>(exp: &Exp, target: &'a Value) -> &'a Value {
+    eval_with(exp, target, &EvalContext::default())
+}
+
+pub (crate) fn eval_with<'a>(exp: &Exp, target: &'a Value, ctx: &EvalContext) -> &'a Value {
     match exp {
         Exp::Contains(ref path, ref val) =>
             eval_contains(path, val, target),
         Exp::Or(ref conditions) =>
-            eval_or(conditions, target),
+            eval_or(conditions, target, ctx),
         Exp::And(ref conditions) =>
-            eval_and(conditions, target),
+            eval_and(conditions, target, ctx),
         Exp::Not(ref exp) => {
-            eval_not(exp, target)
+            eval_not(exp, target, ctx)
         },
         Exp::Exists(ref path) =>
             eval_exists(path, target),
         Exp::Equals(path, value) =>
-            eval_equals(&path, value, target),
+            eval_equals(path, value, target, ctx),
         Exp::NotEquals(path, value) =>
-            eval_not_equals(&path, value, target)
+            eval_not_equals(path, value, target, ctx),
+        Exp::Compare(path, op, value) =>
+            eval_compare(path, op, value, target),
     }
 }
 
@@ -594,8 +915,8 @@ mod test {
         });
 
         let i = "contains(mykey0, 1)";
-        let res = filter(i, &payload).unwrap();
-        assert_eq!(res, true);
+        let res = filter(&parse(i).unwrap(), &payload).unwrap();
+        assert!(res);
     }
 
     #[test]
@@ -605,8 +926,8 @@ mod test {
         });
 
         let i = "contains(mykey, myval1.something-el)";
-        let res = filter(i, &payload).unwrap();
-        assert_eq!(res, true);
+        let res = filter(&parse(i).unwrap(), &payload).unwrap();
+        assert!(res);
     }
 
     #[test]
@@ -616,7 +937,157 @@ mod test {
         });
 
         let i = "contains(mykey, \"some()\")";
-        let res = filter(i, &payload).unwrap();
-        assert_eq!(res, true);
+        let res = filter(&parse(i).unwrap(), &payload).unwrap();
+        assert!(res);
+    }
+
+    #[test]
+    fn compare_parse_test() {
+        let m = super::parse("mykey >= 10").unwrap();
+        assert_eq!(m, Exp::Compare(EPath(vec!["mykey".into()]), CmpOp::GreaterOrEqual, "10".into()));
+
+        let m = super::parse("mykey<10").unwrap();
+        assert_eq!(m, Exp::Compare(EPath(vec!["mykey".into()]), CmpOp::Less, "10".into()));
+    }
+
+    #[test]
+    fn compare_numbers_test() {
+        let payload = json!({
+            "status": 503,
+            "ratio": "0.5"
+        });
+
+        assert!(filter(&parse("status >= 500").unwrap(), &payload).unwrap());
+        assert!(! filter(&parse("status < 500").unwrap(), &payload).unwrap());
+        assert!(filter(&parse("ratio > 0.25").unwrap(), &payload).unwrap());
+    }
+
+    #[test]
+    fn compare_durations_test() {
+        let payload = json!({
+            "elapsed": "154ms",
+            "timeout": "2s"
+        });
+
+        assert!(filter(&parse("elapsed > 100ms").unwrap(), &payload).unwrap());
+        assert!(! filter(&parse("elapsed > 1s").unwrap(), &payload).unwrap());
+        assert!(filter(&parse("timeout <= 2000ms").unwrap(), &payload).unwrap());
+    }
+
+    #[test]
+    fn compare_timestamps_test() {
+        let payload = json!({
+            "time": "2024-05-01T14:30:00Z"
+        });
+
+        assert!(filter(&parse("time > \"2024-05-01 14:00\"").unwrap(), &payload).unwrap());
+        assert!(! filter(&parse("time >= \"2024-05-01T15:00:00+00:00\"").unwrap(), &payload).unwrap());
+    }
+
+    #[test]
+    fn compare_not_comparable_test() {
+        let payload = json!({
+            "elapsed": "154ms",
+            "msg": "hello"
+        });
+
+        assert!(! filter(&parse("elapsed > 100").unwrap(), &payload).unwrap());
+        assert!(! filter(&parse("msg > 1").unwrap(), &payload).unwrap());
+    }
+
+    #[test]
+    fn eval_equals_numeric_test() {
+        let payload = json!({
+            "ratio": 1.0,
+            "count": "7"
+        });
+
+        assert!(filter(&parse("ratio == 1").unwrap(), &payload).unwrap());
+        assert!(filter(&parse("count == 007").unwrap(), &payload).unwrap());
+        assert!(! filter(&parse("count != 7.0").unwrap(), &payload).unwrap());
+    }
+
+    #[test]
+    fn eval_equals_epsilon_test() {
+        let payload = json!({
+            "ratio": 0.30000000000000004
+        });
+
+        let exp = parse("ratio == 0.3").unwrap();
+        assert!(! filter(&exp, &payload).unwrap());
+
+        let ctx = EvalContext { epsilon: 1e-9 };
+        assert!(filter_with(&exp, &payload, &ctx).unwrap());
+    }
+
+    #[test]
+    fn quoted_path_test() {
+        let (rest, m) = super::path(LocatedSpan::from("labels.\"app/name\".\"a\\\"b\"")).unwrap();
+
+        assert!(rest.is_empty());
+        assert_eq!(m.0, vec!["labels", "app/name", "a\"b"]);
+    }
+
+    #[test]
+    fn escaped_pointer_test() {
+        let payload = json!({
+            "http/path": "/api",
+            "a~b": {
+                "c.d": 1
+            }
+        });
+
+        assert!(filter(&parse("\"http/path\" == /api").unwrap(), &payload).unwrap());
+        assert!(filter(&parse("exists(\"a~b\".\"c.d\")").unwrap(), &payload).unwrap());
+        assert!(! filter(&parse("exists(a.b)").unwrap(), &payload).unwrap());
+    }
+
+    #[test]
+    fn matches_test() {
+        let payload = json!({
+            "msg": "user logged in",
+            "err": "timeout"
+        });
+
+        let exp = parse("or(contains(msg, logged), contains(err, nope), and(not(contains(msg, user)), contains(err, time)))").unwrap();
+        let res = matches(&exp, &payload, &EvalContext::default());
+
+        assert_eq!(res, vec![Match { path: vec!["msg".into()], substring: "logged".into() }]);
+    }
+
+    #[test]
+    fn parse_arith_test() {
+        let m = parse_arith("a.b + 2 * (c - 1)").unwrap();
+
+        assert_eq!(m, Arith::Op(
+            Box::new(Arith::Path(EPath(vec!["a".into(), "b".into()]))),
+            '+',
+            Box::new(Arith::Op(
+                Box::new(Arith::Number(2.0)),
+                '*',
+                Box::new(Arith::Op(Box::new(Arith::Path(EPath(vec!["c".into()]))), '-', Box::new(Arith::Number(1.0)))),
+            )),
+        ));
+
+        assert!(parse_arith("a +").is_err());
+    }
+
+    #[test]
+    fn eval_arith_test() {
+        let payload = json!({
+            "duration_ms": 1500,
+            "count": "4",
+            "host": "example.com",
+            "port": 8080
+        });
+
+        let eval = |s: &str| eval_arith(&parse_arith(s).unwrap(), &payload);
+
+        assert_eq!(eval("duration_ms / 1000"), Some(json!(1.5)));
+        assert_eq!(eval("count * 2 - 1"), Some(json!(7)));
+        assert_eq!(eval("host + \":\" + port"), Some(json!("example.com:8080")));
+        assert_eq!(eval("host / 2"), None);
+        assert_eq!(eval("missing + 1"), None);
+        assert_eq!(eval("count / 0"), None);
     }
 }
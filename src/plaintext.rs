@@ -0,0 +1,381 @@
+use std::str::FromStr;
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, SecondsFormat, TimeDelta, TimeZone};
+use serde_json::{json, Map, Value};
+
+use crate::level::{self, Scheme};
+
+/// Formats of input lines, given to --input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub (crate) enum InputFormat {
+    /// Json lines, or syslog and glog lines
+    Json,
+    /// logfmt `key=value` pairs
+    Logfmt,
+    /// Json, syslog, glog or logfmt, whichever the line is
+    Auto,
+}
+
+impl FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "json" => Ok(InputFormat::Json),
+            "logfmt" => Ok(InputFormat::Logfmt),
+            "auto" => Ok(InputFormat::Auto),
+            _ => Err(anyhow!("Unknown input format: {}, expected json, logfmt or auto", src)),
+        }
+    }
+}
+
+/// A record for a line that is not json, in the formats read with `format`
+pub (crate) fn parse(line: &str, format: InputFormat) -> Option<Map<String, Value>> {
+    match format {
+        InputFormat::Json => syslog(line).or_else(|| glog(line)),
+        InputFormat::Logfmt => logfmt(line),
+        InputFormat::Auto => syslog(line).or_else(|| glog(line)).or_else(|| logfmt(line)),
+    }
+}
+
+/// Parses logfmt lines such as `level=info msg="user created" id=12`.
+/// Values are kept as strings, and keys without a value are `true`. Lines
+/// without any `key=value` pair are not logfmt.
+pub (crate) fn logfmt(line: &str) -> Option<Map<String, Value>> {
+    let mut record = Map::new();
+    let mut has_pair = false;
+    let mut rest = line.trim_end_matches(['\r', '\n']);
+
+    loop {
+        rest = rest.trim_start_matches(' ');
+        if rest.is_empty() {
+            break
+        }
+
+        let key_len = rest.find([' ', '=']).unwrap_or(rest.len());
+        let key = &rest[..key_len];
+        if key.is_empty() || key.contains('"') {
+            return None
+        }
+        rest = &rest[key_len..];
+
+        let Some(value) = rest.strip_prefix('=') else {
+            record.insert(key.to_owned(), Value::Bool(true));
+            continue
+        };
+
+        let (value, len) = match value.strip_prefix('"') {
+            Some(quoted) => unquote(quoted).map(|(value, len)| (value, len + 1))?,
+            None => {
+                let len = value.find(' ').unwrap_or(value.len());
+                (value[..len].to_owned(), len)
+            },
+        };
+
+        record.insert(key.to_owned(), Value::String(value));
+        has_pair = true;
+        rest = &rest[1 + len..];
+    }
+
+    has_pair.then_some(record)
+}
+
+/// The text of a quoted logfmt value up to its closing quote, and the
+/// length of the value including that quote
+fn unquote(text: &str) -> Option<(String, usize)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, i + 1)),
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                other => value.push(other),
+            },
+            _ => value.push(c),
+        }
+    }
+
+    None
+}
+
+/// Parses RFC 5424 and RFC 3164 syslog lines into a record with the level,
+/// time, host, app, pid and message of the line. The priority, which gives
+/// the level, is optional as it is not written to syslog files.
+pub (crate) fn syslog(line: &str) -> Option<Map<String, Value>> {
+    let line = line.trim_end_matches(['\r', '\n']);
+
+    let (priority, rest) = match line.strip_prefix('<') {
+        Some(rest) => {
+            let end = rest.find('>')?;
+            let priority: u8 = rest[..end].parse().ok()?;
+            (Some(priority), &rest[end + 1..])
+        },
+        None => (None, line),
+    };
+
+    let mut record = Map::new();
+
+    if let Some(priority) = priority {
+        let severity = json!((priority % 8).to_string());
+        record.insert("level".to_owned(), json!(level::normalize(&severity, &[], &[], Scheme::Syslog)));
+    }
+
+    match rest.strip_prefix("1 ") {
+        Some(rest) if priority.is_some() => rfc5424(rest, &mut record)?,
+        _ => rfc3164(rest, &mut record)?,
+    }
+
+    Some(record)
+}
+
+/// `TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG`, where
+/// `-` is a missing value
+fn rfc5424(line: &str, record: &mut Map<String, Value>) -> Option<()> {
+    let mut parts = line.splitn(6, ' ');
+    let header = ["time", "host", "app", "pid", "msgid"].map(|key| (key, parts.next()));
+    let rest = parts.next()?;
+
+    for (key, value) in header {
+        match value? {
+            "-" => (),
+            value => { record.insert(key.to_owned(), json!(value)); },
+        }
+    }
+
+    let data_len = structured_data_len(rest)?;
+    if &rest[..data_len] != "-" {
+        record.insert("structured_data".to_owned(), json!(&rest[..data_len]));
+    }
+
+    let msg = rest[data_len..].trim_start_matches(' ').trim_start_matches('\u{feff}');
+    if ! msg.is_empty() {
+        record.insert("msg".to_owned(), json!(msg));
+    }
+
+    Some(())
+}
+
+/// Length of the structured data at the start of `text`, either `-` or
+/// `[id key="value"]` elements where values may contain escaped `]`
+fn structured_data_len(text: &str) -> Option<usize> {
+    if text.starts_with('-') {
+        return Some(1)
+    }
+
+    let (mut in_value, mut escaped, mut depth) = (false, false, 0);
+
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_value => escaped = true,
+            '"' => in_value = ! in_value,
+            '[' if ! in_value => depth += 1,
+            ']' if ! in_value => {
+                depth -= 1;
+
+                if depth == 0 && ! text[i + 1..].starts_with('[') {
+                    return Some(i + 1)
+                }
+            },
+            _ if depth == 0 => return None,
+            _ => (),
+        }
+    }
+
+    None
+}
+
+/// `Mmm dd hh:mm:ss HOSTNAME TAG[PID]: MSG`. Times have no year or zone,
+/// they are taken as local times within the last year.
+fn rfc3164(line: &str, record: &mut Map<String, Value>) -> Option<()> {
+    let stamp = line.get(..15)?;
+    let rest = line.get(15..)?.strip_prefix(' ')?;
+
+    let time = without_year(stamp, "%b %e %H:%M:%S")?;
+    record.insert("time".to_owned(), json!(time.to_rfc3339_opts(SecondsFormat::Secs, false)));
+
+    let (host, rest) = rest.split_once(' ')?;
+    record.insert("host".to_owned(), json!(host));
+
+    let msg = match rest.split_once(": ") {
+        Some((tag, msg)) if ! tag.contains(' ') => {
+            let (app, pid) = match tag.split_once('[') {
+                Some((app, pid)) => (app, pid.strip_suffix(']')),
+                None => (tag, None),
+            };
+
+            record.insert("app".to_owned(), json!(app));
+            if let Some(pid) = pid {
+                record.insert("pid".to_owned(), json!(pid));
+            }
+
+            msg
+        },
+        _ => rest,
+    };
+
+    record.insert("msg".to_owned(), json!(msg));
+
+    Some(())
+}
+
+/// Parses `stamp`, a time without a year, as a local time within the last
+/// year
+fn without_year(stamp: &str, format: &str) -> Option<DateTime<Local>> {
+    let now = Local::now();
+    let parse = |year: i32| NaiveDateTime::parse_from_str(&format!("{} {}", year, stamp), &format!("%Y {}", format)).ok()
+        .and_then(|time| Local.from_local_datetime(&time).earliest());
+
+    let time = parse(now.year())?;
+    if time > now + TimeDelta::days(1) {
+        return parse(now.year() - 1)
+    }
+
+    Some(time)
+}
+
+/// Parses glog and klog lines, as written by kubernetes components, such
+/// as `I0527 10:00:00.123456    1234 server.go:123] msg`. The structured
+/// lines of klog, a quoted message followed by `key="value"` pairs, get
+/// these pairs as fields.
+pub (crate) fn glog(line: &str) -> Option<Map<String, Value>> {
+    let line = line.trim_end_matches(['\r', '\n']);
+
+    let level = match line.get(..1)? {
+        "I" => "INFO",
+        "W" => "WARN",
+        "E" => "ERROR",
+        "F" => "FATAL",
+        _ => return None,
+    };
+
+    let (header, msg) = line[1..].split_once("] ")
+        .or_else(|| line[1..].strip_suffix(']').map(|header| (header, "")))?;
+    let mut parts = header.split_whitespace();
+    let (date, clock, thread, source) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+
+    if parts.next().is_some() || date.len() != 4 || ! thread.bytes().all(|b| b.is_ascii_digit()) || ! source.contains(':') {
+        return None
+    }
+
+    let time = without_year(&format!("{} {}", date, clock), "%m%d %H:%M:%S%.f")?;
+
+    let mut record = Map::new();
+    record.insert("level".to_owned(), json!(level));
+    record.insert("time".to_owned(), json!(time.to_rfc3339_opts(SecondsFormat::AutoSi, false)));
+    record.insert("thread".to_owned(), json!(thread));
+    record.insert("file".to_owned(), json!(source));
+
+    let structured = msg.strip_prefix('"')
+        .and_then(unquote)
+        .and_then(|(text, len)| match msg[1 + len..].trim_start() {
+            "" => Some((text, Map::new())),
+            pairs => logfmt(pairs).map(|pairs| (text, pairs)),
+        });
+
+    match structured {
+        Some((text, pairs)) => {
+            record.insert("msg".to_owned(), json!(text));
+            for (key, value) in pairs {
+                record.entry(key).or_insert(value);
+            }
+        },
+        None => { record.insert("msg".to_owned(), json!(msg)); },
+    }
+
+    Some(record)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rfc5424_test() {
+        let line = r#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut="3" eventID="1011"] An application event"#;
+
+        assert_eq!(Value::Object(syslog(line).unwrap()), json!({
+            "level": "INFO",
+            "time": "2003-10-11T22:14:15.003Z",
+            "host": "mymachine.example.com",
+            "app": "evntslog",
+            "msgid": "ID47",
+            "structured_data": r#"[exampleSDID@32473 iut="3" eventID="1011"]"#,
+            "msg": "An application event"
+        }));
+
+        let line = "<11>1 2003-10-11T22:14:15Z host app 812 - - worker exited\n";
+        let record = syslog(line).unwrap();
+        assert_eq!(record["level"], "ERROR");
+        assert_eq!(record["pid"], "812");
+        assert_eq!(record["msg"], "worker exited");
+    }
+
+    #[test]
+    fn rfc3164_test() {
+        let record = syslog("<34>Oct 11 22:14:15 mymachine su[230]: 'su root' failed for lonvick on /dev/pts/8").unwrap();
+
+        assert_eq!(record["level"], "FATAL");
+        assert!(record["time"].as_str().unwrap().contains("-10-11T22:14:15"));
+        assert_eq!(record["host"], "mymachine");
+        assert_eq!(record["app"], "su");
+        assert_eq!(record["pid"], "230");
+        assert_eq!(record["msg"], "'su root' failed for lonvick on /dev/pts/8");
+
+        let record = syslog("May  1 04:00:00 web kernel: eth0 link up").unwrap();
+        assert!(! record.contains_key("level"));
+        assert_eq!(record["app"], "kernel");
+        assert_eq!(record["msg"], "eth0 link up");
+    }
+
+    #[test]
+    fn logfmt_test() {
+        let record = logfmt("level=info msg=\"user \\\"bob\\\" created\" id=12 dry_run\n").unwrap();
+
+        assert_eq!(Value::Object(record), json!({"level": "info", "msg": "user \"bob\" created", "id": "12", "dry_run": true}));
+        assert_eq!(Value::Object(logfmt("a= b=2").unwrap()), json!({"a": "", "b": "2"}));
+        assert_eq!(logfmt("plain text"), None);
+        assert_eq!(logfmt("msg=\"unterminated"), None);
+    }
+
+    #[test]
+    fn parse_test() {
+        let syslog_line = "<11>1 2003-10-11T22:14:15Z host app 812 - - worker exited";
+
+        assert!(parse(syslog_line, InputFormat::Json).is_some());
+        assert_eq!(parse("level=info", InputFormat::Json), None);
+        assert!(parse("level=info", InputFormat::Logfmt).is_some());
+        assert!(parse("level=info", InputFormat::Auto).is_some());
+        assert_eq!(parse(syslog_line, InputFormat::Auto).unwrap()["app"], "app");
+    }
+
+    #[test]
+    fn not_syslog_test() {
+        assert_eq!(syslog("plain text"), None);
+        assert_eq!(syslog("<html> page"), None);
+        assert_eq!(syslog("Connection refused: retrying in 5s"), None);
+    }
+
+    #[test]
+    fn glog_test() {
+        let record = glog("E0527 10:00:00.123456    1234 server.go:123] connection refused\n").unwrap();
+
+        assert_eq!(record["level"], "ERROR");
+        assert!(record["time"].as_str().unwrap().contains("-05-27T10:00:00.123456"));
+        assert_eq!(record["thread"], "1234");
+        assert_eq!(record["file"], "server.go:123");
+        assert_eq!(record["msg"], "connection refused");
+
+        let record = glog(r#"I0527 10:00:01.000000 1 controller.go:12] "Pod updated" pod="kube-system/dns" ready=true"#).unwrap();
+        assert_eq!(record["level"], "INFO");
+        assert_eq!(record["msg"], "Pod updated");
+        assert_eq!(record["pod"], "kube-system/dns");
+        assert_eq!(record["ready"], "true");
+
+        assert_eq!(glog("Info: nothing to see"), None);
+        assert_eq!(glog("I0527 not a glog line"), None);
+    }
+}
@@ -0,0 +1,780 @@
+use std::str::FromStr;
+use anyhow::{anyhow, Context, Result};
+use serde_json::{Map, Value};
+
+use regex::Regex;
+
+use crate::Opt;
+use crate::cli::{self, KeyValue, MultOpt};
+use crate::level::{self, Scheme};
+use crate::theme::Theme;
+
+/// Log formats with known keys and shapes, see --preset
+#[derive(Debug, Clone)]
+pub (crate) enum Preset {
+    /// The json-file logging driver of docker, which wraps each line as
+    /// `{"log": "...", "stream": "stdout", "time": "..."}`
+    Docker,
+    /// The json output of journalctl, with journald fields such as
+    /// `PRIORITY`, `MESSAGE` and `__REALTIME_TIMESTAMP`
+    Journal,
+    /// Graylog extended log format, with `short_message`, syslog levels and
+    /// custom fields starting with `_`
+    Gelf,
+    /// AWS CloudWatch Logs events, as exported or queried with Insights
+    /// (`@timestamp`, `@message`), or as written by `aws logs tail`
+    Cloudwatch,
+    /// Google Cloud Logging entries, as written by `gcloud logging read
+    /// --format json`
+    Gcp,
+    /// Elastic Common Schema logs, as written by filebeat and the
+    /// ecs-logging libraries
+    Ecs,
+    /// OpenTelemetry log records, in OTLP json or as written by the
+    /// logging exporters
+    Otel,
+    /// Node services logging with bunyan, with numeric levels, the `name`,
+    /// `pid` and `hostname` of the process and `err` objects with stacks
+    Bunyan,
+    /// Node services logging with pino, like bunyan but with times as epoch
+    /// milliseconds
+    Pino,
+    /// A preset defined in the presets file
+    User(Box<UserPreset>),
+}
+
+/// A built-in preset, or a preset of `~/.config/jaxe/presets.toml`
+impl FromStr for Preset {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        if let Some(preset) = Preset::builtin(src) {
+            return Ok(preset)
+        }
+
+        let Some(path) = cli::config_file("presets.toml") else {
+            return Err(anyhow!("Unknown preset: {}", src))
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Could not read presets file: {}", path.display()))?;
+        let presets: toml::Table = contents.parse()
+            .with_context(|| format!("Invalid presets file: {}", path.display()))?;
+
+        match presets.get(src) {
+            Some(preset) => UserPreset::from_toml(preset)
+                .map(|preset| Preset::User(Box::new(preset)))
+                .with_context(|| format!("Invalid preset {} in {}", src, path.display())),
+            None => Err(anyhow!("Unknown preset: {}, not built-in nor in {}", src, path.display())),
+        }
+    }
+}
+
+impl Preset {
+    fn builtin(name: &str) -> Option<Preset> {
+        match name {
+            "docker" => Some(Preset::Docker),
+            "journal" => Some(Preset::Journal),
+            "gelf" => Some(Preset::Gelf),
+            "cloudwatch" => Some(Preset::Cloudwatch),
+            "gcp" => Some(Preset::Gcp),
+            "ecs" => Some(Preset::Ecs),
+            "otel" => Some(Preset::Otel),
+            "bunyan" => Some(Preset::Bunyan),
+            "pino" => Some(Preset::Pino),
+            _ => None,
+        }
+    }
+
+    /// Uses the keys of this format, unless other keys are given
+    pub (crate) fn apply_defaults(&self, opts: &mut Opt) {
+        match self {
+            Preset::Docker => {
+                default_keys(&mut opts.time, &["time"]);
+                default_keys(&mut opts.message, &["msg", "message", "log"]);
+            },
+            Preset::Journal | Preset::Gelf | Preset::Otel =>
+                default_keys(&mut opts.message, &["msg"]),
+            Preset::Gcp => {
+                default_keys(&mut opts.message, &["msg", "message"]);
+
+                // gcloud writes a pretty printed array of entries
+                opts.multiline = true;
+            },
+            Preset::Ecs => {
+                default_keys(&mut opts.message, &["msg"]);
+
+                if opts.omit.0.is_empty() {
+                    opts.omit = MultOpt::from_keys(ECS_METADATA);
+                }
+            },
+            Preset::Cloudwatch => {
+                default_keys(&mut opts.message, &["msg", "message"]);
+
+                // `aws logs tail` writes the time and log stream before each event
+                if opts.prefix_regex.is_none() {
+                    opts.prefix_regex = Regex::new(r"^(?P<time>\d{4}-\d{2}-\d{2}T\S+) (?P<stream>\S+) ").ok();
+                }
+            },
+            Preset::Bunyan | Preset::Pino => {
+                default_keys(&mut opts.time, &["time"]);
+                default_keys(&mut opts.message, &["msg"]);
+                default_keys(&mut opts.first, &["name", "pid", "hostname"]);
+                default_keys(&mut opts.stack, &["err"]);
+            },
+            Preset::User(preset) => preset.apply_defaults(opts),
+        }
+    }
+
+    /// Turns a line of this format into a plain log record
+    pub (crate) fn normalize(&self, line: Value) -> Value {
+        match self {
+            Preset::Docker => unwrap_docker(line),
+            Preset::Journal => journal_record(line),
+            Preset::Gelf => gelf_record(line),
+            Preset::Cloudwatch => cloudwatch_record(line),
+            Preset::Gcp => gcp_record(line),
+            Preset::Ecs => ecs_record(line),
+            Preset::Otel => otel_record(line),
+            Preset::Bunyan | Preset::Pino => bunyan_record(line),
+            Preset::User(preset) => match &preset.base {
+                Some(base) => base.normalize(line),
+                None => line,
+            },
+        }
+    }
+}
+
+/// The standard name of a syslog level, such as `3` or `warning`. Other
+/// level names are upper cased.
+fn named_level(value: Value) -> Value {
+    match (level::normalize(&value, &[], &[], Scheme::Syslog), value) {
+        (Some(name), _) => Value::String(name),
+        (None, Value::String(name)) => Value::String(name.to_uppercase()),
+        (None, other) => other,
+    }
+}
+
+fn default_keys(keys: &mut Vec<String>, defaults: &[impl AsRef<str>]) {
+    if keys.is_empty() {
+        keys.extend(defaults.iter().map(|key| key.as_ref().to_owned()));
+    }
+}
+
+/// Defaults shared in the presets file, such as:
+///
+/// ```toml
+/// [payments]
+/// base = "pino"
+/// level = "severity"
+/// message = ["event", "msg"]
+/// omit = ["trace_id", "k8s.*"]
+/// level_map = { 35 = "NOTICE" }
+/// theme = "light"
+/// ```
+///
+/// Options given on the command line are used instead of the preset's.
+#[derive(Debug, Clone, Default)]
+pub (crate) struct UserPreset {
+    /// A built-in preset whose format is read and whose defaults are used
+    /// for the settings the preset leaves out
+    base: Option<Preset>,
+    level: Vec<String>,
+    time: Vec<String>,
+    message: Vec<String>,
+    first: Vec<String>,
+    stack: Vec<String>,
+    omit: Vec<String>,
+    level_map: Vec<KeyValue>,
+    theme: Option<Theme>,
+}
+
+impl UserPreset {
+    fn from_toml(value: &toml::Value) -> Result<UserPreset> {
+        let table = value.as_table().ok_or_else(|| anyhow!("Expected a table, got: {}", value))?;
+        let mut preset = UserPreset::default();
+
+        for (key, value) in table {
+            match key.as_str() {
+                "base" => {
+                    let name = value.as_str().ok_or_else(|| anyhow!("Expected a preset name for base, got: {}", value))?;
+                    preset.base = Some(Preset::builtin(name).ok_or_else(|| anyhow!("Unknown built-in preset: {}", name))?);
+                },
+                "level" => preset.level = toml_keys(value)?,
+                "time" => preset.time = toml_keys(value)?,
+                "message" => preset.message = toml_keys(value)?,
+                "first" => preset.first = toml_keys(value)?,
+                "stack" => preset.stack = toml_keys(value)?,
+                "omit" => preset.omit = toml_keys(value)?,
+                "level_map" => {
+                    let levels = value.as_table().ok_or_else(|| anyhow!("Expected a table for level_map"))?;
+                    for (from, to) in levels {
+                        let to = to.as_str().ok_or_else(|| anyhow!("Expected a level name for {}, got: {}", from, to))?;
+                        preset.level_map.push(KeyValue(from.clone(), to.to_owned()));
+                    }
+                },
+                "theme" => {
+                    let theme = value.as_str().ok_or_else(|| anyhow!("Expected a theme name or path, got: {}", value))?;
+                    preset.theme = Some(theme.parse()?);
+                },
+                other => return Err(anyhow!("Unknown preset setting: {}", other)),
+            }
+        }
+
+        Ok(preset)
+    }
+
+    fn apply_defaults(&self, opts: &mut Opt) {
+        default_keys(&mut opts.level, &self.level);
+        default_keys(&mut opts.time, &self.time);
+        default_keys(&mut opts.message, &self.message);
+        default_keys(&mut opts.first, &self.first);
+        default_keys(&mut opts.stack, &self.stack);
+
+        if opts.omit.0.is_empty() && ! self.omit.is_empty() {
+            opts.omit = MultOpt::from_keys(&self.omit);
+        }
+        if opts.level_map.is_empty() {
+            opts.level_map = self.level_map.clone();
+        }
+        if opts.theme.is_none() {
+            opts.theme = self.theme.clone();
+        }
+
+        if let Some(base) = &self.base {
+            base.apply_defaults(opts);
+        }
+    }
+}
+
+/// A key, or an array of keys
+fn toml_keys(value: &toml::Value) -> Result<Vec<String>> {
+    match value {
+        toml::Value::String(key) => Ok(vec![key.clone()]),
+        toml::Value::Array(keys) => keys.iter()
+            .map(|key| key.as_str().map(str::to_owned).ok_or_else(|| anyhow!("Expected a key, got: {}", key)))
+            .collect(),
+        other => Err(anyhow!("Expected a key or an array of keys, got: {}", other)),
+    }
+}
+
+/// The line logged by the container, parsed when it is a json object, with
+/// the time docker received it. Lines written to stderr are tagged with
+/// `stream=stderr`.
+fn unwrap_docker(line: Value) -> Value {
+    let Value::Object(mut wrapper) = line else { return line };
+    let Some(Value::String(log)) = wrapper.remove("log") else { return Value::Object(wrapper) };
+    let log = log.trim_end_matches(['\r', '\n']);
+
+    let mut record = match serde_json::from_str(log) {
+        Ok(Value::Object(record)) => record,
+        _ => Map::from_iter([("log".to_owned(), Value::String(log.to_owned()))]),
+    };
+
+    if let Some(time) = wrapper.remove("time") {
+        record.insert("time".to_owned(), time);
+    }
+
+    if wrapper.get("stream").and_then(Value::as_str) == Some("stderr") {
+        record.insert("stream".to_owned(), Value::String("stderr".to_owned()));
+    }
+
+    Value::Object(record)
+}
+
+/// A record with the level, time and message of a journal entry, and its
+/// unit, pid and host. Other fields journald adds, starting with `_`, are
+/// left out while fields set by the service are kept.
+fn journal_record(line: Value) -> Value {
+    let Value::Object(entry) = line else { return line };
+    let mut record = Map::new();
+
+    for (key, value) in entry {
+        let key = match key.as_str() {
+            "PRIORITY" => {
+                record.insert("level".to_owned(), named_level(value));
+                continue
+            },
+            "__REALTIME_TIMESTAMP" => "time",
+            "MESSAGE" => "msg",
+            "_SYSTEMD_UNIT" => "unit",
+            "_PID" => "pid",
+            "_HOSTNAME" => "host",
+            _ if key.starts_with('_') => continue,
+            _ => &key,
+        };
+
+        record.insert(key.to_owned(), value);
+    }
+
+    Value::Object(record)
+}
+
+/// A record with the level, time and message of a GELF message, and its
+/// custom fields without their leading `_`
+fn gelf_record(line: Value) -> Value {
+    let Value::Object(message) = line else { return line };
+    let mut record = Map::new();
+
+    for (key, value) in message {
+        let key = match key.as_str() {
+            "level" => {
+                record.insert("level".to_owned(), named_level(value));
+                continue
+            },
+            "timestamp" => "time",
+            "short_message" => "msg",
+            "version" => continue,
+            custom => custom.strip_prefix('_').unwrap_or(custom),
+        };
+
+        record.insert(key.to_owned(), value);
+    }
+
+    Value::Object(record)
+}
+
+/// The event logged to CloudWatch, parsed when it is a json object, with
+/// its time and log stream
+fn cloudwatch_record(line: Value) -> Value {
+    let Value::Object(event) = line else { return line };
+
+    if ! event.contains_key("@message") && ! event.contains_key("logStreamName") {
+        return Value::Object(event)
+    }
+
+    let mut record = Map::new();
+
+    for (key, value) in event {
+        let key = match key.as_str() {
+            "@timestamp" | "timestamp" => "time",
+            "@logStream" | "logStreamName" => "stream",
+            "@log" => "log_group",
+            "@ptr" | "eventId" | "ingestionTime" => continue,
+            "@message" | "message" => {
+                match value.as_str().map(|text| serde_json::from_str(text.trim_end())) {
+                    Some(Ok(Value::Object(message))) => record.extend(message),
+                    _ => { record.insert("msg".to_owned(), value); },
+                }
+                continue
+            },
+            other => other,
+        };
+
+        record.insert(key.to_owned(), value);
+    }
+
+    Value::Object(record)
+}
+
+/// The payload of a Cloud Logging entry with its level, time and the
+/// labels of the resource that logged it
+fn gcp_record(line: Value) -> Value {
+    let Value::Object(entry) = line else { return line };
+    let mut record = Map::new();
+    let mut labels = Map::new();
+
+    for (key, value) in entry {
+        let key = match key.as_str() {
+            "severity" => {
+                if let Some(level) = value.as_str().and_then(gcp_level) {
+                    record.insert("level".to_owned(), Value::String(level.to_owned()));
+                }
+                continue
+            },
+            "timestamp" => "time",
+            "textPayload" => "msg",
+            "jsonPayload" => {
+                match value {
+                    Value::Object(payload) => record.extend(payload),
+                    other => { record.insert("msg".to_owned(), other); },
+                }
+                continue
+            },
+            "resource" => {
+                if let Value::Object(mut resource) = value {
+                    if let Some(Value::Object(resource_labels)) = resource.remove("labels") {
+                        labels.extend(resource_labels);
+                    }
+                    if let Some(kind) = resource.remove("type") {
+                        record.insert("resource".to_owned(), kind);
+                    }
+                }
+                continue
+            },
+            "insertId" | "receiveTimestamp" => continue,
+            other => other,
+        };
+
+        record.insert(key.to_owned(), value);
+    }
+
+    for (key, value) in labels {
+        record.entry(key).or_insert(value);
+    }
+
+    Value::Object(record)
+}
+
+/// Level names of Cloud Logging severities. `DEFAULT` has no level.
+fn gcp_level(severity: &str) -> Option<&'static str> {
+    match severity {
+        "DEBUG" => Some("DEBUG"),
+        "INFO" | "NOTICE" => Some("INFO"),
+        "WARNING" => Some("WARN"),
+        "ERROR" => Some("ERROR"),
+        "CRITICAL" | "ALERT" | "EMERGENCY" => Some("FATAL"),
+        _ => None,
+    }
+}
+
+/// Metadata of ECS logs left out by default, about the shipper and the
+/// machine rather than the event
+const ECS_METADATA: &[&str] = &["agent.*", "ecs.*", "input.*", "host.os", "cloud.account", "cloud.instance", "cloud.machine"];
+
+/// An ECS record with its level, time and message. The level may be a
+/// `log.level` key, as ecs-logging writes, or nested in a `log` object.
+fn ecs_record(line: Value) -> Value {
+    let Value::Object(event) = line else { return line };
+    let mut record = Map::new();
+
+    for (key, value) in event {
+        let key = match key.as_str() {
+            "@timestamp" => "time",
+            "log.level" => {
+                record.insert("level".to_owned(), named_level(value));
+                continue
+            },
+            "message" => "msg",
+            "log" => {
+                let Value::Object(mut log) = value else {
+                    record.insert(key, value);
+                    continue
+                };
+
+                if let Some(level) = log.remove("level") {
+                    record.insert("level".to_owned(), named_level(level));
+                }
+                if ! log.is_empty() {
+                    record.insert(key, Value::Object(log));
+                }
+                continue
+            },
+            other => other,
+        };
+
+        record.insert(key.to_owned(), value);
+    }
+
+    Value::Object(record)
+}
+
+/// Length of the trace and span ids written by --preset otel, enough to
+/// tell them apart when correlating lines
+const SHORT_ID_LEN: usize = 8;
+
+/// An OpenTelemetry log record with its level, time, body as message and
+/// attributes as fields. Trace and span ids are shortened.
+fn otel_record(line: Value) -> Value {
+    let Value::Object(log) = line else { return line };
+    let mut record = Map::new();
+
+    let level = match (log.get("severityText"), log.get("severityNumber").and_then(Value::as_u64)) {
+        (Some(Value::String(text)), _) if ! text.is_empty() => Some(named_level(Value::String(text.clone()))),
+        (_, Some(number)) => otel_level(number).map(|name| Value::String(name.to_owned())),
+        _ => None,
+    };
+
+    if let Some(level) = level {
+        record.insert("level".to_owned(), level);
+    }
+
+    for (key, value) in log {
+        let key = match key.as_str() {
+            "timeUnixNano" => "time",
+            "body" => {
+                record.insert("msg".to_owned(), any_value(value));
+                continue
+            },
+            "attributes" => {
+                record.extend(key_values(value));
+                continue
+            },
+            "traceId" | "spanId" => {
+                let id = value.as_str().unwrap_or_default();
+
+                // Ids of records outside of a trace are empty or zeros
+                if id.contains(|c| c != '0') {
+                    let short: String = id.chars().take(SHORT_ID_LEN).collect();
+                    let name = if key == "traceId" { "trace" } else { "span" };
+                    record.insert(name.to_owned(), Value::String(short.to_lowercase()));
+                }
+                continue
+            },
+            "severityText" | "severityNumber" | "observedTimeUnixNano" | "flags" | "droppedAttributesCount" => continue,
+            other => other,
+        };
+
+        record.insert(key.to_owned(), value);
+    }
+
+    Value::Object(record)
+}
+
+/// Level names of OpenTelemetry severity numbers, 1 to 24
+fn otel_level(number: u64) -> Option<&'static str> {
+    match number {
+        1..=4 => Some("TRACE"),
+        5..=8 => Some("DEBUG"),
+        9..=12 => Some("INFO"),
+        13..=16 => Some("WARN"),
+        17..=20 => Some("ERROR"),
+        21..=24 => Some("FATAL"),
+        _ => None,
+    }
+}
+
+/// A plain value for an OTLP `AnyValue` such as `{"stringValue": "GET"}`.
+/// Values that are already plain are left as they are.
+fn any_value(value: Value) -> Value {
+    let Value::Object(wrapper) = value else { return value };
+    if wrapper.len() != 1 {
+        return Value::Object(wrapper)
+    }
+
+    let Some((kind, inner)) = wrapper.into_iter().next() else { return Value::Null };
+    match (kind.as_str(), inner) {
+        ("stringValue" | "boolValue" | "doubleValue" | "bytesValue", inner) => inner,
+        ("intValue", Value::String(n)) => n.parse::<i64>().map(Value::from).unwrap_or(Value::String(n)),
+        ("intValue", inner) => inner,
+        ("arrayValue", Value::Object(mut array)) => match array.remove("values") {
+            Some(Value::Array(values)) => Value::Array(values.into_iter().map(any_value).collect()),
+            _ => Value::Array(vec![]),
+        },
+        ("kvlistValue", Value::Object(mut list)) => Value::Object(key_values(list.remove("values").unwrap_or_default())),
+        (kind, inner) => Value::Object(Map::from_iter([(kind.to_owned(), inner)])),
+    }
+}
+
+/// Fields for OTLP attributes, a list of `{"key": ..., "value": ...}`, or
+/// for attributes that are already an object
+fn key_values(attributes: Value) -> Map<String, Value> {
+    match attributes {
+        Value::Array(list) => list.into_iter()
+            .filter_map(|mut pair| {
+                let key = pair.get("key")?.as_str()?.to_owned();
+                Some((key, any_value(pair.get_mut("value")?.take())))
+            })
+            .collect(),
+        Value::Object(map) => map,
+        _ => Map::new(),
+    }
+}
+
+/// A bunyan or pino record without the version of its format. Levels are
+/// named by the bunyan --level-scheme, which pino shares.
+fn bunyan_record(line: Value) -> Value {
+    let Value::Object(mut record) = line else { return line };
+    record.shift_remove("v");
+
+    Value::Object(record)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use structopt::StructOpt;
+    use super::*;
+
+    #[test]
+    fn docker_test() {
+        let line = json!({"log": "{\"level\": \"INFO\", \"msg\": \"started\"}\n", "stream": "stdout", "time": "2024-05-01T14:00:00.123Z"});
+        assert_eq!(Preset::Docker.normalize(line), json!({"level": "INFO", "msg": "started", "time": "2024-05-01T14:00:00.123Z"}));
+
+        let line = json!({"log": "panic: oops\n", "stream": "stderr", "time": "2024-05-01T14:00:01Z"});
+        assert_eq!(Preset::Docker.normalize(line), json!({"log": "panic: oops", "time": "2024-05-01T14:00:01Z", "stream": "stderr"}));
+
+        let line = json!({"msg": "not docker"});
+        assert_eq!(Preset::Docker.normalize(line.clone()), line);
+    }
+
+    #[test]
+    fn journal_test() {
+        let line = json!({
+            "__CURSOR": "s=abc",
+            "__REALTIME_TIMESTAMP": "1714572000123456",
+            "_BOOT_ID": "f00",
+            "PRIORITY": "3",
+            "_SYSTEMD_UNIT": "nginx.service",
+            "_PID": "812",
+            "SYSLOG_IDENTIFIER": "nginx",
+            "MESSAGE": "worker exited"
+        });
+
+        assert_eq!(Preset::Journal.normalize(line), json!({
+            "time": "1714572000123456",
+            "level": "ERROR",
+            "unit": "nginx.service",
+            "pid": "812",
+            "SYSLOG_IDENTIFIER": "nginx",
+            "msg": "worker exited"
+        }));
+    }
+
+    #[test]
+    fn gelf_test() {
+        let line = json!({
+            "version": "1.1",
+            "host": "web-1",
+            "short_message": "disk almost full",
+            "full_message": "disk almost full\n/var at 95%",
+            "timestamp": 1714572000.123,
+            "level": 4,
+            "_user_id": 9001
+        });
+
+        assert_eq!(Preset::Gelf.normalize(line), json!({
+            "host": "web-1",
+            "msg": "disk almost full",
+            "full_message": "disk almost full\n/var at 95%",
+            "time": 1714572000.123,
+            "level": "WARN",
+            "user_id": 9001
+        }));
+    }
+
+    #[test]
+    fn cloudwatch_test() {
+        let line = json!({
+            "@timestamp": "2024-05-01 14:00:00.123",
+            "@logStream": "api/1f2e",
+            "@message": "{\"level\": \"ERROR\", \"msg\": \"timeout\"}",
+            "@ptr": "CmAKJgoi"
+        });
+
+        assert_eq!(Preset::Cloudwatch.normalize(line), json!({
+            "time": "2024-05-01 14:00:00.123",
+            "stream": "api/1f2e",
+            "level": "ERROR",
+            "msg": "timeout"
+        }));
+
+        let line = json!({"logStreamName": "api/1f2e", "timestamp": 1714572000123i64, "message": "START RequestId: 42", "eventId": "3"});
+
+        assert_eq!(Preset::Cloudwatch.normalize(line), json!({"stream": "api/1f2e", "time": 1714572000123i64, "msg": "START RequestId: 42"}));
+
+        let line = json!({"level": "INFO", "msg": "tailed"});
+        assert_eq!(Preset::Cloudwatch.normalize(line.clone()), line);
+    }
+
+    #[test]
+    fn gcp_test() {
+        let line = json!({
+            "insertId": "abc",
+            "jsonPayload": {"message": "request failed", "status": 503},
+            "resource": {"type": "k8s_container", "labels": {"pod_name": "api-7d9f", "namespace_name": "prod"}},
+            "severity": "WARNING",
+            "timestamp": "2024-05-01T14:00:00.123Z"
+        });
+
+        assert_eq!(Preset::Gcp.normalize(line), json!({
+            "message": "request failed",
+            "status": 503,
+            "resource": "k8s_container",
+            "level": "WARN",
+            "time": "2024-05-01T14:00:00.123Z",
+            "pod_name": "api-7d9f",
+            "namespace_name": "prod"
+        }));
+
+        let line = json!({"textPayload": "started", "severity": "DEFAULT", "timestamp": "2024-05-01T14:00:00Z"});
+        assert_eq!(Preset::Gcp.normalize(line), json!({"msg": "started", "time": "2024-05-01T14:00:00Z"}));
+    }
+
+    #[test]
+    fn ecs_test() {
+        let line = json!({"@timestamp": "2024-05-01T14:00:00.123Z", "log.level": "error", "message": "timeout", "ecs.version": "1.6.0"});
+        assert_eq!(Preset::Ecs.normalize(line), json!({"time": "2024-05-01T14:00:00.123Z", "level": "ERROR", "msg": "timeout", "ecs.version": "1.6.0"}));
+
+        let line = json!({"@timestamp": "2024-05-01T14:00:00Z", "log": {"level": "info", "logger": "api"}, "message": "ok"});
+        assert_eq!(Preset::Ecs.normalize(line), json!({"time": "2024-05-01T14:00:00Z", "level": "INFO", "log": {"logger": "api"}, "msg": "ok"}));
+    }
+
+    #[test]
+    fn otel_test() {
+        let line = json!({
+            "timeUnixNano": "1714572000123456789",
+            "observedTimeUnixNano": "1714572000123456999",
+            "severityNumber": 17,
+            "body": {"stringValue": "request failed"},
+            "attributes": [
+                {"key": "http.method", "value": {"stringValue": "GET"}},
+                {"key": "http.status_code", "value": {"intValue": "503"}}
+            ],
+            "traceId": "5B8EFFF798038103D269B633813FC60C",
+            "spanId": "0000000000000000"
+        });
+
+        assert_eq!(Preset::Otel.normalize(line), json!({
+            "level": "ERROR",
+            "time": "1714572000123456789",
+            "msg": "request failed",
+            "http.method": "GET",
+            "http.status_code": 503,
+            "trace": "5b8efff7"
+        }));
+
+        let line = json!({"severityText": "warning", "body": "slow", "attributes": {"db": "users"}, "spanId": "eee19b7ec3c1b174"});
+        assert_eq!(Preset::Otel.normalize(line), json!({"level": "WARN", "msg": "slow", "db": "users", "span": "eee19b7e"}));
+    }
+
+    #[test]
+    fn bunyan_test() {
+        let line = json!({"name": "api", "hostname": "web-1", "pid": 812, "level": 50, "msg": "failed", "time": "2024-05-01T14:00:00.123Z", "v": 0});
+        assert_eq!(Preset::Bunyan.normalize(line), json!({"name": "api", "hostname": "web-1", "pid": 812, "level": 50, "msg": "failed", "time": "2024-05-01T14:00:00.123Z"}));
+
+        let mut opts = Opt::from_iter(["jaxe", "--first", "req_id"]);
+        Preset::Bunyan.apply_defaults(&mut opts);
+        assert_eq!(opts.first, vec!["req_id"]);
+        assert_eq!(opts.stack, vec!["err"]);
+    }
+
+    #[test]
+    fn pino_test() {
+        let line = json!({"level": 30, "time": 1714572000123i64, "pid": 812, "hostname": "web-1", "msg": "hello"});
+        assert_eq!(Preset::Pino.normalize(line.clone()), line);
+
+        let mut opts = Opt::from_iter(["jaxe", "--preset", "pino"]);
+        Preset::Pino.apply_defaults(&mut opts);
+        assert_eq!(opts.time, vec!["time"]);
+        assert_eq!(opts.message, vec!["msg"]);
+    }
+
+    #[test]
+    fn user_preset_test() {
+        let preset = UserPreset::from_toml(&toml::toml! {
+            base = "pino"
+            level = "severity"
+            message = ["event", "msg"]
+            omit = ["trace_id"]
+            level_map = { 35 = "NOTICE" }
+        }.into()).unwrap();
+
+        let mut opts = Opt::from_iter(["jaxe", "-m", "text"]);
+        preset.apply_defaults(&mut opts);
+
+        assert_eq!(opts.level, vec!["severity"]);
+        assert_eq!(opts.message, vec!["text"]);
+        assert_eq!(opts.time, vec!["time"]);
+        assert_eq!(opts.omit.to_string(), r#"["trace_id"]"#);
+        assert_eq!(opts.level_map, vec![KeyValue("35".into(), "NOTICE".into())]);
+        assert_eq!(opts.stack, vec!["err"]);
+    }
+
+    #[test]
+    fn user_preset_invalid_test() {
+        assert!(UserPreset::from_toml(&toml::toml! { base = "nope" }.into()).is_err());
+        assert!(UserPreset::from_toml(&toml::toml! { colour = "red" }.into()).is_err());
+        assert!(UserPreset::from_toml(&toml::toml! { level = 3 }.into()).is_err());
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, IsTerminal, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::anyhow;
+use serde_json::Value;
+use terminal_size::Width;
+
+use crate::Opt;
+use crate::level;
+
+/// How often the rate is drawn, also the length of each bar
+const REFRESH: Duration = Duration::from_secs(1);
+
+/// Bars of the sparkline, one per second
+const HISTORY: usize = 60;
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Lines written per second over the last minute, drawn on stderr as a
+/// sparkline with the rate of each level, see --rate. On a terminal the
+/// rate is a status line that is redrawn in place, and cleared before
+/// lines are printed on stdout.
+#[derive(Clone)]
+pub (crate) struct Rate {
+    window: Arc<Mutex<Window>>,
+}
+
+#[derive(Debug, Default)]
+pub (crate) struct Window {
+    /// Lines of each second, oldest first. The last one is the current
+    /// second, which is not drawn until it is over.
+    seconds: VecDeque<Second>,
+    /// Whether the status line is on the terminal
+    shown: bool,
+}
+
+#[derive(Debug, Default)]
+struct Second {
+    lines: u64,
+    levels: BTreeMap<String, u64>,
+}
+
+impl Rate {
+    pub (crate) fn start() -> Rate {
+        let window = Arc::new(Mutex::new(Window { seconds: VecDeque::from([Second::default()]), shown: false }));
+        let terminal = io::stderr().is_terminal();
+
+        let drawn = window.clone();
+        thread::spawn(move || loop {
+            thread::sleep(REFRESH);
+
+            let Ok(mut window) = drawn.lock() else { return };
+            window.seconds.push_back(Second::default());
+            if window.seconds.len() > HISTORY + 1 {
+                window.seconds.pop_front();
+            }
+
+            let mut status = window.render();
+            let mut stderr = io::stderr().lock();
+
+            let written = if terminal {
+                if let Some((Width(width), _)) = terminal_size::terminal_size() {
+                    status = status.chars().take(width as usize).collect();
+                }
+                window.shown = true;
+                write!(stderr, "\r\x1b[2K{}", status)
+            } else {
+                writeln!(stderr, "{}", status)
+            };
+
+            if written.and_then(|_| stderr.flush()).is_err() {
+                return
+            }
+        });
+
+        Rate { window }
+    }
+
+    /// Counts `line` in the current second
+    pub (crate) fn record(&self, line: &Value, opts: &Opt) {
+        let Ok(mut window) = self.window.lock() else { return };
+        let Some(second) = window.seconds.back_mut() else { return };
+
+        second.lines += 1;
+        if let Some(level) = opts.level.iter().find_map(|key| line.get(key)?.as_str()) {
+            *second.levels.entry(level.to_owned()).or_default() += 1;
+        }
+    }
+
+    /// Clears the status line and keeps it from being drawn until the
+    /// returned guard is dropped, so lines can be printed on stdout
+    pub (crate) fn suspend(&self) -> Option<MutexGuard<'_, Window>> {
+        let mut window = self.window.lock().ok()?;
+
+        if window.shown {
+            let mut stderr = io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+            window.shown = false;
+        }
+
+        Some(window)
+    }
+}
+
+impl Window {
+    /// The sparkline of the seconds that are over, with the rate of the last
+    /// one, in total and for each level, most severe first
+    fn render(&self) -> String {
+        let over: Vec<&Second> = self.seconds.iter().take(self.seconds.len().saturating_sub(1)).collect();
+        let max = over.iter().map(|second| second.lines).max().unwrap_or(0);
+
+        let mut status: String = over.iter().map(|second| match second.lines {
+            0 => ' ',
+            lines => BARS[((lines * BARS.len() as u64).div_ceil(max.max(1)) as usize).clamp(1, BARS.len()) - 1],
+        }).collect();
+
+        let Some(last) = over.last() else { return status };
+        status.push_str(&format!(" {}/s", last.lines));
+
+        let mut levels: Vec<(&String, &u64)> = last.levels.iter().collect();
+        levels.sort_by_key(|(level, _)| std::cmp::Reverse(level::severity(level)));
+        for (level, lines) in levels {
+            status.push_str(&format!(" {} {}/s", level, lines));
+        }
+
+        status
+    }
+}
+
+/// A number of lines per second, minute or hour, such as `200/s`, given to
+/// --rate-limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub (crate) struct RateLimit {
+    lines: u64,
+    per: Duration,
+}
+
+impl FromStr for RateLimit {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let (lines, per) = src.split_once('/').ok_or_else(|| anyhow!("Expected lines/s, lines/m or lines/h, got {}", src))?;
+
+        let per = match per {
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(3600),
+            _ => return Err(anyhow!("Expected s, m or h after /, got {}", per)),
+        };
+
+        match lines.parse() {
+            Ok(lines) if lines > 0 => Ok(RateLimit { lines, per }),
+            _ => Err(anyhow!("Expected a number of lines greater than 0, got {}", lines)),
+        }
+    }
+}
+
+/// Drops the lines past a --rate-limit in each window of time, counting them
+/// by level to summarize them once the window is over
+#[derive(Debug)]
+pub (crate) struct Limiter {
+    limit: RateLimit,
+    /// When the current window started
+    start: Instant,
+    written: u64,
+    dropped: u64,
+    dropped_levels: BTreeMap<String, u64>,
+}
+
+impl Limiter {
+    pub (crate) fn new(limit: RateLimit) -> Limiter {
+        Limiter { limit, start: Instant::now(), written: 0, dropped: 0, dropped_levels: BTreeMap::new() }
+    }
+
+    /// Whether `line`, read `now`, can be written. When a new window starts,
+    /// also returns the summary of the lines dropped in the last one.
+    pub (crate) fn admit(&mut self, line: &Value, opts: &Opt, now: Instant) -> (bool, Option<String>) {
+        let mut summary = None;
+
+        if now.duration_since(self.start) >= self.limit.per {
+            summary = self.flush();
+            self.start = now;
+            self.written = 0;
+        }
+
+        if self.written < self.limit.lines {
+            self.written += 1;
+            return (true, summary)
+        }
+
+        self.dropped += 1;
+        if let Some(level) = opts.level.iter().find_map(|key| line.get(key)?.as_str()) {
+            *self.dropped_levels.entry(level.to_owned()).or_default() += 1;
+        }
+
+        (false, summary)
+    }
+
+    /// The summary of the lines dropped since the last one, such as
+    /// `dropped 120 lines: ERROR 118, INFO 2`
+    pub (crate) fn flush(&mut self) -> Option<String> {
+        if self.dropped == 0 {
+            return None
+        }
+
+        let mut levels: Vec<(String, u64)> = std::mem::take(&mut self.dropped_levels).into_iter().collect();
+        levels.sort_by_key(|(level, _)| std::cmp::Reverse(level::severity(level)));
+
+        let mut summary = format!("dropped {} line{}", self.dropped, if self.dropped == 1 { "" } else { "s" });
+        if ! levels.is_empty() {
+            let levels: Vec<String> = levels.iter().map(|(level, lines)| format!("{} {}", level, lines)).collect();
+            summary.push_str(&format!(": {}", levels.join(", ")));
+        }
+
+        self.dropped = 0;
+        Some(summary)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use structopt::StructOpt;
+    use super::*;
+
+    #[test]
+    fn render_test() {
+        let second = |lines, levels: &[(&str, u64)]| Second { lines, levels: levels.iter().map(|(level, n)| (level.to_string(), *n)).collect() };
+
+        let window = Window {
+            seconds: VecDeque::from([
+                second(8, &[("INFO", 8)]),
+                second(0, &[]),
+                second(1, &[("INFO", 1)]),
+                second(4, &[("INFO", 3), ("ERROR", 1)]),
+                // The current second
+                second(100, &[]),
+            ]),
+            shown: false,
+        };
+
+        assert_eq!(window.render(), "█ ▁▄ 4/s ERROR 1/s INFO 3/s");
+        assert_eq!(Window::default().render(), "");
+    }
+
+    #[test]
+    fn rate_limit_test() {
+        assert_eq!("200/s".parse::<RateLimit>().unwrap(), RateLimit { lines: 200, per: Duration::from_secs(1) });
+        assert_eq!("5/m".parse::<RateLimit>().unwrap(), RateLimit { lines: 5, per: Duration::from_secs(60) });
+        assert!("200".parse::<RateLimit>().is_err());
+        assert!("0/s".parse::<RateLimit>().is_err());
+        assert!("200/d".parse::<RateLimit>().is_err());
+
+        let opts = Opt::from_iter(["jaxe", "-l", "level"]);
+        let mut limiter = Limiter::new("2/s".parse().unwrap());
+        let start = limiter.start;
+        let mut admit = |level: &str, millis: u64| limiter.admit(&json!({"level": level}), &opts, start + Duration::from_millis(millis));
+
+        assert_eq!(admit("INFO", 0), (true, None));
+        assert_eq!(admit("INFO", 100), (true, None));
+        assert_eq!(admit("INFO", 200), (false, None));
+        assert_eq!(admit("ERROR", 300), (false, None));
+        assert_eq!(admit("ERROR", 400), (false, None));
+        assert_eq!(admit("ERROR", 1000), (true, Some("dropped 3 lines: ERROR 2, INFO 1".to_owned())));
+        assert_eq!(admit("INFO", 1100), (true, None));
+        assert_eq!(admit("INFO", 1200), (false, None));
+        assert_eq!(limiter.flush(), Some("dropped 1 line: INFO 1".to_owned()));
+        assert_eq!(limiter.flush(), None);
+    }
+}
@@ -0,0 +1,80 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset};
+use serde_json::Value;
+
+use crate::Opt;
+use crate::timestamp;
+
+/// Paces lines by the time between them, so recorded logs are written as if
+/// they were live, see --replay
+pub (crate) struct Replay {
+    speed: f64,
+    /// Time of the first line with a time, and when it was written
+    start: Option<(DateTime<FixedOffset>, Instant)>,
+}
+
+/// Parses --speed, a factor greater than 0
+pub (crate) fn parse_speed(input: &str) -> Result<f64> {
+    match input.parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(speed),
+        _ => Err(anyhow!("Expected a speed greater than 0, got: {}", input)),
+    }
+}
+
+impl Replay {
+    pub (crate) fn new(speed: f64) -> Replay {
+        Replay { speed, start: None }
+    }
+
+    /// How long to wait before writing a line of time `time`, read `now`.
+    /// Waits are measured from the first line rather than the previous one,
+    /// so they don't add up to a drift.
+    fn delay(&mut self, time: DateTime<FixedOffset>, now: Instant) -> Duration {
+        let (first, started) = *self.start.get_or_insert((time, now));
+
+        let Ok(offset) = time.signed_duration_since(first).to_std() else {
+            return Duration::ZERO
+        };
+
+        // Waits too long to represent, at tiny speeds, are as good as forever
+        Duration::try_from_secs_f64(offset.as_secs_f64() / self.speed).ok()
+            .and_then(|wait| started.checked_add(wait))
+            .map_or(Duration::MAX, |due| due.saturating_duration_since(now))
+    }
+
+    /// Waits until `line` is due. Lines without a time and lines older than
+    /// the first one are not waited for.
+    pub (crate) fn wait(&mut self, line: &Value, opts: &Opt) {
+        if let Some(time) = timestamp::of_line(line, opts) {
+            thread::sleep(self.delay(time, Instant::now()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn replay_test() {
+        let time = |s: &str| DateTime::parse_from_rfc3339(s).unwrap();
+        let mut replay = Replay::new(10.0);
+        let now = Instant::now();
+
+        assert_eq!(replay.delay(time("2024-03-01T10:00:00Z"), now), Duration::ZERO);
+        assert_eq!(replay.delay(time("2024-03-01T10:00:05Z"), now), Duration::from_millis(500));
+        // Half a second later, the next line is due 1s after the first one
+        assert_eq!(replay.delay(time("2024-03-01T10:00:10Z"), now + Duration::from_millis(500)), Duration::from_millis(500));
+        assert_eq!(replay.delay(time("2024-03-01T09:59:00Z"), now), Duration::ZERO);
+
+        let mut replay = Replay::new(f64::MIN_POSITIVE);
+        assert_eq!(replay.delay(time("2024-03-01T10:00:00Z"), now), Duration::ZERO);
+        assert_eq!(replay.delay(time("2024-03-01T10:00:01Z"), now), Duration::MAX);
+
+        assert!(parse_speed("0.5").is_ok());
+        assert!(parse_speed("0").is_err());
+        assert!(parse_speed("fast").is_err());
+    }
+}
@@ -0,0 +1,1178 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset, Local, Offset, SecondsFormat};
+use serde_json::{json, Map, Value};
+use unicode_width::UnicodeWidthStr;
+
+use crate::Opt;
+use crate::level;
+use crate::parser::{self, EPath};
+use crate::timestamp;
+use crate::transform;
+
+/// Tallies the lines that passed the filters, to write a report of them
+/// instead of the lines, such as --stats
+pub (crate) trait Report: Send {
+    fn record(&mut self, line: &Value, opts: &Opt) -> Result<()>;
+
+    /// Called for each line of the input, before parsing it
+    fn line_read(&mut self) {}
+
+    /// Called for lines that are neither json nor another known format
+    fn parse_failed(&mut self) {}
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()>;
+}
+
+/// The reports asked for by the options. They are written at the end of the
+/// input, and every --report-interval when one is given.
+#[derive(Default)]
+pub (crate) struct Reports {
+    reports: Arc<Mutex<Vec<Box<dyn Report>>>>,
+}
+
+impl Reports {
+    pub (crate) fn from_opts(opts: &Opt) -> Result<Reports> {
+        let mut reports: Vec<Box<dyn Report>> = vec![];
+
+        if opts.stats {
+            reports.push(Box::new(LevelStats::default()));
+        }
+
+        if opts.summary {
+            reports.push(Box::new(Summary::default()));
+        }
+
+        if ! opts.group_by.is_empty() || ! opts.agg.is_empty() {
+            reports.push(Box::new(GroupBy::new(&opts.group_by, opts.agg.clone())?));
+        }
+
+        for top in &opts.top {
+            reports.push(Box::new(TopValues { top: top.clone(), counts: HashMap::new() }));
+        }
+
+        if opts.keys {
+            reports.push(Box::new(Keys::default()));
+        }
+
+        if opts.infer_schema {
+            reports.push(Box::new(Schema::default()));
+        }
+
+        if opts.cardinality {
+            reports.push(Box::new(Cardinality::default()));
+        }
+
+        if let Some(interval) = opts.histogram {
+            reports.push(Box::new(Histogram::new(interval)));
+        }
+
+        let reports = Reports { reports: Arc::new(Mutex::new(reports)) };
+
+        if let Some(interval) = opts.report_interval.filter(|_| ! reports.is_empty()) {
+            let written = reports.reports.clone();
+            thread::spawn(move || loop {
+                thread::sleep(interval);
+
+                let mut stdout = io::stdout().lock();
+                let _ = writeln!(stdout, "──── {} ────", Local::now().format("%H:%M:%S"));
+                if let Err(err) = write(&written, &mut stdout) {
+                    log::debug!("Could not write reports: {}", err);
+                    return
+                }
+            });
+        }
+
+        Ok(reports)
+    }
+
+    pub (crate) fn is_empty(&self) -> bool {
+        self.reports.lock().map_or(true, |reports| reports.is_empty())
+    }
+
+    pub (crate) fn record(&self, line: &Value, opts: &Opt) -> Result<()> {
+        let mut reports = self.reports.lock().map_err(|_| anyhow!("Reports were poisoned"))?;
+        reports.iter_mut().try_for_each(|report| report.record(line, opts))
+    }
+
+    pub (crate) fn line_read(&self) {
+        if let Ok(mut reports) = self.reports.lock() {
+            reports.iter_mut().for_each(|report| report.line_read());
+        }
+    }
+
+    pub (crate) fn parse_failed(&self) {
+        if let Ok(mut reports) = self.reports.lock() {
+            reports.iter_mut().for_each(|report| report.parse_failed());
+        }
+    }
+
+    pub (crate) fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        write(&self.reports, out)
+    }
+}
+
+/// Writes the reports one after the other, separated by an empty line
+fn write(reports: &Mutex<Vec<Box<dyn Report>>>, out: &mut dyn Write) -> io::Result<()> {
+    let reports = reports.lock().map_err(|_| io::Error::other("Reports were poisoned"))?;
+
+    for (i, report) in reports.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        report.write(out)?;
+    }
+
+    out.flush()
+}
+
+/// Lines per level, see --stats
+#[derive(Debug, Default)]
+struct LevelStats {
+    levels: BTreeMap<String, u64>,
+    /// Lines without a level
+    unleveled: u64,
+}
+
+impl Report for LevelStats {
+    fn record(&mut self, line: &Value, opts: &Opt) -> Result<()> {
+        match opts.level.iter().find_map(|key| line.get(key)) {
+            Some(Value::String(level)) => *self.levels.entry(level.clone()).or_default() += 1,
+            Some(other) if ! other.is_null() => *self.levels.entry(other.to_string()).or_default() += 1,
+            _ => self.unleveled += 1,
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        let total = self.levels.values().sum::<u64>() + self.unleveled;
+
+        // Most severe first, then levels that are not standard names
+        let mut rows: Vec<(&str, u64)> = self.levels.iter().map(|(level, count)| (level.as_str(), *count)).collect();
+        rows.sort_by_key(|(level, _)| std::cmp::Reverse(level::severity(level)));
+        if self.unleveled > 0 {
+            rows.push(("-", self.unleveled));
+        }
+
+        let width = rows.iter().map(|(level, _)| level.width()).max().unwrap_or(0).max("LEVEL".len());
+
+        writeln!(out, "{} {:>10} {:>7}", pad("LEVEL", width, false), "LINES", "%")?;
+        for (level, count) in rows {
+            writeln!(out, "{} {:>10} {:>6.1}%", pad(level, width, false), count, 100.0 * count as f64 / total as f64)?;
+        }
+        writeln!(out, "{} {:>10}", pad("TOTAL", width, false), total)
+    }
+}
+
+/// Error messages written by --summary
+const SUMMARY_ERRORS: usize = 5;
+
+/// An overview of the input, see --summary
+#[derive(Debug, Default)]
+struct Summary {
+    lines_read: u64,
+    parse_failures: u64,
+    matches: u64,
+    first: Option<DateTime<FixedOffset>>,
+    last: Option<DateTime<FixedOffset>>,
+    levels: LevelStats,
+    /// Messages of lines with a level of at least ERROR
+    errors: HashMap<String, u64>,
+}
+
+impl Report for Summary {
+    fn record(&mut self, line: &Value, opts: &Opt) -> Result<()> {
+        self.matches += 1;
+        self.levels.record(line, opts)?;
+
+        if let Some(time) = timestamp::of_line(line, opts) {
+            self.first = Some(self.first.map_or(time, |first| first.min(time)));
+            self.last = Some(self.last.map_or(time, |last| last.max(time)));
+        }
+
+        let level = opts.level.iter().find_map(|key| line.get(key)?.as_str());
+        if level.and_then(level::severity) >= level::severity("ERROR") {
+            if let Some(message) = message_keys(opts).iter().find_map(|key| line.get(*key)) {
+                *self.errors.entry(text(Some(message))).or_default() += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn line_read(&mut self) {
+        self.lines_read += 1;
+    }
+
+    fn parse_failed(&mut self) {
+        self.parse_failures += 1;
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        let time = |time: Option<DateTime<FixedOffset>>| time.map_or("-".to_owned(), |time| time.to_rfc3339_opts(SecondsFormat::AutoSi, true));
+        let span = match (self.first, self.last) {
+            (Some(first), Some(last)) => timestamp::span(last.signed_duration_since(first)),
+            _ => "-".to_owned(),
+        };
+
+        writeln!(out, "Lines read      {}", self.lines_read)?;
+        writeln!(out, "Parse failures  {}", self.parse_failures)?;
+        writeln!(out, "Matches         {}", self.matches)?;
+        writeln!(out, "First line      {}", time(self.first))?;
+        writeln!(out, "Last line       {}", time(self.last))?;
+        writeln!(out, "Span            {}", span)?;
+
+        if self.matches > 0 {
+            writeln!(out)?;
+            self.levels.write(out)?;
+        }
+
+        if ! self.errors.is_empty() {
+            let mut errors: Vec<(&String, &u64)> = self.errors.iter().collect();
+            errors.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+
+            let rows = errors.into_iter()
+                .take(SUMMARY_ERRORS)
+                .map(|(message, count)| vec![count.to_string(), message.clone()])
+                .collect();
+
+            writeln!(out)?;
+            write_table(out, vec!["COUNT".to_owned(), "ERROR MESSAGE".to_owned()], rows, &[true, false])?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The --message keys, or the usual message keys when none are given
+pub (crate) fn message_keys(opts: &Opt) -> Vec<&str> {
+    if opts.message.is_empty() {
+        vec!["msg", "message"]
+    } else {
+        opts.message.iter().map(String::as_str).collect()
+    }
+}
+
+/// A statistic of the values of a numeric field, see --agg
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stat {
+    Sum,
+    Avg,
+    Min,
+    Max,
+    /// Such as 95 for p95
+    Percentile(f64),
+}
+
+impl FromStr for Stat {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "sum" => Ok(Stat::Sum),
+            "avg" | "mean" => Ok(Stat::Avg),
+            "min" => Ok(Stat::Min),
+            "max" => Ok(Stat::Max),
+            _ => match src.strip_prefix('p').and_then(|p| p.parse::<f64>().ok()) {
+                Some(p) if (0.0..=100.0).contains(&p) => Ok(Stat::Percentile(p)),
+                _ => Err(anyhow!("Unknown statistic: {}, expected sum, avg, min, max or a percentile such as p95", src)),
+            },
+        }
+    }
+}
+
+impl Stat {
+    fn name(&self) -> String {
+        match self {
+            Stat::Sum => "sum".to_owned(),
+            Stat::Avg => "avg".to_owned(),
+            Stat::Min => "min".to_owned(),
+            Stat::Max => "max".to_owned(),
+            Stat::Percentile(p) => format!("p{}", p),
+        }
+    }
+
+    /// The statistic of `values`, which are sorted
+    fn compute(&self, values: &[f64]) -> Option<f64> {
+        if values.is_empty() {
+            return None
+        }
+
+        let sum = || values.iter().sum::<f64>();
+
+        Some(match self {
+            Stat::Sum => sum(),
+            Stat::Avg => sum() / values.len() as f64,
+            Stat::Min => values[0],
+            Stat::Max => values[values.len() - 1],
+            // Nearest rank
+            Stat::Percentile(p) => {
+                let rank = (p / 100.0 * values.len() as f64).ceil() as usize;
+                values[rank.clamp(1, values.len()) - 1]
+            },
+        })
+    }
+}
+
+/// Statistics of a numeric field, such as `duration_ms:sum,avg,p95`, see
+/// --agg
+#[derive(Debug, Clone)]
+pub (crate) struct Aggregation {
+    field: String,
+    path: EPath,
+    stats: Vec<Stat>,
+}
+
+impl FromStr for Aggregation {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let (field, stats) = src.rsplit_once(':')
+            .ok_or_else(|| anyhow!("Expected a field and statistics, as in duration_ms:sum,avg,p95, got {}", src))?;
+
+        Ok(Aggregation {
+            field: field.to_owned(),
+            path: parser::parse_path(field)?,
+            stats: stats.split(',').map(str::parse).collect::<Result<_>>()?,
+        })
+    }
+}
+
+/// Lines per combination of values of some fields, most frequent first, with
+/// statistics of numeric fields in each, see --group-by and --agg
+struct GroupBy {
+    /// The fields as given, with their paths
+    fields: Vec<(String, EPath)>,
+    aggregations: Vec<Aggregation>,
+    groups: HashMap<Vec<String>, Group>,
+}
+
+#[derive(Debug, Default)]
+struct Group {
+    count: u64,
+    /// Values of each aggregated field
+    values: Vec<Vec<f64>>,
+}
+
+impl GroupBy {
+    fn new(fields: &[String], aggregations: Vec<Aggregation>) -> Result<GroupBy> {
+        let fields = fields.iter()
+            .map(|field| Ok((field.clone(), parser::parse_path(field)?)))
+            .collect::<Result<_>>()?;
+
+        Ok(GroupBy { fields, aggregations, groups: HashMap::new() })
+    }
+}
+
+impl Report for GroupBy {
+    fn record(&mut self, line: &Value, _opts: &Opt) -> Result<()> {
+        let key = self.fields.iter().map(|(_, path)| text(path.lookup(line))).collect();
+        let group = self.groups.entry(key).or_insert_with(|| Group { count: 0, values: vec![vec![]; self.aggregations.len()] });
+
+        group.count += 1;
+        for (aggregation, values) in self.aggregations.iter().zip(&mut group.values) {
+            values.extend(aggregation.path.lookup(line).and_then(number));
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut groups: Vec<(&Vec<String>, &Group)> = self.groups.iter().collect();
+        groups.sort_by(|(a, a_group), (b, b_group)| b_group.count.cmp(&a_group.count).then_with(|| a.cmp(b)));
+
+        let stats = || self.aggregations.iter().enumerate()
+            .flat_map(|(i, aggregation)| aggregation.stats.iter().map(move |stat| (i, aggregation, stat)));
+
+        let mut header = vec!["COUNT".to_owned()];
+        header.extend(self.fields.iter().map(|(name, _)| name.clone()));
+        header.extend(stats().map(|(_, aggregation, stat)| format!("{}({})", stat.name(), aggregation.field)));
+
+        let mut right = vec![true];
+        right.extend(self.fields.iter().map(|_| false));
+        right.extend(stats().map(|_| true));
+
+        let rows = groups.into_iter().map(|(key, group)| {
+            let sorted: Vec<Vec<f64>> = group.values.iter().map(|values| {
+                let mut values = values.clone();
+                values.sort_by(f64::total_cmp);
+                values
+            }).collect();
+
+            let mut row = vec![group.count.to_string()];
+            row.extend(key.iter().cloned());
+            row.extend(stats().map(|(i, _, stat)| stat.compute(&sorted[i]).map_or("-".to_owned(), format_number)));
+            row
+        }).collect();
+
+        write_table(out, header, rows, &right)
+    }
+}
+
+/// A field and how many of its values to report, such as `http_path:5`, see
+/// --top
+#[derive(Debug, Clone)]
+pub (crate) struct Top {
+    field: String,
+    path: EPath,
+    count: usize,
+}
+
+impl FromStr for Top {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let (field, count) = match src.rsplit_once(':') {
+            Some((field, count)) => (field, count.parse().map_err(|_| anyhow!("Expected a number of values after :, got {}", count))?),
+            None => (src, 10),
+        };
+
+        Ok(Top { field: field.to_owned(), path: parser::parse_path(field)?, count })
+    }
+}
+
+/// The most frequent values of a field, see --top
+struct TopValues {
+    top: Top,
+    counts: HashMap<String, u64>,
+}
+
+impl Report for TopValues {
+    fn record(&mut self, line: &Value, _opts: &Opt) -> Result<()> {
+        if let Some(value) = self.top.path.lookup(line).filter(|value| ! value.is_null()) {
+            *self.counts.entry(text(Some(value))).or_default() += 1;
+        }
+        Ok(())
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut counts: Vec<(&String, &u64)> = self.counts.iter().collect();
+        counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+
+        let rows = counts.into_iter()
+            .take(self.top.count)
+            .map(|(value, count)| vec![count.to_string(), value.clone()])
+            .collect();
+
+        write_table(out, vec!["COUNT".to_owned(), self.top.field.clone()], rows, &[true, false])
+    }
+}
+
+/// Widest bar of --histogram
+const BAR_WIDTH: u64 = 40;
+
+/// Runs of empty buckets longer than this are written as a single row
+const MAX_EMPTY_ROWS: i64 = 5;
+
+/// Lines per interval of their time as a bar chart, see --histogram
+struct Histogram {
+    /// Length of the buckets, in milliseconds
+    interval: i64,
+    /// Lines per bucket, by the start of the bucket in milliseconds since
+    /// the epoch
+    buckets: BTreeMap<i64, u64>,
+    /// Offset of the first line with a time, converted to --time-zone.
+    /// Buckets are aligned to it and written with it.
+    offset: Option<FixedOffset>,
+    /// Lines without a time
+    untimed: u64,
+}
+
+impl Histogram {
+    fn new(interval: std::time::Duration) -> Histogram {
+        Histogram { interval: (interval.as_millis() as i64).max(1), buckets: BTreeMap::new(), offset: None, untimed: 0 }
+    }
+}
+
+impl Report for Histogram {
+    fn record(&mut self, line: &Value, opts: &Opt) -> Result<()> {
+        let Some(time) = timestamp::of_line(line, opts) else {
+            self.untimed += 1;
+            return Ok(())
+        };
+
+        let offset = *self.offset.get_or_insert_with(|| timestamp::in_zone(time, opts.time_zone).offset().fix());
+        let shift = offset.local_minus_utc() as i64 * 1000;
+        let start = (time.timestamp_millis() + shift).div_euclid(self.interval) * self.interval - shift;
+
+        *self.buckets.entry(start).or_default() += 1;
+        Ok(())
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        let (Some((&first, _)), Some(offset)) = (self.buckets.first_key_value(), self.offset) else {
+            return writeln!(out, "No lines with a time")
+        };
+
+        let format = match self.interval {
+            i if i % 60_000 == 0 => "%Y-%m-%d %H:%M",
+            i if i % 1000 == 0 => "%Y-%m-%d %H:%M:%S",
+            _ => "%Y-%m-%d %H:%M:%S%.3f",
+        };
+
+        let max = self.buckets.values().copied().max().unwrap_or(0);
+        let count_width = max.to_string().len();
+
+        let row = |out: &mut dyn Write, start: i64, count: u64| {
+            let label = DateTime::from_timestamp_millis(start).map(|time| time.with_timezone(&offset).format(format).to_string()).unwrap_or_default();
+            let bar = "█".repeat((count * BAR_WIDTH).div_ceil(max) as usize);
+
+            writeln!(out, "{}", format!("{} {:>count_width$} {}", label, count, bar).trim_end())
+        };
+
+        // Empty buckets are written too, so gaps show, but long runs of them
+        // are written as one row
+        let mut next = first;
+        for (&start, &count) in &self.buckets {
+            let empty = (start - next) / self.interval;
+
+            if empty > MAX_EMPTY_ROWS {
+                writeln!(out, "… {} empty", empty)?;
+            } else {
+                for i in 0..empty {
+                    row(out, next + i * self.interval, 0)?;
+                }
+            }
+
+            row(out, start, count)?;
+            next = start + self.interval;
+        }
+
+        if self.untimed > 0 {
+            writeln!(out, "Lines without a time: {}", self.untimed)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Bits of the hash indexing the registers of a `Sketch`
+const SKETCH_BITS: u32 = 12;
+
+/// An approximate count of distinct values with a HyperLogLog sketch, using
+/// 4KiB whatever the number of values. The standard error is about 1.6%.
+#[derive(Debug)]
+struct Sketch {
+    registers: Vec<u8>,
+}
+
+impl Default for Sketch {
+    fn default() -> Sketch {
+        Sketch { registers: vec![0; 1 << SKETCH_BITS] }
+    }
+}
+
+impl Sketch {
+    fn insert(&mut self, value: &str) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let index = (hash >> (64 - SKETCH_BITS)) as usize;
+        // Position of the first set bit after the index bits, the guard bit
+        // keeps it within the hash
+        let rank = ((hash << SKETCH_BITS) | (1 << (SKETCH_BITS - 1))).leading_zeros() as u8 + 1;
+
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&rank| 2f64.powi(-(rank as i32))).sum();
+        let estimate = alpha * m * m / sum;
+
+        // Linear counting is more accurate for few values
+        let empty = self.registers.iter().filter(|&&rank| rank == 0).count();
+        if estimate <= 2.5 * m && empty > 0 {
+            m * (m / empty as f64).ln()
+        } else {
+            estimate
+        }
+    }
+}
+
+/// Approximate number of distinct values of each key, with nested keys
+/// flattened, see --cardinality
+#[derive(Debug, Default)]
+struct Cardinality {
+    /// Lines with each key, and its values
+    keys: BTreeMap<String, (u64, Sketch)>,
+}
+
+impl Report for Cardinality {
+    fn record(&mut self, line: &Value, _opts: &Opt) -> Result<()> {
+        let Value::Object(obj) = line else { return Ok(()) };
+
+        for (key, value) in transform::flatten(obj.clone()) {
+            if value.is_null() {
+                continue
+            }
+
+            let (lines, sketch) = self.keys.entry(key).or_default();
+            *lines += 1;
+            sketch.insert(&text(Some(&value)));
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut keys: Vec<(&String, u64, u64)> = self.keys.iter()
+            .map(|(key, (lines, sketch))| (key, sketch.estimate().round() as u64, *lines))
+            .collect();
+        keys.sort_by(|(a, a_distinct, _), (b, b_distinct, _)| b_distinct.cmp(a_distinct).then_with(|| a.cmp(b)));
+
+        let rows = keys.into_iter()
+            .map(|(key, distinct, lines)| vec![distinct.to_string(), lines.to_string(), key.clone()])
+            .collect();
+
+        write_table(out, vec!["DISTINCT".to_owned(), "LINES".to_owned(), "KEY".to_owned()], rows, &[true, true, false])
+    }
+}
+
+/// Every key seen, with nested keys flattened, how many lines had it and
+/// the types of its values, see --keys. Keys of objects in arrays are
+/// written as in `items[].id`.
+#[derive(Debug, Default)]
+struct Keys {
+    keys: BTreeMap<String, (u64, BTreeSet<&'static str>)>,
+}
+
+impl Keys {
+    fn visit(&mut self, path: String, value: &Value) {
+        match value {
+            Value::Object(obj) if ! obj.is_empty() =>
+                for (key, value) in obj {
+                    let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                    self.visit(path, value);
+                },
+            value => {
+                if let Value::Array(items) = value {
+                    let mut nested = Keys::default();
+                    for item in items.iter().filter(|item| item.is_object()) {
+                        nested.visit(format!("{}[]", path), item);
+                    }
+                    // Counted once per line, not per item
+                    for (key, (_, types)) in nested.keys {
+                        let (lines, seen) = self.keys.entry(key).or_default();
+                        *lines += 1;
+                        seen.extend(types);
+                    }
+                }
+
+                let (lines, types) = self.keys.entry(path).or_default();
+                *lines += 1;
+                types.insert(type_name(value));
+            },
+        }
+    }
+}
+
+impl Report for Keys {
+    fn record(&mut self, line: &Value, _opts: &Opt) -> Result<()> {
+        if line.is_object() {
+            self.visit(String::new(), line);
+        }
+        Ok(())
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        let rows = self.keys.iter()
+            .map(|(key, (lines, types))| vec![lines.to_string(), key.clone(), types.iter().copied().collect::<Vec<_>>().join(",")])
+            .collect();
+
+        write_table(out, vec!["LINES".to_owned(), "KEY".to_owned(), "TYPES".to_owned()], rows, &[true, false, false])
+    }
+}
+
+/// A JSON Schema of the lines, see --infer-schema
+#[derive(Debug, Default)]
+struct Schema {
+    shape: Shape,
+}
+
+/// The values seen at one place of the lines
+#[derive(Debug, Default)]
+struct Shape {
+    /// JSON Schema types of the values
+    types: BTreeSet<&'static str>,
+    /// Values that were objects
+    objects: u64,
+    /// Shapes of the keys of objects, with how many objects had each key
+    properties: BTreeMap<String, (u64, Shape)>,
+    /// Shape of the items of arrays
+    items: Option<Box<Shape>>,
+}
+
+impl Shape {
+    fn add(&mut self, value: &Value) {
+        self.types.insert(match value {
+            Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+            value => type_name(value),
+        });
+
+        match value {
+            Value::Object(obj) => {
+                self.objects += 1;
+                for (key, value) in obj {
+                    let (count, shape) = self.properties.entry(key.clone()).or_default();
+                    *count += 1;
+                    shape.add(value);
+                }
+            },
+            Value::Array(items) =>
+                for item in items {
+                    self.items.get_or_insert_with(Default::default).add(item);
+                },
+            _ => (),
+        }
+    }
+
+    fn schema(&self) -> Value {
+        let mut schema = Map::new();
+
+        // Integers are numbers too
+        let types: Vec<&str> = self.types.iter().copied()
+            .filter(|&name| name != "integer" || ! self.types.contains("number"))
+            .collect();
+        match &types[..] {
+            [] => (),
+            [name] => { schema.insert("type".to_owned(), json!(name)); },
+            names => { schema.insert("type".to_owned(), json!(names)); },
+        }
+
+        if ! self.properties.is_empty() {
+            let properties: Map<String, Value> = self.properties.iter().map(|(key, (_, shape))| (key.clone(), shape.schema())).collect();
+            let required: Vec<&String> = self.properties.iter().filter(|(_, (count, _))| *count == self.objects).map(|(key, _)| key).collect();
+
+            schema.insert("properties".to_owned(), Value::Object(properties));
+            if ! required.is_empty() {
+                schema.insert("required".to_owned(), json!(required));
+            }
+        }
+
+        if let Some(items) = &self.items {
+            schema.insert("items".to_owned(), items.schema());
+        }
+
+        Value::Object(schema)
+    }
+}
+
+impl Report for Schema {
+    fn record(&mut self, line: &Value, _opts: &Opt) -> Result<()> {
+        self.shape.add(line);
+        Ok(())
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut schema = Map::new();
+        schema.insert("$schema".to_owned(), json!("https://json-schema.org/draft/2020-12/schema"));
+        if let Value::Object(shape) = self.shape.schema() {
+            schema.extend(shape);
+        }
+
+        writeln!(out, "{}", serde_json::to_string_pretty(&schema)?)
+    }
+}
+
+/// The json type of `value`
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// The values of a field not seen before, written as they come rather than
+/// at the end, see --distinct
+pub (crate) struct Distinct {
+    path: EPath,
+    seen: HashSet<String>,
+}
+
+impl Distinct {
+    pub (crate) fn new(field: &str) -> Result<Distinct> {
+        Ok(Distinct { path: parser::parse_path(field)?, seen: HashSet::new() })
+    }
+
+    /// The value of the field in `line` if it is the first time it is seen
+    pub (crate) fn first_seen(&mut self, line: &Value) -> Option<String> {
+        let value = text(Some(self.path.lookup(line).filter(|value| ! value.is_null())?));
+        self.seen.insert(value.clone()).then_some(value)
+    }
+}
+
+/// Writes rows in aligned columns, right aligned for columns marked in
+/// `right`
+fn write_table(out: &mut dyn Write, header: Vec<String>, rows: Vec<Vec<String>>, right: &[bool]) -> io::Result<()> {
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| rows.iter().chain([&header]).map(|row| row[i].width()).max().unwrap_or(0))
+        .collect();
+
+    for row in [header].iter().chain(&rows) {
+        let cells: Vec<String> = row.iter().zip(&widths).zip(right)
+            .map(|((cell, &width), &right)| pad(cell, width, right))
+            .collect();
+
+        writeln!(out, "{}", cells.join(" ").trim_end())?;
+    }
+
+    Ok(())
+}
+
+/// Pads `text` with spaces to `width` terminal columns, on the left when
+/// `right` aligned. Wide characters such as CJK and emoji take two columns.
+fn pad(text: &str, width: usize, right: bool) -> String {
+    let padding = " ".repeat(width.saturating_sub(text.width()));
+
+    if right {
+        padding + text
+    } else {
+        format!("{}{}", text, padding)
+    }
+}
+
+/// A number or a string holding one
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Writes whole numbers without decimals and others with at most 3
+fn format_number(n: f64) -> String {
+    if n.fract() == 0.0 && n.abs() < 1e15 {
+        format!("{}", n as i64)
+    } else {
+        let text = format!("{:.3}", n);
+        text.trim_end_matches('0').trim_end_matches('.').to_owned()
+    }
+}
+
+/// How a value is written in reports, `-` when there is none
+fn text(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => "-".to_owned(),
+        Some(Value::String(text)) => text.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use structopt::StructOpt;
+    use super::*;
+
+    #[test]
+    fn level_stats_test() {
+        let opts = Opt::from_iter(["jaxe", "-l", "level"]);
+        let mut stats = LevelStats::default();
+
+        for line in [json!({"level": "INFO"}), json!({"level": "ERROR"}), json!({"level": "INFO"}), json!({"level": "notice"}), json!({"msg": "no level"})] {
+            stats.record(&line, &opts).unwrap();
+        }
+
+        let mut out = vec![];
+        stats.write(&mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+LEVEL       LINES       %
+ERROR           1   20.0%
+INFO            2   40.0%
+notice          1   20.0%
+-               1   20.0%
+TOTAL           5
+");
+
+        // Wide characters take two columns
+        let mut stats = LevelStats::default();
+        stats.record(&json!({"level": "重大警告"}), &opts).unwrap();
+
+        let mut out = vec![];
+        stats.write(&mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+LEVEL         LINES       %
+重大警告          1  100.0%
+TOTAL             1
+");
+    }
+
+    #[test]
+    fn summary_test() {
+        let opts = Opt::from_iter(["jaxe", "-l", "level", "-t", "time", "-m", "msg"]);
+        let mut summary = Summary::default();
+
+        for line in [
+            json!({"level": "INFO", "time": "2024-03-01T10:00:00Z", "msg": "started"}),
+            json!({"level": "ERROR", "time": "2024-03-01T10:05:30Z", "msg": "connection refused"}),
+            json!({"level": "FATAL", "time": "2024-03-01T10:02:00Z", "msg": "out of memory"}),
+            json!({"level": "ERROR", "time": "2024-03-01T10:03:00Z", "msg": "connection refused"}),
+        ] {
+            summary.line_read();
+            summary.record(&line, &opts).unwrap();
+        }
+        summary.line_read();
+        summary.parse_failed();
+
+        let mut out = vec![];
+        summary.write(&mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+Lines read      5
+Parse failures  1
+Matches         4
+First line      2024-03-01T10:00:00Z
+Last line       2024-03-01T10:05:30Z
+Span            5m30s
+
+LEVEL      LINES       %
+FATAL          1   25.0%
+ERROR          2   50.0%
+INFO           1   25.0%
+TOTAL          4
+
+COUNT ERROR MESSAGE
+    2 connection refused
+    1 out of memory
+");
+    }
+
+    #[test]
+    fn group_by_test() {
+        let opts = Opt::from_iter(["jaxe"]);
+        let mut groups = GroupBy::new(&["service".to_owned(), "http.status".to_owned()], vec![]).unwrap();
+
+        for line in [
+            json!({"service": "api", "http": {"status": 500}}),
+            json!({"service": "worker"}),
+            json!({"service": "api", "http": {"status": 200}}),
+            json!({"service": "api", "http": {"status": 500}}),
+        ] {
+            groups.record(&line, &opts).unwrap();
+        }
+
+        let mut out = vec![];
+        groups.write(&mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+COUNT service http.status
+    2 api     500
+    1 api     200
+    1 worker  -
+");
+    }
+
+    #[test]
+    fn aggregation_test() {
+        let opts = Opt::from_iter(["jaxe"]);
+        let aggregations = vec!["duration_ms:sum,avg,min,max,p50,p95".parse().unwrap()];
+        let mut groups = GroupBy::new(&["service".to_owned()], aggregations).unwrap();
+
+        for (service, duration) in [("api", json!(10)), ("api", json!(20.5)), ("api", json!("30")), ("api", json!(null)), ("worker", json!(100))] {
+            groups.record(&json!({"service": service, "duration_ms": duration}), &opts).unwrap();
+        }
+
+        let mut out = vec![];
+        groups.write(&mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+COUNT service sum(duration_ms) avg(duration_ms) min(duration_ms) max(duration_ms) p50(duration_ms) p95(duration_ms)
+    4 api                 60.5           20.167               10               30             20.5               30
+    1 worker               100              100              100              100              100              100
+");
+
+        assert!("duration_ms".parse::<Aggregation>().is_err());
+        assert!("duration_ms:median".parse::<Aggregation>().is_err());
+        assert_eq!("http.time:p99.9".parse::<Aggregation>().unwrap().stats, vec![Stat::Percentile(99.9)]);
+    }
+
+    #[test]
+    fn top_test() {
+        let opts = Opt::from_iter(["jaxe"]);
+        let mut top = TopValues { top: "path:2".parse().unwrap(), counts: HashMap::new() };
+
+        for path in ["/b", "/a", "/c", "/a", "/b", "/a"] {
+            top.record(&json!({"path": path}), &opts).unwrap();
+        }
+        top.record(&json!({"msg": "no path"}), &opts).unwrap();
+
+        let mut out = vec![];
+        top.write(&mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "COUNT path\n    3 /a\n    2 /b\n");
+    }
+
+    #[test]
+    fn write_table_test() {
+        let rows = vec![
+            vec!["東京".to_owned(), "3".to_owned()],
+            vec!["🦀".to_owned(), "12".to_owned()],
+            vec!["paris".to_owned(), "1".to_owned()],
+        ];
+
+        let mut out = vec![];
+        write_table(&mut out, vec!["CITY".to_owned(), "N".to_owned()], rows, &[false, true]).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "CITY   N\n東京   3\n🦀    12\nparis  1\n");
+        assert_eq!("user.id".parse::<Top>().unwrap().count, 10);
+        assert!("user.id:many".parse::<Top>().is_err());
+    }
+
+    #[test]
+    fn histogram_test() {
+        let opts = Opt::from_iter(["jaxe", "-t", "time"]);
+        let mut histogram = Histogram::new(std::time::Duration::from_secs(60));
+
+        for time in ["2024-03-01T10:00:05+01:00", "2024-03-01T10:00:55+01:00", "2024-03-01T10:03:00+01:00", "2024-03-01T10:00:30+01:00"] {
+            histogram.record(&json!({"time": time}), &opts).unwrap();
+        }
+        histogram.record(&json!({"msg": "no time"}), &opts).unwrap();
+
+        let mut out = vec![];
+        histogram.write(&mut out).unwrap();
+
+        let bar = |len| "█".repeat(len);
+        assert_eq!(String::from_utf8(out).unwrap(), format!("\
+2024-03-01 10:00 3 {}
+2024-03-01 10:01 0
+2024-03-01 10:02 0
+2024-03-01 10:03 1 {}
+Lines without a time: 1
+", bar(40), bar(14)));
+
+        // Far apart lines don't write every bucket in between
+        let mut histogram = Histogram::new(std::time::Duration::from_secs(1));
+        for time in [5, 1_714_572_000] {
+            histogram.record(&json!({"time": time}), &opts).unwrap();
+        }
+
+        let mut out = vec![];
+        histogram.write(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), format!("\
+1970-01-01 00:00:05 1 {}
+… 1714571994 empty
+2024-05-01 14:00:00 1 {}
+", bar(40), bar(40)));
+    }
+
+    #[test]
+    fn sketch_test() {
+        for count in [1, 100, 50_000] {
+            let mut sketch = Sketch::default();
+            for i in 0..count {
+                // Values seen twice count once
+                sketch.insert(&format!("user-{}", i));
+                sketch.insert(&format!("user-{}", i));
+            }
+
+            let error = (sketch.estimate() - count as f64).abs() / count as f64;
+            assert!(error < 0.05, "{} estimated as {}", count, sketch.estimate());
+        }
+    }
+
+    #[test]
+    fn cardinality_test() {
+        let opts = Opt::from_iter(["jaxe"]);
+        let mut cardinality = Cardinality::default();
+
+        for i in 0..10 {
+            cardinality.record(&json!({"level": "INFO", "request": {"id": i}, "user": null}), &opts).unwrap();
+        }
+
+        let mut out = vec![];
+        cardinality.write(&mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+DISTINCT LINES KEY
+      10    10 request.id
+       1    10 level
+");
+    }
+
+    #[test]
+    fn keys_test() {
+        let opts = Opt::from_iter(["jaxe"]);
+        let mut keys = Keys::default();
+
+        for line in [
+            json!({"status": 200, "http": {"path": "/"}, "items": [{"id": 1}, {"id": "a"}]}),
+            json!({"status": "ok", "http": {}, "items": []}),
+            json!({"status": null}),
+        ] {
+            keys.record(&line, &opts).unwrap();
+        }
+
+        let mut out = vec![];
+        keys.write(&mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+LINES KEY        TYPES
+    1 http       object
+    1 http.path  string
+    2 items      array
+    1 items[].id number,string
+    3 status     null,number,string
+");
+    }
+
+    #[test]
+    fn schema_test() {
+        let opts = Opt::from_iter(["jaxe"]);
+        let mut schema = Schema::default();
+
+        for line in [
+            json!({"status": 200, "took": 1.5, "tags": ["a"], "user": {"id": 1}}),
+            json!({"status": "ok", "took": 2, "user": {"id": 2, "name": "ana"}}),
+        ] {
+            schema.record(&line, &opts).unwrap();
+        }
+
+        let mut out = vec![];
+        schema.write(&mut out).unwrap();
+
+        let written: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(written, json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "type": "object",
+            "properties": {
+                "status": {"type": ["integer", "string"]},
+                "tags": {"type": "array", "items": {"type": "string"}},
+                "took": {"type": "number"},
+                "user": {
+                    "type": "object",
+                    "properties": {"id": {"type": "integer"}, "name": {"type": "string"}},
+                    "required": ["id"],
+                },
+            },
+            "required": ["status", "took", "user"],
+        }));
+    }
+
+    #[test]
+    fn distinct_test() {
+        let mut distinct = Distinct::new("service").unwrap();
+
+        let seen: Vec<Option<String>> = [json!({"service": "api"}), json!({"service": 1}), json!({}), json!({"service": "api"}), json!({"service": "worker"})]
+            .iter()
+            .map(|line| distinct.first_seen(line))
+            .collect();
+
+        assert_eq!(seen, vec![Some("api".to_owned()), Some("1".to_owned()), None, None, Some("worker".to_owned())]);
+    }
+}
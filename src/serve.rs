@@ -0,0 +1,315 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use percent_encoding::percent_decode_str;
+use serde_json::{Map, Value};
+
+use crate::Opt;
+use crate::output::{self, Printer};
+use crate::parser::{self, EvalContext, Exp};
+use crate::theme::Theme;
+
+/// How long a write to a client may block before the client is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long reading a request may take before the connection is dropped,
+/// so an idle connection doesn't hold up the next ones
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Events waiting to be written to a client. Clients falling further behind
+/// are dropped, so a slow client doesn't hold up the input.
+const QUEUE_LENGTH: usize = 1024;
+
+/// Streams the lines written by jaxe to clients connected to
+/// `GET /events` as server-sent events, see --serve. Each client can pass
+/// its own `filter` and a `format` of `text` or `json`.
+pub (crate) struct Server {
+    clients: Arc<Mutex<Vec<Client>>>,
+    /// Renders lines as text without colors
+    printer: Printer,
+}
+
+struct Client {
+    /// Events for the thread writing to the client
+    events: SyncSender<Arc<str>>,
+    filter: Option<Exp>,
+    json: bool,
+}
+
+impl Server {
+    pub (crate) fn start(address: &str) -> io::Result<Server> {
+        Ok(Server::serve(bind(address)?))
+    }
+
+    pub (crate) fn serve(listener: TcpListener) -> Server {
+        let clients = Arc::new(Mutex::new(vec![]));
+
+        let accepted = clients.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let clients = accepted.clone();
+                        thread::spawn(move || {
+                            if let Err(err) = connect(stream, &clients) {
+                                log::debug!("Dropping client: {}", err);
+                            }
+                        });
+                    },
+                    Err(err) => log::debug!("Could not accept connection: {}", err),
+                }
+            }
+        });
+
+        Server { clients, printer: Printer::capture(Theme::default()) }
+    }
+
+    /// How many clients are connected
+    #[cfg(test)]
+    pub (crate) fn clients(&self) -> usize {
+        self.clients.lock().map_or(0, |clients| clients.len())
+    }
+
+    /// Sends `json`, the written fields of `line`, to the clients whose
+    /// filter matches `line`
+    pub (crate) fn publish(&mut self, opts: &Opt, line: &Value, json: &Map<String, Value>) -> Result<()> {
+        let mut clients = self.clients.lock().map_err(|_| anyhow!("Clients of --serve were poisoned"))?;
+        if clients.is_empty() {
+            return Ok(())
+        }
+
+        let ctx = EvalContext { epsilon: opts.epsilon };
+        let (mut text, mut raw): (Option<Arc<str>>, Option<Arc<str>>) = (None, None);
+        let mut dropped = vec![];
+
+        for (i, client) in clients.iter().enumerate() {
+            if let Some(filter) = &client.filter {
+                if ! parser::filter_with(filter, line, &ctx)? {
+                    continue
+                }
+            }
+
+            let event = if client.json {
+                raw.get_or_insert_with(|| serde_json::to_string(json).unwrap_or_default().into())
+            } else {
+                if text.is_none() {
+                    output::write_text(opts, json.clone(), false, &[], &mut self.printer)?;
+                    text = Some(self.printer.take_captured().into());
+                }
+                text.as_ref().unwrap()
+            };
+
+            // Clients are written to by their own thread, not while holding
+            // the lock
+            match client.events.try_send(event.clone()) {
+                Ok(()) => (),
+                Err(TrySendError::Full(_)) => {
+                    log::debug!("Dropping client that is {} events behind", QUEUE_LENGTH);
+                    dropped.push(i);
+                },
+                Err(TrySendError::Disconnected(_)) => dropped.push(i),
+            }
+        }
+
+        for i in dropped.into_iter().rev() {
+            clients.remove(i);
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the request of a new connection and adds it to `clients`, then
+/// writes it the events until it is dropped. Answers with an error for
+/// other requests.
+fn connect(mut stream: TcpStream, clients: &Mutex<Vec<Client>>) -> Result<()> {
+    let Some(target) = read_request(&stream)? else {
+        return respond(&mut stream, "405 Method Not Allowed", "Only GET /events is served")
+    };
+
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    if path != "/events" {
+        return respond(&mut stream, "404 Not Found", "Only GET /events is served");
+    }
+
+    let (events, queue) = mpsc::sync_channel(QUEUE_LENGTH);
+    let mut client = Client { events, filter: None, json: false };
+
+    for (key, value) in query_params(query) {
+        match (key.as_str(), value.as_str()) {
+            ("filter", filter) => match parser::parse(filter) {
+                Ok(filter) => client.filter = Some(filter),
+                Err(err) => return respond(&mut stream, "400 Bad Request", &err.to_string()),
+            },
+            ("format", "json") => client.json = true,
+            ("format", "text") => client.json = false,
+            ("format", other) => return respond(&mut stream, "400 Bad Request", &format!("Unknown format: {}, expected text or json", other)),
+            _ => (),
+        }
+    }
+
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n")?;
+
+    clients.lock().map_err(|_| anyhow!("Clients of --serve were poisoned"))?.push(client);
+
+    for event in queue {
+        send(&mut stream, &event)?;
+    }
+
+    Ok(())
+}
+
+/// Listens on `address`, such as `127.0.0.1:8080`. A port alone, as in
+/// `:8080`, listens on localhost, as --listen does.
+pub (crate) fn bind(address: &str) -> io::Result<TcpListener> {
+    let address = match address.strip_prefix(':').unwrap_or(address).parse::<u16>() {
+        Ok(port) => format!("127.0.0.1:{}", port),
+        Err(_) => address.to_owned(),
+    };
+
+    TcpListener::bind(&address).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", address, err)))
+}
+
+/// Reads an HTTP request up to the end of its headers, which are not used.
+/// Returns the target of `GET` requests, such as `/events?format=json`.
+pub (crate) fn read_request(stream: &TcpStream) -> io::Result<Option<String>> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    match request.split(' ').collect::<Vec<_>>()[..] {
+        ["GET", target, _] => Ok(Some(target.to_owned())),
+        _ => Ok(None),
+    }
+}
+
+pub (crate) fn respond(stream: &mut TcpStream, status: &str, body: &str) -> Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body)?;
+    Ok(())
+}
+
+/// Decoded `key=value` pairs of a query string
+fn query_params(query: &str) -> Vec<(String, String)> {
+    let decode = |text: &str| percent_decode_str(&text.replace('+', " ")).decode_utf8_lossy().into_owned();
+
+    query.split('&')
+        .filter(|pair| ! pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(key), decode(value))
+        })
+        .collect()
+}
+
+/// Writes `text` as one event, each of its lines being a `data` line
+fn send(stream: &mut TcpStream, text: &str) -> io::Result<()> {
+    let mut event = String::new();
+    for line in text.trim_end().lines() {
+        event.push_str("data: ");
+        event.push_str(line);
+        event.push('\n');
+    }
+    event.push('\n');
+
+    stream.write_all(event.as_bytes())?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use serde_json::json;
+    use structopt::StructOpt;
+    use super::*;
+
+    #[test]
+    fn query_params_test() {
+        assert_eq!(query_params("filter=level+%3D%3D+%22ERROR%22&format=json&"), vec![
+            ("filter".to_owned(), "level == \"ERROR\"".to_owned()),
+            ("format".to_owned(), "json".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn serve_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut server = Server::serve(listener);
+        let opts = Opt::from_iter(["jaxe", "-m", "msg"]);
+
+        let mut client = TcpStream::connect(address).unwrap();
+        write!(client, "GET /events?filter=level%20%3D%3D%20%22ERROR%22&format=json HTTP/1.1\r\nHost: jaxe\r\n\r\n").unwrap();
+
+        while server.clients.lock().unwrap().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        for line in [json!({"level": "INFO", "msg": "ok"}), json!({"level": "ERROR", "msg": "failed"})] {
+            server.publish(&opts, &line, line.as_object().unwrap()).unwrap();
+        }
+        // Closes the connection
+        server.clients.lock().unwrap().clear();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\ndata: {\"level\":\"ERROR\",\"msg\":\"failed\"}\n\n"));
+    }
+
+    #[test]
+    fn slow_client_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut server = Server::serve(listener);
+        let opts = Opt::from_iter(["jaxe", "-m", "msg"]);
+
+        // Never reads what is sent
+        let mut client = TcpStream::connect(address).unwrap();
+        write!(client, "GET /events?format=json HTTP/1.1\r\n\r\n").unwrap();
+
+        while server.clients() == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let line = json!({"msg": "x".repeat(10_000)});
+        for _ in 0..10_000 {
+            server.publish(&opts, &line, line.as_object().unwrap()).unwrap();
+        }
+
+        assert_eq!(server.clients(), 0);
+    }
+
+    #[test]
+    fn bind_test() {
+        let listener = bind(":0").unwrap();
+        assert!(listener.local_addr().unwrap().ip().is_loopback());
+        assert!(bind("localhost:0").is_ok());
+        assert!(bind("nowhere").is_err());
+    }
+
+    #[test]
+    fn send_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+
+        send(&mut stream, "E|failed\n    at main\n").unwrap();
+        drop(stream);
+
+        let mut event = String::new();
+        client.read_to_string(&mut event).unwrap();
+        assert_eq!(event, "data: E|failed\ndata:     at main\n\n");
+    }
+}
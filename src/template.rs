@@ -0,0 +1,120 @@
+use std::str::FromStr;
+use serde_json::Value;
+use anyhow::{anyhow, bail, Result};
+
+use crate::parser::{self, EPath};
+
+#[derive(Debug)]
+enum Part {
+    Literal(String),
+    Field { path: EPath, fallback: Option<String> },
+}
+
+/// A format string such as `{time} [{level}] {msg} ({user.id:-?})`.
+/// Placeholders are paths into the json line, optionally followed by `:-`
+/// and a fallback used when the path does not exist. `{{` and `}}` are
+/// literal braces.
+#[derive(Debug)]
+pub (crate) struct Template(Vec<Part>);
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = src.char_indices().peekable();
+
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '{' if chars.peek().map(|(_, c)| *c) == Some('{') => {
+                    chars.next();
+                    literal.push('{');
+                },
+                '}' if chars.peek().map(|(_, c)| *c) == Some('}') => {
+                    chars.next();
+                    literal.push('}');
+                },
+                '{' => {
+                    let len = src[i..].find('}').ok_or_else(|| anyhow!("Unclosed placeholder in format: {}", src))?;
+                    let placeholder = &src[i + 1..i + len];
+
+                    let (path, fallback) = match placeholder.split_once(":-") {
+                        Some((path, fallback)) => (path, Some(fallback.to_owned())),
+                        None => (placeholder, None),
+                    };
+
+                    if ! literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Field { path: parser::parse_path(path.trim())?, fallback });
+
+                    while chars.peek().map(|(j, _)| *j <= i + len).unwrap_or(false) {
+                        chars.next();
+                    }
+                },
+                '}' =>
+                    bail!("Unmatched }} in format: {}", src),
+                c =>
+                    literal.push(c),
+            }
+        }
+
+        if ! literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Template(parts))
+    }
+}
+
+impl Template {
+    /// Interpolates `line` into the template. Missing values without a
+    /// fallback are rendered as an empty string.
+    pub (crate) fn render(&self, line: &Value) -> String {
+        let mut out = String::new();
+
+        for part in &self.0 {
+            match part {
+                Part::Literal(s) =>
+                    out.push_str(s),
+                Part::Field { path, fallback } =>
+                    match path.lookup(line) {
+                        Some(Value::String(s)) => out.push_str(s),
+                        Some(Value::Null) | None => out.push_str(fallback.as_deref().unwrap_or("")),
+                        Some(other) => out.push_str(&other.to_string()),
+                    }
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use super::*;
+
+    #[test]
+    fn render_test() {
+        let template: Template = "{time} [{level}] {msg} ({request_id})".parse().unwrap();
+        let line = json!({"time": "t", "level": "INFO", "msg": "hello", "request_id": 42});
+
+        assert_eq!(template.render(&line), "t [INFO] hello (42)");
+    }
+
+    #[test]
+    fn render_nested_and_fallback_test() {
+        let template: Template = "{user.id:-?} {user.name:-anonymous} {{literal}}".parse().unwrap();
+        let line = json!({"user": {"id": "u1"}});
+
+        assert_eq!(template.render(&line), "u1 anonymous {literal}");
+    }
+
+    #[test]
+    fn parse_invalid_test() {
+        assert!("{unclosed".parse::<Template>().is_err());
+        assert!("stray }".parse::<Template>().is_err());
+    }
+}
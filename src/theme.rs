@@ -0,0 +1,535 @@
+use std::str::FromStr;
+use termcolor::Color;
+use serde_json::Value;
+use anyhow::{anyhow, Context, Result};
+
+use crate::cli;
+use crate::parser;
+
+/// Colors used by text and table output. `None` uses the terminal's default
+/// color.
+#[derive(Debug, Clone)]
+pub (crate) struct Theme {
+    pub (crate) key: Option<Color>,
+    pub (crate) number: Option<Color>,
+    pub (crate) non_json: Option<Color>,
+    pub (crate) match_fg: Option<Color>,
+    pub (crate) match_bg: Option<Color>,
+    pub (crate) levels: Vec<(String, Option<Color>)>,
+    /// Color of levels not in `levels`
+    pub (crate) other_level: Option<Color>,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            key: Some(Color::Blue),
+            number: Some(Color::Red),
+            non_json: Some(Color::White),
+            match_fg: Some(Color::Black),
+            match_bg: Some(Color::Yellow),
+            levels: vec![
+                ("TRACE".to_owned(), Some(Color::Magenta)),
+                ("DEBUG".to_owned(), Some(Color::Blue)),
+                ("INFO".to_owned(), Some(Color::Green)),
+                ("WARN".to_owned(), Some(Color::Yellow)),
+                ("ERROR".to_owned(), Some(Color::Red)),
+            ],
+            other_level: Some(Color::Red),
+        }
+    }
+}
+
+impl Theme {
+    /// Only bold, dimmed and underlined text, no colors
+    fn mono() -> Theme {
+        Theme {
+            key: None,
+            number: None,
+            non_json: None,
+            match_fg: None,
+            match_bg: None,
+            levels: vec![],
+            other_level: None,
+        }
+    }
+
+    /// Darker colors, readable on terminals with a light background
+    fn light() -> Theme {
+        Theme {
+            key: Some(Color::Ansi256(25)),
+            number: Some(Color::Ansi256(124)),
+            non_json: Some(Color::Black),
+            match_fg: Some(Color::Black),
+            match_bg: Some(Color::Ansi256(222)),
+            levels: vec![
+                ("TRACE".to_owned(), Some(Color::Ansi256(90))),
+                ("DEBUG".to_owned(), Some(Color::Ansi256(25))),
+                ("INFO".to_owned(), Some(Color::Ansi256(28))),
+                ("WARN".to_owned(), Some(Color::Ansi256(130))),
+                ("ERROR".to_owned(), Some(Color::Ansi256(160))),
+            ],
+            other_level: Some(Color::Ansi256(160)),
+        }
+    }
+
+    fn builtin(name: &str) -> Option<Theme> {
+        match name {
+            "default" => Some(Theme::default()),
+            "light" => Some(Theme::light()),
+            "mono" => Some(Theme::mono()),
+            _ => None,
+        }
+    }
+
+    /// The light theme if `COLORFGBG` says the terminal has a light
+    /// background, the default theme otherwise
+    pub (crate) fn detect() -> Theme {
+        match std::env::var("COLORFGBG") {
+            Ok(colors) if is_light_background(&colors) => Theme::light(),
+            _ => Theme::default(),
+        }
+    }
+
+    pub (crate) fn level_color(&self, level: &str) -> Option<Color> {
+        self.levels.iter()
+            .find(|(name, _)| name == level)
+            .map(|(_, color)| *color)
+            .unwrap_or(self.other_level)
+    }
+
+    /// Replaces colors the terminal can't show with the closest it can
+    pub (crate) fn adapt(&mut self, palette: Palette) {
+        let adapt = |color: &mut Option<Color>| *color = color.map(|c| palette.adapt(c));
+
+        adapt(&mut self.key);
+        adapt(&mut self.number);
+        adapt(&mut self.non_json);
+        adapt(&mut self.match_fg);
+        adapt(&mut self.match_bg);
+        adapt(&mut self.other_level);
+        self.levels.iter_mut().for_each(|(_, color)| adapt(color));
+    }
+
+    /// Sets the color of `level`, replacing any color it already had
+    pub (crate) fn set_level_color(&mut self, level: &str, color: Option<Color>) {
+        match self.levels.iter_mut().find(|(name, _)| name == level) {
+            Some(entry) => entry.1 = color,
+            None => self.levels.push((level.to_owned(), color)),
+        }
+    }
+
+    /// Parses a theme file such as:
+    ///
+    /// ```toml
+    /// base = "default"
+    /// key = "cyan"
+    ///
+    /// [levels]
+    /// FATAL = "magenta"
+    /// ```
+    ///
+    /// Colors not set in the file are taken from the `base` theme.
+    fn from_toml(src: &str) -> Result<Theme> {
+        let table: toml::Table = src.parse()?;
+
+        let mut theme = match table.get("base") {
+            Some(base) => {
+                let name = base.as_str().ok_or_else(|| anyhow!("Expected a theme name for base, got: {}", base))?;
+                Theme::builtin(name).ok_or_else(|| anyhow!("Unknown theme: {}", name))?
+            },
+            None => Theme::default(),
+        };
+
+        for (key, value) in &table {
+            match key.as_str() {
+                "base" => (),
+                "levels" => {
+                    let levels = value.as_table().ok_or_else(|| anyhow!("Expected a table for levels"))?;
+                    for (level, color) in levels {
+                        theme.set_level_color(level, color_value(color)?);
+                    }
+                },
+                "key" => theme.key = color_value(value)?,
+                "number" => theme.number = color_value(value)?,
+                "non_json" => theme.non_json = color_value(value)?,
+                "match_fg" => theme.match_fg = color_value(value)?,
+                "match_bg" => theme.match_bg = color_value(value)?,
+                "other_level" => theme.other_level = color_value(value)?,
+                other => return Err(anyhow!("Unknown theme setting: {}", other)),
+            }
+        }
+
+        Ok(theme)
+    }
+
+    /// The theme in `$XDG_CONFIG_HOME/jaxe/theme.toml` or
+    /// `~/.config/jaxe/theme.toml`, if that file exists
+    pub (crate) fn from_config_file() -> Result<Option<Theme>> {
+        match cli::config_file("theme.toml") {
+            Some(path) =>
+                path.to_str().unwrap_or_default().parse().map(Some),
+            None =>
+                Ok(None),
+        }
+    }
+}
+
+/// Whether a `COLORFGBG` value such as `0;15` has a light background color.
+/// The background is the last number, 7 and 9 to 15 are light colors
+/// except for 8, dark gray.
+fn is_light_background(colorfgbg: &str) -> bool {
+    match colorfgbg.rsplit(';').next().and_then(|bg| bg.parse::<u8>().ok()) {
+        Some(bg) => bg == 7 || (9..=15).contains(&bg),
+        None => false,
+    }
+}
+
+/// Parses a color name such as `red`, an ansi 256 color number such as `214`,
+/// an `r,g,b` triple, a `#rrggbb` hex color or `none` for the terminal's
+/// default color
+pub (crate) fn parse_color(src: &str) -> Result<Option<Color>> {
+    match src {
+        "none" | "default" =>
+            Ok(None),
+        hex if hex.starts_with('#') && hex.len() == 7 && hex.is_char_boundary(3) && hex.is_char_boundary(5) => {
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| anyhow!("Invalid hex color: {}", hex));
+            Ok(Some(Color::Rgb(channel(1)?, channel(3)?, channel(5)?)))
+        },
+        color =>
+            Color::from_str(color).map(Some).map_err(|e| anyhow!("{}", e)),
+    }
+}
+
+/// Colors a terminal can show
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub (crate) enum Palette {
+    Basic,
+    Ansi256,
+    TrueColor,
+}
+
+const BASIC: [(Color, (u8, u8, u8)); 8] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::White, (229, 229, 229)),
+];
+
+impl Palette {
+    /// Guesses the palette of the terminal from `COLORTERM` and `TERM`
+    pub (crate) fn detect() -> Palette {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            Palette::TrueColor
+        } else if term.contains("256color") {
+            Palette::Ansi256
+        } else {
+            Palette::Basic
+        }
+    }
+
+    /// The closest color to `color` in this palette
+    pub (crate) fn adapt(self, color: Color) -> Color {
+        match color {
+            Color::Rgb(r, g, b) if self < Palette::TrueColor =>
+                self.adapt(Color::Ansi256(ansi256_of_rgb(r, g, b))),
+            Color::Ansi256(n) if self < Palette::Ansi256 => {
+                let (r, g, b) = rgb_of_ansi256(n);
+                BASIC.iter()
+                    .min_by_key(|(_, (br, bg, bb))| {
+                        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+                        d(r, *br) + d(g, *bg) + d(b, *bb)
+                    })
+                    .map(|(c, _)| *c)
+                    .unwrap_or(color)
+            },
+            other =>
+                other,
+        }
+    }
+}
+
+/// The closest color in the 6x6x6 cube or grayscale ramp of ansi 256 colors
+fn ansi256_of_rgb(r: u8, g: u8, b: u8) -> u8 {
+    if r == g && g == b {
+        return match r {
+            0..=7 => 16,
+            248..=255 => 231,
+            _ => 232 + ((r as u16 - 8) * 24 / 240) as u8,
+        }
+    }
+
+    let level = |c: u8| if c < 48 { 0 } else if c < 115 { 1 } else { (c - 35) / 40 };
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
+fn rgb_of_ansi256(n: u8) -> (u8, u8, u8) {
+    let cube = |l: u8| if l == 0 { 0 } else { 55 + l * 40 };
+
+    match n {
+        0..=7 => BASIC[n as usize].1,
+        8..=15 => {
+            let (r, g, b) = BASIC[n as usize - 8].1;
+            (r.saturating_add(50), g.saturating_add(50), b.saturating_add(50))
+        },
+        16..=231 => {
+            let i = n - 16;
+            (cube(i / 36), cube(i / 6 % 6), cube(i % 6))
+        },
+        _ => {
+            let gray = 8 + (n - 232) * 10;
+            (gray, gray, gray)
+        },
+    }
+}
+
+fn color_value(value: &toml::Value) -> Result<Option<Color>> {
+    parse_color(value.as_str().ok_or_else(|| anyhow!("Expected a color name, got: {}", value))?)
+}
+
+/// A `LEVEL=color` pair given to --level-color
+#[derive(Debug)]
+pub (crate) struct LevelColor(pub (crate) String, pub (crate) Option<Color>);
+
+impl FromStr for LevelColor {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src.split_once('=') {
+            Some((level, color)) if ! level.is_empty() =>
+                Ok(LevelColor(level.trim().to_owned(), parse_color(color.trim())?)),
+            _ =>
+                Err(anyhow!("Expected LEVEL=color, got: {}", src)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum RuleTarget {
+    /// The fields the condition looks at
+    Condition,
+    Field(String),
+    Line,
+}
+
+/// A `condition => color` rule given to --color-rule. The color can be
+/// prefixed with `line:` to color the whole line, or with a field name to
+/// color that field, otherwise the fields used by the condition are colored.
+#[derive(Debug)]
+pub (crate) struct ColorRule {
+    condition: parser::Exp,
+    target: RuleTarget,
+    color: Option<Color>,
+}
+
+impl FromStr for ColorRule {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let (condition, color) = src.rsplit_once("=>").ok_or_else(|| anyhow!("Expected condition => color, got: {}", src))?;
+
+        let (target, color) = match color.trim().rsplit_once(':') {
+            Some(("line", color)) => (RuleTarget::Line, color),
+            Some((field, color)) => (RuleTarget::Field(field.trim().to_owned()), color),
+            None => (RuleTarget::Condition, color.trim()),
+        };
+
+        Ok(ColorRule { condition: parser::parse(condition.trim())?, target, color: parse_color(color.trim())? })
+    }
+}
+
+impl ColorRule {
+    /// Replaces the color of the rule with the closest one in `palette`
+    pub (crate) fn adapt(&mut self, palette: Palette) {
+        self.color = self.color.map(|c| palette.adapt(c));
+    }
+}
+
+/// Colors picked by --color-rule for a line
+#[derive(Debug, Default, PartialEq)]
+pub (crate) struct RuleColors {
+    pub (crate) line: Option<Color>,
+    /// Colors of top level fields, nested paths color their top level field
+    pub (crate) fields: Vec<(String, Color)>,
+}
+
+impl RuleColors {
+    pub (crate) fn field(&self, key: &str) -> Option<Color> {
+        self.fields.iter().find(|(k, _)| k == key).map(|(_, c)| *c).or(self.line)
+    }
+}
+
+/// The colors of the rules matching `line`, earlier rules win over later ones
+pub (crate) fn rule_colors(rules: &[ColorRule], line: &Value, ctx: &parser::EvalContext) -> Result<RuleColors> {
+    let mut colors = RuleColors::default();
+
+    for rule in rules {
+        let Some(color) = rule.color else { continue };
+
+        if ! parser::filter_with(&rule.condition, line, ctx)? {
+            continue
+        }
+
+        let fields = match &rule.target {
+            RuleTarget::Line => {
+                colors.line = colors.line.or(Some(color));
+                continue
+            },
+            RuleTarget::Field(field) =>
+                vec![field.clone()],
+            RuleTarget::Condition =>
+                parser::paths(&rule.condition).iter().filter_map(|p| p.segments().first().cloned()).collect(),
+        };
+
+        for field in fields {
+            if colors.fields.iter().all(|(k, _)| *k != field) {
+                colors.fields.push((field, color));
+            }
+        }
+    }
+
+    Ok(colors)
+}
+
+/// A built-in theme name, or the path of a theme file
+impl FromStr for Theme {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        if let Some(theme) = Theme::builtin(src) {
+            return Ok(theme)
+        }
+
+        let contents = std::fs::read_to_string(src)
+            .with_context(|| format!("Not a built-in theme or a readable theme file: {}", src))?;
+
+        Theme::from_toml(&contents).with_context(|| format!("Invalid theme file: {}", src))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use super::*;
+
+    #[test]
+    fn builtin_test() {
+        let theme: Theme = "default".parse().unwrap();
+        assert_eq!(theme.level_color("INFO"), Some(Color::Green));
+        assert_eq!(theme.level_color("NOTICE"), Some(Color::Red));
+
+        let theme: Theme = "mono".parse().unwrap();
+        assert_eq!(theme.level_color("INFO"), None);
+
+        let theme: Theme = "light".parse().unwrap();
+        assert_eq!(theme.non_json, Some(Color::Black));
+    }
+
+    #[test]
+    fn is_light_background_test() {
+        assert!(is_light_background("0;15"));
+        assert!(is_light_background("0;default;7"));
+        assert!(! is_light_background("15;0"));
+        assert!(! is_light_background("15;8"));
+        assert!(! is_light_background(""));
+    }
+
+    #[test]
+    fn from_toml_test() {
+        let theme = Theme::from_toml(r#"
+            key = "cyan"
+            number = "none"
+
+            [levels]
+            FATAL = "magenta"
+            INFO = "white"
+        "#).unwrap();
+
+        assert_eq!(theme.key, Some(Color::Cyan));
+        assert_eq!(theme.number, None);
+        assert_eq!(theme.match_bg, Some(Color::Yellow));
+        assert_eq!(theme.level_color("FATAL"), Some(Color::Magenta));
+        assert_eq!(theme.level_color("INFO"), Some(Color::White));
+        assert_eq!(theme.level_color("DEBUG"), Some(Color::Blue));
+
+        let theme = Theme::from_toml(r#"base = "mono""#).unwrap();
+        assert_eq!(theme.key, None);
+    }
+
+    #[test]
+    fn parse_color_test() {
+        assert_eq!(parse_color("red").unwrap(), Some(Color::Red));
+        assert_eq!(parse_color("214").unwrap(), Some(Color::Ansi256(214)));
+        assert_eq!(parse_color("10,20,30").unwrap(), Some(Color::Rgb(10, 20, 30)));
+        assert_eq!(parse_color("#ff8000").unwrap(), Some(Color::Rgb(255, 128, 0)));
+        assert_eq!(parse_color("none").unwrap(), None);
+        assert!(parse_color("#ff80").is_err());
+        assert!(parse_color("#gg8000").is_err());
+    }
+
+    #[test]
+    fn palette_adapt_test() {
+        assert_eq!(Palette::TrueColor.adapt(Color::Rgb(255, 128, 0)), Color::Rgb(255, 128, 0));
+        assert_eq!(Palette::Ansi256.adapt(Color::Rgb(255, 135, 0)), Color::Ansi256(208));
+        assert_eq!(Palette::Ansi256.adapt(Color::Rgb(128, 128, 128)), Color::Ansi256(244));
+        assert_eq!(Palette::Basic.adapt(Color::Rgb(250, 10, 10)), Color::Red);
+        assert_eq!(Palette::Basic.adapt(Color::Ansi256(21)), Color::Blue);
+        assert_eq!(Palette::Basic.adapt(Color::Green), Color::Green);
+    }
+
+    #[test]
+    fn level_color_test() {
+        let LevelColor(level, color) = "FATAL=magenta".parse().unwrap();
+        assert_eq!(level, "FATAL");
+        assert_eq!(color, Some(Color::Magenta));
+
+        assert!("FATAL".parse::<LevelColor>().is_err());
+        assert!("FATAL=blurple".parse::<LevelColor>().is_err());
+    }
+
+    #[test]
+    fn from_toml_invalid_test() {
+        assert!(Theme::from_toml(r#"key = "blurple""#).is_err());
+        assert!(Theme::from_toml(r#"keys = "blue""#).is_err());
+        assert!(Theme::from_toml(r#"base = "unknown""#).is_err());
+    }
+
+    #[test]
+    fn color_rule_parse_test() {
+        let rule: ColorRule = "status >= 500 => red".parse().unwrap();
+        assert_eq!(rule.target, RuleTarget::Condition);
+        assert_eq!(rule.color, Some(Color::Red));
+
+        let rule: ColorRule = "level == FATAL => line:magenta".parse().unwrap();
+        assert_eq!(rule.target, RuleTarget::Line);
+
+        let rule: ColorRule = "duration_ms > 1000 => msg:yellow".parse().unwrap();
+        assert_eq!(rule.target, RuleTarget::Field("msg".to_owned()));
+
+        assert!("status >= 500".parse::<ColorRule>().is_err());
+        assert!("status >= 500 => blurple".parse::<ColorRule>().is_err());
+    }
+
+    #[test]
+    fn rule_colors_test() {
+        let rules: Vec<ColorRule> = ["http.status >= 500 => red", "duration_ms > 1000 => yellow", "level == FATAL => line:magenta", "exists(duration_ms) => green"]
+            .iter().map(|r| r.parse().unwrap()).collect();
+        let ctx = parser::EvalContext::default();
+
+        let colors = rule_colors(&rules, &json!({"http": {"status": 503}, "duration_ms": 10, "level": "INFO"}), &ctx).unwrap();
+        assert_eq!(colors.field("http"), Some(Color::Red));
+        assert_eq!(colors.field("duration_ms"), Some(Color::Green));
+        assert_eq!(colors.field("level"), None);
+
+        let colors = rule_colors(&rules, &json!({"duration_ms": 2000, "level": "FATAL"}), &ctx).unwrap();
+        assert_eq!(colors.field("duration_ms"), Some(Color::Yellow));
+        assert_eq!(colors.field("level"), Some(Color::Magenta));
+    }
+}
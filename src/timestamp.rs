@@ -0,0 +1,398 @@
+use std::str::FromStr;
+use std::fmt::Display;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, SecondsFormat, TimeDelta, TimeZone, Utc};
+use serde_json::Value;
+use chrono_tz::Tz;
+use chrono::format::{Item, StrftimeItems};
+use anyhow::{anyhow, Result};
+
+use crate::Opt;
+use crate::duration;
+
+/// Formats of timestamps without an offset, these are taken to be UTC
+const NAIVE_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+    "%Y%m%dT%H%M%S",
+];
+
+/// Formats of timestamps with an offset, other than rfc 3339 and rfc 2822
+const OFFSET_FORMATS: &[&str] = &[
+    "%Y-%m-%d %H:%M:%S%.f%:z",
+    "%Y-%m-%d %H:%M:%S%.f %z",
+    "%d/%b/%Y:%H:%M:%S %z",
+];
+
+/// A unix timestamp in seconds, milliseconds, microseconds or nanoseconds,
+/// told apart by their magnitude
+fn from_epoch(epoch: f64) -> Option<DateTime<FixedOffset>> {
+    // Seconds as f64 are only precise to the microsecond
+    let (value, scale) = match epoch.abs() {
+        e if e < 1e11 => (epoch * 1e6, 1000),
+        e if e < 1e14 => (epoch * 1e6, 1),
+        e if e < 1e17 => (epoch * 1e3, 1),
+        _ => (epoch, 1),
+    };
+
+    if ! value.is_finite() || value.abs() >= i64::MAX as f64 {
+        return None
+    }
+
+    (value.round() as i64).checked_mul(scale).map(|nanos| DateTime::from_timestamp_nanos(nanos).fixed_offset())
+}
+
+/// Whether `text` is a unix timestamp rather than a compact date such as
+/// `20240501`, going by the digits of seconds, milliseconds, microseconds or
+/// nanoseconds since 2001
+fn is_epoch(text: &str) -> bool {
+    let digits = text.split_once('.').map_or(text, |(int, _)| int);
+    [10, 13, 16, 19].contains(&digits.len()) && digits.bytes().all(|b| b.is_ascii_digit()) && text.parse::<f64>().is_ok()
+}
+
+/// Like `from_epoch`, without losing the precision of nanosecond timestamps
+fn from_integer_epoch(epoch: i64) -> Option<DateTime<FixedOffset>> {
+    let scale = match epoch.unsigned_abs() {
+        e if e < 100_000_000_000 => 1_000_000_000,
+        e if e < 100_000_000_000_000 => 1_000_000,
+        e if e < 100_000_000_000_000_000 => 1_000,
+        _ => 1,
+    };
+
+    epoch.checked_mul(scale).map(|nanos| DateTime::from_timestamp_nanos(nanos).fixed_offset())
+}
+
+/// Parses common timestamp formats, such as `2022-03-24T08:56:20.576Z`,
+/// `2022-03-24 08:56:20`, `24/Mar/2022:08:56:20 +0000` or unix timestamps
+/// such as `1648112180`
+pub (crate) fn parse(text: &str) -> Option<DateTime<FixedOffset>> {
+    let text = text.trim();
+
+    if is_epoch(text) {
+        return match text.parse::<i64>() {
+            Ok(epoch) => from_integer_epoch(epoch),
+            Err(_) => from_epoch(text.parse().ok()?),
+        }
+    }
+
+    DateTime::parse_from_rfc3339(text).ok()
+        .or_else(|| OFFSET_FORMATS.iter().find_map(|f| DateTime::parse_from_str(text, f).ok()))
+        .or_else(|| NAIVE_FORMATS.iter().find_map(|f| NaiveDateTime::parse_from_str(text, f).ok()).map(|t| t.and_utc().fixed_offset()))
+        .or_else(|| ["%Y-%m-%d", "%Y%m%d"].iter().find_map(|f| NaiveDate::parse_from_str(text, f).ok()).map(|d| d.and_time(NaiveTime::MIN).and_utc().fixed_offset()))
+        .or_else(|| DateTime::parse_from_rfc2822(text).ok())
+}
+
+/// A time given to --since or --until, either a timestamp or a duration
+/// before now such as `2h`
+#[derive(Debug, Clone, Copy)]
+pub (crate) struct TimeBound(DateTime<FixedOffset>);
+
+impl FromStr for TimeBound {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        if let Some(ago) = duration::parse(src) {
+            let ago = TimeDelta::from_std(ago)?;
+            let time = Utc::now().checked_sub_signed(ago).ok_or_else(|| anyhow!("Duration too long: {}", src))?;
+            return Ok(TimeBound(time.fixed_offset()))
+        }
+
+        parse(src).map(TimeBound).ok_or_else(|| anyhow!("Expected a timestamp or a duration such as 2h, got: {}", src))
+    }
+}
+
+/// Whether the time of `line` is within --since and --until. Lines without
+/// a timestamp are always shown.
+pub (crate) fn in_window(line: &Value, opts: &Opt) -> bool {
+    if opts.since.is_none() && opts.until.is_none() {
+        return true
+    }
+
+    let Some(time) = of_line(line, opts) else { return true };
+
+    opts.since.is_none_or(|since| time >= since.0) && opts.until.is_none_or(|until| time < until.0)
+}
+
+/// A chrono format string given to --time-format, such as `%H:%M:%S%.3f`
+#[derive(Debug)]
+pub (crate) struct TimeFormat(String);
+
+impl FromStr for TimeFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        if StrftimeItems::new(src).any(|item| item == Item::Error) {
+            return Err(anyhow!("Invalid time format: {}", src))
+        }
+
+        Ok(TimeFormat(src.to_owned()))
+    }
+}
+
+/// A time zone given to --time-zone: `local`, `UTC` or a name from the tz
+/// database such as `Europe/Lisbon`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub (crate) enum Zone {
+    Local,
+    Named(Tz),
+}
+
+impl FromStr for Zone {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "local" => Ok(Zone::Local),
+            "utc" | "UTC" => Ok(Zone::Named(Tz::UTC)),
+            name => name.parse().map(Zone::Named).map_err(|_| anyhow!("Unknown time zone: {}", name)),
+        }
+    }
+}
+
+/// How timestamps are written, given to --time-display
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub (crate) enum TimeDisplay {
+    Absolute,
+    /// Time since the timestamp, such as `3s ago`
+    Relative,
+}
+
+impl FromStr for TimeDisplay {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        match src {
+            "absolute" => Ok(TimeDisplay::Absolute),
+            "relative" => Ok(TimeDisplay::Relative),
+            _ => Err(anyhow!("Unknown time display: {}, expected absolute or relative", src)),
+        }
+    }
+}
+
+/// Writes `elapsed` as `3s ago`, `12m ago`, `5h ago` or `2d ago`, or as
+/// `in 3s` for times in the future
+fn ago(elapsed: TimeDelta) -> String {
+    let secs = elapsed.num_seconds().abs();
+
+    let amount = match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m", s / 60),
+        s if s < 86400 => format!("{}h", s / 3600),
+        s => format!("{}d", s / 86400),
+    };
+
+    if elapsed < TimeDelta::zero() {
+        format!("in {}", amount)
+    } else {
+        format!("{} ago", amount)
+    }
+}
+
+/// Writes the time between two lines, such as `+12ms`, `+3.4s`, `+2m5s` or
+/// `+1h20m`
+pub (crate) fn delta(elapsed: TimeDelta) -> String {
+    let sign = if elapsed < TimeDelta::zero() { '-' } else { '+' };
+    format!("{}{}", sign, span(elapsed.abs()))
+}
+
+/// A short, unsigned length of time such as `12ms`, `3.4s`, `2m5s` or `1h20m`
+pub (crate) fn span(elapsed: TimeDelta) -> String {
+    let ms = elapsed.num_milliseconds();
+
+    match elapsed.num_seconds() {
+        _ if ms < 1000 => format!("{}ms", ms),
+        s if s < 60 && ms % 1000 == 0 => format!("{}s", s),
+        s if s < 60 => format!("{:.1}s", ms as f64 / 1000.0),
+        s if s < 3600 && s % 60 == 0 => format!("{}m", s / 60),
+        s if s < 3600 => format!("{}m{}s", s / 60, s % 60),
+        s if s % 3600 < 60 => format!("{}h", s / 3600),
+        s => format!("{}h{}m", s / 3600, s % 3600 / 60),
+    }
+}
+
+fn render<Z: TimeZone>(time: DateTime<Z>, format: Option<&TimeFormat>) -> String where Z::Offset: Display {
+    match format {
+        Some(format) => time.format(&format.0).to_string(),
+        None => time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+    }
+}
+
+/// `time` converted to --time-zone, if any
+pub (crate) fn in_zone(time: DateTime<FixedOffset>, zone: Option<Zone>) -> DateTime<FixedOffset> {
+    match zone {
+        Some(Zone::Local) => time.with_timezone(&Local).fixed_offset(),
+        Some(Zone::Named(tz)) => time.with_timezone(&tz).fixed_offset(),
+        None => time,
+    }
+}
+
+/// The time of `line`, from the first of the --time keys it has
+pub (crate) fn of_line(line: &Value, opts: &Opt) -> Option<DateTime<FixedOffset>> {
+    opts.time.iter().find_map(|key| line.get(key)).and_then(parse_value)
+}
+
+/// The time of a string or number value, see `parse`
+pub (crate) fn parse_value(value: &Value) -> Option<DateTime<FixedOffset>> {
+    match value {
+        Value::String(s) => parse(s),
+        Value::Number(n) => n.as_i64().map_or_else(|| from_epoch(n.as_f64()?), from_integer_epoch),
+        _ => None,
+    }
+}
+
+/// How the timestamp `value` of a line is written, converted to
+/// --time-zone and formatted with --time-format. Unix timestamps are always
+/// written as dates, other timestamps that are not converted or can't be
+/// parsed are written as they are. Values that are not strings or numbers
+/// are not timestamps.
+pub (crate) fn display(value: &Value, opts: &Opt) -> Option<String> {
+    let text = match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        _ => return None,
+    };
+
+    let is_epoch = matches!(value, Value::Number(_)) || is_epoch(text.trim());
+    let relative = opts.time_display == TimeDisplay::Relative;
+    if opts.time_format.is_none() && opts.time_zone.is_none() && ! is_epoch && ! relative {
+        return Some(text)
+    }
+
+    let Some(time) = parse_value(value) else { return Some(text) };
+
+    if relative {
+        return Some(ago(Utc::now().signed_duration_since(time)))
+    }
+    let format = opts.time_format.as_ref();
+
+    Some(match opts.time_zone {
+        Some(Zone::Local) => render(time.with_timezone(&Local), format),
+        Some(Zone::Named(tz)) => render(time.with_timezone(&tz), format),
+        None if is_epoch => render(time.with_timezone(&Utc), format),
+        None => render(time, format),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use structopt::StructOpt;
+    use super::*;
+
+    fn utc(text: &str) -> Option<DateTime<Utc>> {
+        parse(text).map(|t| t.with_timezone(&Utc))
+    }
+
+    #[test]
+    fn parse_test() {
+        let expected = Utc.with_ymd_and_hms(2022, 3, 24, 8, 56, 20).unwrap();
+
+        assert_eq!(utc("2022-03-24T08:56:20Z"), Some(expected));
+        assert_eq!(utc("2022-03-24T09:56:20+01:00"), Some(expected));
+        assert_eq!(utc("2022-03-24 08:56:20"), Some(expected));
+        assert_eq!(utc("2022-03-24T08:56:20"), Some(expected));
+        assert_eq!(utc("24/Mar/2022:08:56:20 +0000"), Some(expected));
+        assert_eq!(utc("Thu, 24 Mar 2022 08:56:20 +0000"), Some(expected));
+        assert_eq!(utc("2022-03-24T08:56:20.576Z").map(|t| t.timestamp_subsec_millis()), Some(576));
+        assert_eq!(utc("2022-03-24").map(|t| t.to_rfc3339()), Some("2022-03-24T00:00:00+00:00".to_owned()));
+        assert_eq!(utc("yesterday"), None);
+    }
+
+    #[test]
+    fn parse_epoch_test() {
+        let expected = Utc.with_ymd_and_hms(2022, 3, 24, 8, 56, 20).unwrap();
+
+        assert_eq!(utc("1648112180"), Some(expected));
+        assert_eq!(utc("1648112180000"), Some(expected));
+        assert_eq!(utc("1648112180000000"), Some(expected));
+        assert_eq!(utc("1648112180000000000"), Some(expected));
+        assert_eq!(utc("1648112180123456789").map(|t| t.timestamp_subsec_nanos()), Some(123_456_789));
+        assert_eq!(parse_value(&json!(1648112180.5)).map(|t| t.timestamp_subsec_millis()), Some(500));
+        assert_eq!(parse_value(&json!(1714572000.123)).map(|t| t.timestamp_subsec_nanos()), Some(123_000_000));
+        assert_eq!(utc("1648112180.5").map(|t| t.timestamp_subsec_millis()), Some(500));
+    }
+
+    #[test]
+    fn parse_compact_test() {
+        // Not epochs, despite being numbers
+        assert_eq!(utc("20240501").map(|t| t.to_rfc3339()), Some("2024-05-01T00:00:00+00:00".to_owned()));
+        assert_eq!(utc("20240501T140000").map(|t| t.to_rfc3339()), Some("2024-05-01T14:00:00+00:00".to_owned()));
+        assert_eq!(utc("12345"), None);
+
+        let opts = Opt::from_iter(["jaxe"]);
+        assert_eq!(display(&json!("20240501"), &opts), Some("20240501".to_owned()));
+    }
+
+    #[test]
+    fn ago_test() {
+        assert_eq!(ago(TimeDelta::seconds(3)), "3s ago");
+        assert_eq!(ago(TimeDelta::seconds(12 * 60 + 5)), "12m ago");
+        assert_eq!(ago(TimeDelta::hours(5)), "5h ago");
+        assert_eq!(ago(TimeDelta::days(2)), "2d ago");
+        assert_eq!(ago(TimeDelta::seconds(-3)), "in 3s");
+    }
+
+    #[test]
+    fn delta_test() {
+        assert_eq!(delta(TimeDelta::milliseconds(12)), "+12ms");
+        assert_eq!(delta(TimeDelta::milliseconds(3420)), "+3.4s");
+        assert_eq!(delta(TimeDelta::seconds(125)), "+2m5s");
+        assert_eq!(delta(TimeDelta::seconds(4800)), "+1h20m");
+        assert_eq!(delta(TimeDelta::milliseconds(-5)), "-5ms");
+        assert_eq!(span(TimeDelta::seconds(125)), "2m5s");
+        assert_eq!(span(TimeDelta::seconds(60)), "1m");
+        assert_eq!(span(TimeDelta::seconds(2)), "2s");
+        assert_eq!(span(TimeDelta::seconds(7230)), "2h");
+    }
+
+    #[test]
+    fn in_window_test() {
+        let opts = Opt::from_iter(["jaxe", "-t", "time", "--since", "2024-05-01 14:00", "--until", "2024-05-01 15:00"]);
+
+        assert!(in_window(&json!({"time": "2024-05-01T14:00:00Z"}), &opts));
+        assert!(in_window(&json!({"time": "2024-05-01T14:59:59Z"}), &opts));
+        assert!(! in_window(&json!({"time": "2024-05-01T15:00:00Z"}), &opts));
+        assert!(! in_window(&json!({"time": "2024-05-01T13:00:00Z"}), &opts));
+        assert!(in_window(&json!({"msg": "no time"}), &opts));
+
+        let opts = Opt::from_iter(["jaxe", "-t", "time", "--since", "2h"]);
+        let recent = (Utc::now() - TimeDelta::hours(1)).to_rfc3339();
+        let old = (Utc::now() - TimeDelta::hours(3)).to_rfc3339();
+        assert!(in_window(&json!({ "time": recent }), &opts));
+        assert!(! in_window(&json!({ "time": old }), &opts));
+
+        assert!("9999999999d".parse::<TimeBound>().is_err());
+    }
+
+    #[test]
+    fn time_format_test() {
+        assert!("%H:%M:%S%.3f".parse::<TimeFormat>().is_ok());
+        assert!("%Q".parse::<TimeFormat>().is_err());
+    }
+
+    #[test]
+    fn zone_test() {
+        assert_eq!("UTC".parse::<Zone>().unwrap(), Zone::Named(Tz::UTC));
+        assert_eq!("local".parse::<Zone>().unwrap(), Zone::Local);
+        assert_eq!("Europe/Lisbon".parse::<Zone>().unwrap(), Zone::Named(Tz::Europe__Lisbon));
+        assert!("Mars/Olympus".parse::<Zone>().is_err());
+    }
+
+    #[test]
+    fn display_test() {
+        let display = |value: Value, opts: &Opt| super::display(&value, opts).unwrap();
+
+        let opts = Opt::from_iter(["jaxe", "--time-zone", "Europe/Lisbon"]);
+        assert_eq!(display(json!("2022-07-01T08:00:00Z"), &opts), "2022-07-01T09:00:00+01:00");
+
+        let opts = Opt::from_iter(["jaxe", "--time-zone", "Asia/Tokyo", "--time-format", "%H:%M %Z"]);
+        assert_eq!(display(json!("2022-07-01T08:00:00Z"), &opts), "17:00 JST");
+
+        let opts = Opt::from_iter(["jaxe"]);
+        assert_eq!(display(json!("2022-07-01T08:00:00Z"), &opts), "2022-07-01T08:00:00Z");
+        assert_eq!(display(json!("not a time"), &opts), "not a time");
+        assert_eq!(display(json!(1656662400), &opts), "2022-07-01T08:00:00Z");
+        assert_eq!(display(json!("1656662400500"), &opts), "2022-07-01T08:00:00.500Z");
+        assert_eq!(super::display(&json!(null), &opts), None);
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use serde_json::Value;
+
+use crate::Opt;
+use crate::timestamp;
+
+const TRACE_KEYS: &[&str] = &["traceId", "trace_id"];
+const SPAN_KEYS: &[&str] = &["spanId", "span_id"];
+const PARENT_KEYS: &[&str] = &["parentSpanId", "parent_span_id", "parentId"];
+const NAME_KEYS: &[&str] = &["name", "spanName"];
+
+/// Indentation of each level of a trace tree
+const INDENT: usize = 2;
+
+/// Collects the spans of traces, to write each trace as a tree once its root
+/// span is read, see --trace-tree. Root spans end last, so they are usually
+/// written last too.
+#[derive(Debug, Default)]
+pub (crate) struct TraceTrees {
+    /// Spans of the traces not written yet, in the order of their first span
+    traces: Vec<Trace>,
+}
+
+#[derive(Debug)]
+pub (crate) struct Trace {
+    pub (crate) id: String,
+    spans: Vec<Span>,
+}
+
+#[derive(Debug)]
+struct Span {
+    id: String,
+    parent: Option<String>,
+    name: String,
+    /// Nanoseconds since the epoch
+    start: Option<i128>,
+    end: Option<i128>,
+}
+
+/// The first of `keys` with a non-empty string value
+fn text(line: &Value, keys: &[&str]) -> Option<String> {
+    keys.iter()
+        .find_map(|key| line.get(key)?.as_str())
+        .filter(|text| ! text.is_empty() && text.chars().any(|c| c != '0'))
+        .map(str::to_owned)
+}
+
+/// Nanoseconds since the epoch, given as a number or a string as in OTLP
+fn nanos(line: &Value, key: &str) -> Option<i128> {
+    match line.get(key)? {
+        Value::Number(n) => n.as_u64().map(i128::from),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+impl TraceTrees {
+    /// Whether `line` is a span, with a trace and a span id
+    pub (crate) fn is_span(line: &Value) -> bool {
+        text(line, TRACE_KEYS).is_some() && text(line, SPAN_KEYS).is_some()
+    }
+
+    /// Adds the span `line`, returning its trace when it is the root span
+    pub (crate) fn add(&mut self, line: &Value, opts: &Opt) -> Option<Trace> {
+        let trace_id = text(line, TRACE_KEYS)?;
+
+        let start = nanos(line, "startTimeUnixNano")
+            .or_else(|| timestamp::of_line(line, opts)?.timestamp_nanos_opt().map(i128::from));
+        let span = Span {
+            id: text(line, SPAN_KEYS)?,
+            parent: text(line, PARENT_KEYS),
+            name: text(line, NAME_KEYS)
+                .or_else(|| opts.message.iter().find_map(|key| line.get(key)?.as_str().map(str::to_owned)))
+                .unwrap_or_else(|| "-".to_owned()),
+            start,
+            end: nanos(line, "endTimeUnixNano"),
+        };
+        let root = span.parent.is_none();
+
+        let index = match self.traces.iter().position(|trace| trace.id == trace_id) {
+            Some(index) => index,
+            None => {
+                self.traces.push(Trace { id: trace_id, spans: vec![] });
+                self.traces.len() - 1
+            },
+        };
+        self.traces[index].spans.push(span);
+
+        root.then(|| self.traces.remove(index))
+    }
+
+    /// The traces whose root span was not read, at the end of the input
+    pub (crate) fn flush(&mut self) -> Vec<Trace> {
+        std::mem::take(&mut self.traces)
+    }
+}
+
+impl Trace {
+    /// Lines of the tree, each span with its start since the start of the
+    /// trace, its name indented under its parent and its duration. Spans
+    /// whose parent is not in the trace are roots.
+    pub (crate) fn render(&self) -> Vec<String> {
+        let ids: Vec<&str> = self.spans.iter().map(|span| span.id.as_str()).collect();
+        let mut children: HashMap<Option<&str>, Vec<&Span>> = HashMap::new();
+
+        for span in &self.spans {
+            let parent = span.parent.as_deref().filter(|parent| ids.contains(parent));
+            children.entry(parent).or_default().push(span);
+        }
+        for spans in children.values_mut() {
+            spans.sort_by_key(|span| span.start);
+        }
+
+        let first = self.spans.iter().filter_map(|span| span.start).min();
+        let mut lines = vec![];
+        let mut stack: Vec<(&Span, usize)> = children.get(&None).into_iter().flatten().rev().map(|span| (*span, 0)).collect();
+
+        while let Some((span, depth)) = stack.pop() {
+            let offset = match (span.start, first) {
+                (Some(start), Some(first)) => format!("+{}", timestamp::span(chrono::TimeDelta::nanoseconds((start - first) as i64))),
+                _ => String::new(),
+            };
+            let duration = match (span.start, span.end) {
+                (Some(start), Some(end)) => format!(" {}", timestamp::span(chrono::TimeDelta::nanoseconds((end - start) as i64))),
+                _ => String::new(),
+            };
+
+            lines.push(format!("{:>8} {:indent$}{}{}", offset, "", span.name, duration, indent = depth * INDENT));
+
+            let nested = children.get(&Some(span.id.as_str())).into_iter().flatten().rev();
+            stack.extend(nested.map(|child| (*child, depth + 1)));
+        }
+
+        lines
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use structopt::StructOpt;
+    use super::*;
+
+    #[test]
+    fn trace_tree_test() {
+        let opts = Opt::from_iter(["jaxe"]);
+        let mut trees = TraceTrees::default();
+
+        let span = |id: &str, parent: &str, name: &str, start: u64, end: u64| json!({
+            "traceId": "t1", "spanId": id, "parentSpanId": parent, "name": name,
+            "startTimeUnixNano": (1_700_000_000_000_000_000u64 + start * 1_000_000).to_string(),
+            "endTimeUnixNano": (1_700_000_000_000_000_000u64 + end * 1_000_000).to_string(),
+        });
+
+        assert!(! TraceTrees::is_span(&json!({"traceId": "t1", "spanId": "0000000000000000"})));
+
+        for line in [span("c", "b", "pool.acquire", 12, 14), span("b", "a", "db.query", 10, 90), span("d", "a", "auth", 2, 7)] {
+            assert!(TraceTrees::is_span(&line));
+            assert!(trees.add(&line, &opts).is_none());
+        }
+
+        let trace = trees.add(&span("a", "", "GET /users", 0, 120), &opts).unwrap();
+        assert_eq!(trace.id, "t1");
+        assert_eq!(trace.render(), vec![
+            "    +0ms GET /users 120ms",
+            "    +2ms   auth 5ms",
+            "   +10ms   db.query 80ms",
+            "   +12ms     pool.acquire 2ms",
+        ]);
+        assert!(trees.flush().is_empty());
+    }
+}
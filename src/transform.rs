@@ -0,0 +1,442 @@
+use std::str::FromStr;
+use serde_json::{Map, Value};
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use base64::{engine::general_purpose, Engine};
+use percent_encoding::percent_decode_str;
+
+use crate::cli::{KeyPattern, KeyValue, MultOpt};
+use crate::parser::{self, EPath};
+
+/// Replaces nested objects with dotted keys, so `{"http": {"method": "GET"}}`
+/// becomes `{"http.method": "GET"}`. Arrays and empty objects are kept as is.
+/// A dotted key that is already taken, as in `{"a.b": 1, "a": {"b": 2}}`,
+/// is numbered rather than overwriting the first value: `a.b#2`.
+pub (crate) fn flatten(json: Map<String, Value>) -> Map<String, Value> {
+    let mut flat = Map::new();
+
+    for (key, value) in json {
+        flatten_into(&mut flat, key, value);
+    }
+
+    flat
+}
+
+fn flatten_into(flat: &mut Map<String, Value>, prefix: String, value: Value) {
+    match value {
+        Value::Object(obj) if ! obj.is_empty() =>
+            for (key, value) in obj {
+                flatten_into(flat, format!("{}.{}", prefix, key), value);
+            },
+        value if flat.contains_key(&prefix) => {
+            let key = (2..).map(|n| format!("{}#{}", prefix, n)).find(|key| ! flat.contains_key(key)).unwrap();
+            flat.insert(key, value);
+        },
+        value => {
+            flat.insert(prefix, value);
+        }
+    }
+}
+
+/// Whether `value` is null, or an empty string, array or object
+pub (crate) fn is_empty(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::String(s) => s.is_empty(),
+        Value::Array(a) => a.is_empty(),
+        Value::Object(o) => o.is_empty(),
+        _ => false,
+    }
+}
+
+/// Removes the value at `path`, when its parent is an object
+pub (crate) fn remove_path(json: &mut Map<String, Value>, path: &EPath) {
+    let (last, parents) = match path.segments().split_last() {
+        Some(split) => split,
+        None => return,
+    };
+
+    let mut current = json;
+
+    for segment in parents {
+        match current.get_mut(segment) {
+            Some(Value::Object(obj)) => current = obj,
+            _ => return,
+        }
+    }
+
+    current.shift_remove(last);
+}
+
+fn insert_at(json: &mut Map<String, Value>, segments: &[String], value: Value) {
+    match segments {
+        [last] => {
+            json.insert(last.clone(), value);
+        },
+        [first, rest @ ..] => {
+            if let Value::Object(obj) = json.entry(first.clone()).or_insert_with(|| Value::Object(Map::new())) {
+                insert_at(obj, rest, value);
+            }
+        },
+        [] => {}
+    }
+}
+
+/// Replaces the values under the first segment of each path with only the
+/// sub values addressed by the paths, so `user.id` keeps `{"user": {"id": 1}}`
+pub (crate) fn project_paths(json: &mut Map<String, Value>, paths: &[EPath]) {
+    let mut projected: Map<String, Value> = Map::new();
+
+    for path in paths {
+        if let Some(value) = path.lookup_in(json) {
+            insert_at(&mut projected, path.segments(), value.clone());
+        }
+    }
+
+    for path in paths {
+        let first = &path.segments()[0];
+        match projected.get(first) {
+            Some(value) => json.insert(first.clone(), value.clone()),
+            None => json.shift_remove(first),
+        };
+    }
+}
+
+/// Removes the parents of `path` that were left as empty objects
+fn prune_empty_parents(json: &mut Map<String, Value>, path: &EPath) {
+    for len in (1..path.segments().len()).rev() {
+        let parent = EPath::new(path.segments()[..len].to_vec());
+
+        if parent.lookup_in(json).map(is_empty) == Some(true) {
+            remove_path(json, &parent);
+        }
+    }
+}
+
+/// Renames keys, or moves the value at a nested path to a new top level key
+pub (crate) fn rename(json: &mut Map<String, Value>, renames: &[KeyValue]) {
+    for KeyValue(from, to) in renames {
+        if let Some(value) = json.shift_remove(from) {
+            json.insert(to.clone(), value);
+        } else if let Ok(path) = parser::parse_path(from) {
+            if let Some(value) = path.lookup_in(json).cloned() {
+                remove_path(json, &path);
+                prune_empty_parents(json, &path);
+                json.insert(to.clone(), value);
+            }
+        }
+    }
+}
+
+fn value_at_mut<'a>(json: &'a mut Map<String, Value>, path: &EPath) -> Option<&'a mut Value> {
+    let (first, rest) = path.segments().split_first()?;
+    json.get_mut(first)?.pointer_mut(&EPath::new(rest.to_vec()).pointer())
+}
+
+/// Applies `f` to the values of keys matching `patterns`, or at the nested
+/// paths in `patterns`
+fn update_matching(json: &mut Map<String, Value>, patterns: &MultOpt<KeyPattern>, f: impl Fn(&mut Value)) {
+    for (key, value) in json.iter_mut() {
+        if patterns.matches(key) {
+            f(value);
+        }
+    }
+
+    for path in patterns.nested_paths(json) {
+        if let Some(value) = value_at_mut(json, &path) {
+            f(value);
+        }
+    }
+}
+
+/// Replaces the values of keys or paths given to --redact with `mask`
+pub (crate) fn redact(json: &mut Map<String, Value>, patterns: &MultOpt<KeyPattern>, mask: &str) {
+    update_matching(json, patterns, |value| *value = Value::String(mask.to_owned()));
+}
+
+/// Replaces the values of keys or paths given to --hash with a short hash of
+/// `salt` and the value, so equal values still look equal
+pub (crate) fn hash(json: &mut Map<String, Value>, patterns: &MultOpt<KeyPattern>, salt: &str) {
+    update_matching(json, patterns, |value| *value = Value::String(hash_value(value, salt)));
+}
+
+fn hash_value(value: &Value, salt: &str) -> String {
+    let text = match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+
+    let digest = Sha256::new()
+        .chain_update(salt)
+        .chain_update(text)
+        .finalize();
+
+    digest.iter().take(6).map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A `name = expression` field computed from other fields with --derive
+#[derive(Debug)]
+pub (crate) struct Derivation {
+    name: String,
+    exp: parser::Arith,
+}
+
+impl FromStr for Derivation {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let (name, exp) = src.split_once('=').ok_or_else(|| anyhow!("Expected name = expression, got: {}", src))?;
+
+        Ok(Derivation { name: name.trim().to_owned(), exp: parser::parse_arith(exp)? })
+    }
+}
+
+/// Adds the fields derived with --derive to `line`. Fields are derived in
+/// order, so later expressions can use fields derived before them.
+pub (crate) fn derive(line: &mut Value, derivations: &[Derivation]) {
+    for d in derivations {
+        if let Some(value) = parser::eval_arith(&d.exp, line) {
+            if let Value::Object(obj) = line {
+                obj.insert(d.name.clone(), value);
+            }
+        }
+    }
+}
+
+/// Replaces string values holding a serialized JSON object or array with the
+/// parsed value, for keys or paths given to --parse-nested
+pub (crate) fn parse_nested(json: &mut Map<String, Value>, patterns: &MultOpt<KeyPattern>) {
+    update_matching(json, patterns, |value| {
+        if let Value::String(s) = value {
+            if s.trim_start().starts_with(['{', '[']) {
+                if let Ok(parsed) = serde_json::from_str(s) {
+                    *value = parsed;
+                }
+            }
+        }
+    });
+}
+
+/// A `path=encoding` pair given to --decode
+#[derive(Debug)]
+pub (crate) struct Decoding {
+    path: EPath,
+    encoding: Encoding,
+}
+
+#[derive(Debug, PartialEq)]
+enum Encoding {
+    Base64,
+    Url,
+}
+
+impl FromStr for Decoding {
+    type Err = anyhow::Error;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let (path, encoding) = src.split_once('=').ok_or_else(|| anyhow!("Expected path=encoding, got: {}", src))?;
+
+        let encoding = match encoding {
+            "base64" => Encoding::Base64,
+            "url" => Encoding::Url,
+            other => return Err(anyhow!("Unknown encoding: {}, expected base64 or url", other)),
+        };
+
+        Ok(Decoding { path: parser::parse_path(path)?, encoding })
+    }
+}
+
+fn decode_str(encoded: &str, encoding: &Encoding) -> Option<String> {
+    match encoding {
+        Encoding::Base64 => {
+            let engines = [&general_purpose::STANDARD, &general_purpose::URL_SAFE, &general_purpose::STANDARD_NO_PAD, &general_purpose::URL_SAFE_NO_PAD];
+            let bytes = engines.iter().find_map(|e| e.decode(encoded).ok())?;
+            String::from_utf8(bytes).ok()
+        }
+        Encoding::Url =>
+            percent_decode_str(&encoded.replace('+', " ")).decode_utf8().ok().map(|s| s.into_owned()),
+    }
+}
+
+/// Decodes the string fields given to --decode in place. Values that are not
+/// valid in their encoding, or do not decode to text, are left as they are.
+pub (crate) fn decode(line: &mut Value, decodings: &[Decoding]) {
+    for d in decodings {
+        if let Some(Value::String(encoded)) = line.pointer_mut(&d.path.pointer()) {
+            if let Some(decoded) = decode_str(encoded, &d.encoding) {
+                *encoded = decoded;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use super::*;
+
+    fn object(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn flatten_test() {
+        let json = object(json!({
+            "http": {"method": "GET", "status": 200, "headers": {"host": "a"}},
+            "tags": ["a", "b"],
+            "empty": {},
+            "msg": "hi"
+        }));
+
+        let expected = object(json!({
+            "http.method": "GET",
+            "http.status": 200,
+            "http.headers.host": "a",
+            "tags": ["a", "b"],
+            "empty": {},
+            "msg": "hi"
+        }));
+
+        assert_eq!(flatten(json), expected);
+
+        let json = object(json!({"a.b": 1, "a": {"b": 2, "c": 3}, "x": {"y": {"z": 4}, "y.z": 5}}));
+        let expected = object(json!({"a.b": 1, "a.b#2": 2, "a.c": 3, "x.y.z": 4, "x.y.z#2": 5}));
+
+        assert_eq!(flatten(json), expected);
+    }
+
+    #[test]
+    fn is_empty_test() {
+        assert!(is_empty(&json!(null)));
+        assert!(is_empty(&json!("")));
+        assert!(is_empty(&json!([])));
+        assert!(is_empty(&json!({})));
+        assert!(! is_empty(&json!(0)));
+        assert!(! is_empty(&json!(false)));
+        assert!(! is_empty(&json!(" ")));
+        assert!(! is_empty(&json!([null])));
+    }
+
+    #[test]
+    fn remove_path_test() {
+        let mut json = object(json!({"ctx": {"internal": {"a": 1}, "user": "u"}, "msg": "hi"}));
+
+        remove_path(&mut json, &parser::parse_path("ctx.internal").unwrap());
+        remove_path(&mut json, &parser::parse_path("msg.nope").unwrap());
+
+        assert_eq!(json, object(json!({"ctx": {"user": "u"}, "msg": "hi"})));
+    }
+
+    #[test]
+    fn project_paths_test() {
+        let mut json = object(json!({
+            "user": {"id": 1, "email": "a@b"},
+            "http": {"status": 200, "method": "GET", "headers": {"host": "h", "ua": "x"}},
+            "msg": "hi"
+        }));
+
+        let paths: Vec<EPath> = ["user.id", "http.status", "http.headers.host", "missing.key"].iter()
+            .map(|p| parser::parse_path(p).unwrap())
+            .collect();
+        project_paths(&mut json, &paths);
+
+        assert_eq!(json, object(json!({
+            "user": {"id": 1},
+            "http": {"status": 200, "headers": {"host": "h"}},
+            "msg": "hi"
+        })));
+    }
+
+    #[test]
+    fn rename_test() {
+        let mut json = object(json!({"kubernetes": {"pod_name": "p1", "ns": "n"}, "msg": "hi"}));
+        let renames = vec![
+            KeyValue("kubernetes.pod_name".into(), "pod".into()),
+            KeyValue("msg".into(), "message".into()),
+            KeyValue("missing".into(), "nope".into()),
+        ];
+
+        rename(&mut json, &renames);
+
+        assert_eq!(json, object(json!({"kubernetes": {"ns": "n"}, "pod": "p1", "message": "hi"})));
+
+        let mut json = object(json!({"a": {"b": {"c": 1}}, "msg": "hi"}));
+        rename(&mut json, &[KeyValue("a.b.c".into(), "c".into())]);
+        assert_eq!(json, object(json!({"msg": "hi", "c": 1})));
+    }
+
+    #[test]
+    fn derive_test() {
+        let mut line = json!({"duration_ms": 250});
+        let derivations: Vec<Derivation> = vec!["latency_s = duration_ms / 1000".parse().unwrap(), "slow = latency_s * 4".parse().unwrap()];
+
+        derive(&mut line, &derivations);
+
+        assert_eq!(line, json!({"duration_ms": 250, "latency_s": 0.25, "slow": 1}));
+    }
+
+    #[test]
+    fn redact_test() {
+        let mut json = object(json!({"password": "hunter2", "user": {"token": "t", "name": "n"}, "auth_header": "x"}));
+        let patterns: MultOpt<KeyPattern> = "password,user.token,auth_*".parse().unwrap();
+
+        redact(&mut json, &patterns, "***");
+
+        assert_eq!(json, object(json!({"password": "***", "user": {"token": "***", "name": "n"}, "auth_header": "***"})));
+    }
+
+    #[test]
+    fn hash_test() {
+        let mut json = object(json!({"email": "a@b.c", "other": "a@b.c", "id": 1}));
+        let patterns: MultOpt<KeyPattern> = "email,other,id".parse().unwrap();
+
+        hash(&mut json, &patterns, "");
+
+        assert_eq!(json["email"], json["other"]);
+        assert_eq!(json["email"].as_str().unwrap().len(), 12);
+        assert_ne!(json["email"], json!("a@b.c"));
+        assert_ne!(json["id"], json["email"]);
+
+        let mut salted = object(json!({"email": "a@b.c"}));
+        hash(&mut salted, &patterns, "salt");
+        assert_ne!(salted["email"], json["email"]);
+    }
+
+    #[test]
+    fn decode_test() {
+        let mut line = json!({"payload": "aGVsbG8=", "req": {"query": "a%3D1+b%26c"}, "bad": "not base64!"});
+        let decodings: Vec<Decoding> = ["payload=base64", "req.query=url", "bad=base64"]
+            .iter().map(|d| d.parse().unwrap()).collect();
+
+        decode(&mut line, &decodings);
+
+        assert_eq!(line, json!({"payload": "hello", "req": {"query": "a=1 b&c"}, "bad": "not base64!"}));
+    }
+
+    #[test]
+    fn decoding_parse_test() {
+        assert!("payload=base64".parse::<Decoding>().is_ok());
+        assert!("payload=rot13".parse::<Decoding>().is_err());
+        assert!("payload".parse::<Decoding>().is_err());
+    }
+
+    #[test]
+    fn parse_nested_test() {
+        let mut json = object(json!({
+            "msg": "{\"user\": \"u\", \"ids\": [1, 2]}",
+            "req": {"body": "[1, 2]"},
+            "num": "123",
+            "broken": "{not json"
+        }));
+        let patterns: MultOpt<KeyPattern> = "msg,req.body,num,broken".parse().unwrap();
+
+        parse_nested(&mut json, &patterns);
+
+        assert_eq!(json, object(json!({
+            "msg": {"user": "u", "ids": [1, 2]},
+            "req": {"body": [1, 2]},
+            "num": "123",
+            "broken": "{not json"
+        })));
+    }
+}